@@ -1,9 +1,20 @@
-use std::{collections::HashMap, os::fd::FromRawFd, sync::Mutex, time::Duration};
+use std::{
+    collections::HashMap,
+    os::fd::FromRawFd,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use super::{utils::CustomRenderElements, DrmSurfaceDmabufFeedback, UdevData};
+use super::{
+    frame_clock::FrameClock, screencopy::render_screencopy, utils::CustomRenderElements,
+    DrmSurfaceDmabufFeedback, UdevData,
+};
 
 use crate::{
     backend::get_surface_dmabuf_feedback,
+    config::{OutputConfig, OutputMode, OutputPosition, OutputVrr},
+    drawing::{border_elements, CLEAR_COLOR_LOCKED, URGENT_BORDER_COLOR, URGENT_BORDER_WIDTH},
+    protocols::screencopy::Screencopy,
     state::{post_repaint, take_presentation_feedback, SurfaceDmabufFeedback},
     CalloopData, Corrosion,
 };
@@ -22,8 +33,9 @@ use smithay::{
         renderer::{
             damage::{Error as OutputDamageTrackerError, OutputDamageTracker},
             element::{
-                surface::WaylandSurfaceRenderElement, texture::TextureBuffer, AsRenderElements,
-                RenderElement, RenderElementStates,
+                surface::{render_elements_from_surface_tree, WaylandSurfaceRenderElement},
+                texture::TextureBuffer,
+                AsRenderElements, RenderElement, RenderElementStates,
             },
             gles::{GlesRenderer, GlesTexture},
             multigpu::{gbm::GbmGlesBackend, MultiRenderer},
@@ -32,21 +44,29 @@ use smithay::{
         session::Session,
         SwapBuffersError,
     },
-    desktop::{space, utils::OutputPresentationFeedback},
+    desktop::{space, utils::OutputPresentationFeedback, Space, Window},
     input::pointer::{CursorImageAttributes, CursorImageStatus},
     output::{Mode as WlMode, Output, PhysicalProperties},
     reexports::{
-        calloop::{timer::Timer, RegistrationToken},
+        calloop::{
+            timer::{TimeoutAction, Timer},
+            RegistrationToken,
+        },
         drm::{
             self,
-            control::{connector, crtc::Handle as CrtcHandle, ModeTypeFlags},
+            control::{
+                atomic::AtomicModeReq, connector, crtc::Handle as CrtcHandle, framebuffer, plane,
+                property, AtomicCommitFlags, Device as ControlDevice, Mode as DrmMode,
+                ModeTypeFlags, ResourceHandle,
+            },
             Device,
         },
-        nix::fcntl::OFlag,
+        gbm::BufferObject,
+        nix::{errno::Errno, fcntl::OFlag},
         wayland_protocols::wp::presentation_time::server::wp_presentation_feedback,
         wayland_server::{backend::GlobalId, protocol::wl_output::WlOutput, DisplayHandle},
     },
-    utils::{DeviceFd, IsAlive, Scale, Transform},
+    utils::{DeviceFd, IsAlive, Logical, Physical, Point, Rectangle, Scale, Transform},
     wayland::compositor,
 };
 use smithay_drm_extras::{
@@ -70,6 +90,28 @@ type HardwareCompositor = DrmCompositor<
     DrmDeviceFd,
 >;
 
+#[derive(thiserror::Error, Debug)]
+pub enum OutputError {
+    #[error("No drm device {0} found")]
+    UnknownDevice(DrmNode),
+    #[error("Failure to create drm surface: {0}")]
+    Surface(String),
+    #[error("Unable to get device driver: {0}")]
+    Driver(String),
+    #[error("Unable to get surface planes: {0}")]
+    Planes(String),
+    #[error("Error creating compositor: {0}")]
+    Compositor(String),
+    #[error("{0} is not a supported mode")]
+    UnsupportedMode(String),
+    #[error("Crtc {0:?} has no enabled output")]
+    NotEnabled(CrtcHandle),
+    #[error("Crtc {0:?} has no disabled output")]
+    NotDisabled(CrtcHandle),
+    #[error("The driver rejected the configuration: {0}")]
+    Rejected(String),
+}
+
 #[derive(PartialEq)]
 pub struct UdevOutputId {
     pub crtc: CrtcHandle,
@@ -94,6 +136,27 @@ pub struct SurfaceData {
     pub render_node: DrmNode,
     pub device_node: DrmNode,
     pub dmabuf_feedback: Option<DrmSurfaceDmabufFeedback>,
+    pub render_timer: Option<RegistrationToken>,
+    pub frame_clock: FrameClock,
+    // damage arrived since the last repaint
+    pub needs_render: bool,
+    // a frame is queued and waiting for its vblank
+    pub frame_pending: bool,
+    pub vrr: OutputVrr,
+    pub vrr_capable: bool,
+    // what VRR_ENABLED was last set to, None until it is set the first time
+    pub vrr_enabled: Option<bool>,
+    // screencopy frames waiting for the next repaint
+    pub pending_screencopies: Vec<Screencopy>,
+}
+
+// The crtc of an output turned off by dpms. The output stays mapped and its global stays
+// around, it just doesn't get rendered anymore
+pub struct PoweredOffOutput {
+    pub dh: DisplayHandle,
+    pub mode: DrmMode,
+    pub id: Option<GlobalId>,
+    pub vrr: OutputVrr,
 }
 
 pub struct BackendData {
@@ -101,6 +164,8 @@ pub struct BackendData {
     pub scanner: DrmScanner,
     pub render_node: DrmNode,
     pub surfaces: HashMap<CrtcHandle, SurfaceData>,
+    pub disabled_outputs: HashMap<CrtcHandle, Output>,
+    pub powered_off: HashMap<CrtcHandle, PoweredOffOutput>,
     pub gbm: GbmDevice<DrmDeviceFd>,
     pub drm: DrmDevice,
 }
@@ -113,6 +178,14 @@ impl Drop for SurfaceData {
     }
 }
 
+impl Drop for PoweredOffOutput {
+    fn drop(&mut self) {
+        if let Some(global) = self.id.take() {
+            self.dh.remove_global::<Corrosion<UdevData>>(global);
+        }
+    }
+}
+
 impl SurfaceComposition {
     pub fn format(&self) -> smithay::reexports::gbm::Format {
         match self {
@@ -174,12 +247,13 @@ impl SurfaceComposition {
     }
 
     // hell
+    // the damage is None if nothing changed
     fn render_frame<'a, R, E, Target>(
         &'a mut self,
         renderer: &mut R,
         elements: &'a [E],
         clear_color: [f32; 4],
-    ) -> Result<(bool, RenderElementStates), SwapBuffersError>
+    ) -> Result<(Option<Vec<Rectangle<i32, Physical>>>, RenderElementStates), SwapBuffersError>
     where
         R: Renderer + Bind<Dmabuf> + Bind<Target> + Offscreen<Target> + ExportMem,
         <R as Renderer>::TextureId: 'static,
@@ -199,7 +273,6 @@ impl SurfaceComposition {
                     .expect("Unable to bind dmabuf to renderer");
                 let res = damage_tracker
                     .render_output(renderer, age.into(), elements, clear_color)
-                    .map(|(damage, states)| (damage.is_some(), states))
                     .map_err(|err| match err {
                         OutputDamageTrackerError::Rendering(err) => err.into(),
                         _ => unreachable!(),
@@ -208,12 +281,7 @@ impl SurfaceComposition {
             }
             SurfaceComposition::Compositor(comp) => comp
                 .render_frame(renderer, elements, clear_color)
-                .map(|render_frame_result| {
-                    (
-                        render_frame_result.damage.is_some(),
-                        render_frame_result.states,
-                    )
-                })
+                .map(|render_frame_result| (render_frame_result.damage, render_frame_result.states))
                 .map_err(|err| match err {
                     smithay::backend::drm::compositor::RenderFrameError::PrepareFrame(err) => {
                         err.into()
@@ -282,6 +350,8 @@ impl Corrosion<UdevData> {
                 scanner: DrmScanner::new(),
                 render_node,
                 surfaces: HashMap::new(),
+                disabled_outputs: HashMap::new(),
+                powered_off: HashMap::new(),
                 gbm,
                 drm,
             },
@@ -296,22 +366,35 @@ impl Corrosion<UdevData> {
         crtc: CrtcHandle,
         connector: connector::Info,
     ) {
-        let device = if let Some(device) = self.backend_data.backends.get_mut(&node) {
-            device
-        } else {
-            return;
+        let output_name = connector_name(&connector);
+        let output_config = match self.connector_output_config(node, &connector) {
+            Some(output_config) => output_config,
+            None => return,
         };
 
-        let mut renderer = self
+        if !output_config.enabled {
+            tracing::info!("Output {} is disabled in the config", output_name);
+            return;
+        }
+
+        if let Err(err) = self.setup_output(node, crtc, &connector, &output_config) {
+            tracing::error!("Unable to set up connector {}: {}", output_name, err);
+        }
+    }
+
+    // Creates the output of a connector and lights it up
+    fn setup_output(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        connector: &connector::Info,
+        output_config: &OutputConfig,
+    ) -> Result<(), OutputError> {
+        let device = self
             .backend_data
-            .gpu_manager
-            .single_renderer(&device.render_node)
-            .unwrap();
-        let render_formats = renderer
-            .as_mut()
-            .egl_context()
-            .dmabuf_render_formats()
-            .clone();
+            .backends
+            .get_mut(&node)
+            .ok_or(OutputError::UnknownDevice(node))?;
 
         tracing::info!(
             ?crtc,
@@ -320,61 +403,297 @@ impl Corrosion<UdevData> {
             connector.interface_id()
         );
 
-        // Get the preferred output mode
-        let mode = connector
-            .modes()
-            .iter()
-            .position(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
-            .unwrap_or(0);
+        // Outputs disabled at runtime are kept, so they come back with the same state
+        let output = device
+            .disabled_outputs
+            .entry(crtc)
+            .or_insert_with(|| {
+                let (make, model) = connector_make_model(&device.drm, connector);
+                let (physical_width, physical_height) = connector.size().unwrap_or((0, 0));
+                let output = Output::new(
+                    connector_name(connector),
+                    PhysicalProperties {
+                        size: (physical_width as i32, physical_height as i32).into(),
+                        subpixel: smithay::output::Subpixel::Unknown,
+                        make,
+                        model,
+                    },
+                );
+                output.set_preferred(WlMode::from(select_mode(connector, None)));
+                output.user_data().insert_if_missing(|| UdevOutputId {
+                    crtc,
+                    device_id: node,
+                });
+                output
+            })
+            .clone();
 
-        let drm_mode = connector.modes()[mode];
-        let wl_mode = WlMode::from(drm_mode);
+        let position = match output_config.position {
+            Some(position) => (position.x, position.y).into(),
+            None => {
+                let x = self.space.outputs().fold(0, |acc, o| {
+                    acc + self.space.output_geometry(o).unwrap().size.w
+                });
+                (x, 0).into()
+            }
+        };
+        output.change_current_state(
+            None,
+            Some(output_config.transform.into()),
+            Some(output_config.scale()),
+            Some(position),
+        );
 
-        // Create the drm surface
-        let surface = match device
-            .drm
-            .create_surface(crtc, drm_mode, &[connector.handle()])
-        {
+        // Get the configured output mode, or the preferred one
+        let drm_mode = select_mode(connector, output_config.mode.as_ref());
+        self.enable_output(node, crtc, drm_mode)?;
+        self.set_output_vrr(node, crtc, output_config.vrr);
+        Ok(())
+    }
+
+    // Switches the connector on a crtc to another mode. Only the drm surface and compositor get
+    // recreated, the output and its wl_output global stay the same
+    pub fn set_output_mode(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        mode: DrmMode,
+    ) -> Result<(), OutputError> {
+        let output = self
+            .output_for_crtc(node, crtc)
+            .ok_or(OutputError::NotEnabled(crtc))?;
+        let mut previous = self
+            .remove_surface(node, crtc)
+            .ok_or(OutputError::NotEnabled(crtc))?;
+        let previous_mode = previous.compositor.surface().pending_mode();
+        let previous_vrr = previous.vrr;
+        let global = previous.id.take();
+        // The old surface has to release the crtc before a new one can be created
+        drop(previous);
+
+        output.change_current_state(Some(WlMode::from(mode)), None, None, None);
+        let (surface, result) = match self.create_surface(node, crtc, &output, mode) {
+            Ok(surface) => (surface, Ok(())),
+            Err(err) => {
+                tracing::warn!(
+                    "Unable to switch {} to {:?}, going back to {:?}: {}",
+                    output.name(),
+                    mode,
+                    previous_mode,
+                    err
+                );
+                output.change_current_state(Some(WlMode::from(previous_mode)), None, None, None);
+                match self.create_surface(node, crtc, &output, previous_mode) {
+                    Ok(surface) => (surface, Err(err)),
+                    Err(_) => {
+                        // Not even the old mode works anymore, leave the output disabled
+                        if let Some(global) = global {
+                            self.display_handle
+                                .remove_global::<Corrosion<UdevData>>(global);
+                        }
+                        self.space.unmap_output(&output);
+                        if let Some(device) = self.backend_data.backends.get_mut(&node) {
+                            device.disabled_outputs.insert(crtc, output);
+                        }
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        let global = global
+            .unwrap_or_else(|| output.create_global::<Corrosion<UdevData>>(&self.display_handle));
+        self.insert_surface(node, crtc, surface, global, &output);
+        self.set_output_vrr(node, crtc, previous_vrr);
+        result
+    }
+
+    // Turns the crtc off. The output is unmapped and its global removed, but it is kept around
+    // until the connector gets enabled again or disconnected
+    pub fn disable_output(&mut self, node: DrmNode, crtc: CrtcHandle) -> Result<(), OutputError> {
+        let output = self
+            .output_for_crtc(node, crtc)
+            .ok_or(OutputError::NotEnabled(crtc))?;
+
+        // Dropping the surface data removes the global and deactivates the crtc
+        self.remove_surface(node, crtc);
+        self.space.unmap_output(&output);
+        self.backend_data.output_power_state.output_removed(&output);
+
+        let device = self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .ok_or(OutputError::UnknownDevice(node))?;
+        device.powered_off.remove(&crtc);
+        device.disabled_outputs.insert(crtc, output);
+        Ok(())
+    }
+
+    // Turns the crtc of an output off or back on. Unlike disabling it, the output stays mapped
+    // and advertised to clients
+    pub fn set_crtc_power(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        on: bool,
+    ) -> Result<(), OutputError> {
+        let output = self
+            .output_for_crtc(node, crtc)
+            .ok_or(OutputError::NotEnabled(crtc))?;
+        let device = self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .ok_or(OutputError::UnknownDevice(node))?;
+        if device.surfaces.contains_key(&crtc) == on {
+            return Ok(());
+        }
+
+        if on {
+            let mut powered_off = device
+                .powered_off
+                .remove(&crtc)
+                .ok_or(OutputError::NotEnabled(crtc))?;
+            let (mode, vrr) = (powered_off.mode, powered_off.vrr);
+            let surface = match self.create_surface(node, crtc, &output, mode) {
+                Ok(surface) => surface,
+                Err(err) => {
+                    if let Some(device) = self.backend_data.backends.get_mut(&node) {
+                        device.powered_off.insert(crtc, powered_off);
+                    }
+                    return Err(err);
+                }
+            };
+            let global = powered_off.id.take().unwrap_or_else(|| {
+                output.create_global::<Corrosion<UdevData>>(&self.display_handle)
+            });
+            tracing::info!("Powering on {}", output.name());
+            self.insert_surface(node, crtc, surface, global, &output);
+            self.set_output_vrr(node, crtc, vrr);
+        } else {
+            // Dropping the surface stops its repaints and deactivates the crtc
+            let mut surface = self
+                .remove_surface(node, crtc)
+                .ok_or(OutputError::NotEnabled(crtc))?;
+            let powered_off = PoweredOffOutput {
+                dh: self.display_handle.clone(),
+                mode: surface.compositor.surface().pending_mode(),
+                id: surface.id.take(),
+                vrr: surface.vrr,
+            };
+            drop(surface);
+            tracing::info!("Powering off {}", output.name());
+            if let Some(device) = self.backend_data.backends.get_mut(&node) {
+                device.powered_off.insert(crtc, powered_off);
+            }
+        }
+
+        self.backend_data
+            .output_power_state
+            .power_changed(&output, on);
+        Ok(())
+    }
+
+    // Whether the crtc of an output is powered on, None for disabled outputs
+    pub fn crtc_power(&self, node: DrmNode, crtc: CrtcHandle) -> Option<bool> {
+        let device = self.backend_data.backends.get(&node)?;
+        if device.surfaces.contains_key(&crtc) {
+            Some(true)
+        } else if device.powered_off.contains_key(&crtc) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    // The mode and vrr policy of an enabled output, whether it is powered on or not
+    pub fn crtc_state(&self, node: DrmNode, crtc: CrtcHandle) -> Option<(DrmMode, OutputVrr)> {
+        let device = self.backend_data.backends.get(&node)?;
+        match device.surfaces.get(&crtc) {
+            Some(surface) => Some((surface.compositor.surface().pending_mode(), surface.vrr)),
+            None => device
+                .powered_off
+                .get(&crtc)
+                .map(|powered_off| (powered_off.mode, powered_off.vrr)),
+        }
+    }
+
+    // Lights a disabled output up again with the given mode
+    pub fn enable_output(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        mode: DrmMode,
+    ) -> Result<(), OutputError> {
+        let output = self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .ok_or(OutputError::UnknownDevice(node))?
+            .disabled_outputs
+            .remove(&crtc)
+            .ok_or(OutputError::NotDisabled(crtc))?;
+
+        output.change_current_state(Some(WlMode::from(mode)), None, None, None);
+        let surface = match self.create_surface(node, crtc, &output, mode) {
             Ok(surface) => surface,
             Err(err) => {
-                tracing::error!("Failure to create drm surface: {}", err);
-                return;
+                if let Some(device) = self.backend_data.backends.get_mut(&node) {
+                    device.disabled_outputs.insert(crtc, output);
+                }
+                return Err(err);
             }
         };
-        let output_name = format!(
-            "{}-{}",
-            connector.interface().as_str(),
-            connector.interface_id()
-        );
 
-        let (make, model) = EdidInfo::for_connector(&device.drm, connector.handle())
-            .map(|info| (info.manufacturer, info.model))
-            .unwrap();
-        let (physical_width, physical_height) = connector.size().unwrap_or((0, 0));
-        let output = Output::new(
-            output_name,
-            PhysicalProperties {
-                size: (physical_width as i32, physical_height as i32).into(),
-                subpixel: smithay::output::Subpixel::Unknown,
-                make,
-                model,
-            },
-        );
+        // Only advertise the output once it can actually be rendered to
         let global = output.create_global::<Corrosion<UdevData>>(&self.display_handle);
+        self.insert_surface(node, crtc, surface, global, &output);
+        Ok(())
+    }
 
-        let x = self.space.outputs().fold(0, |acc, o| {
-            acc + self.space.output_geometry(o).unwrap().size.w
-        });
+    // Creates the drm surface and compositor for an output, the output's current mode has to be
+    // set to `mode` already
+    fn create_surface(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        output: &Output,
+        mode: DrmMode,
+    ) -> Result<SurfaceData, OutputError> {
+        let device = self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .ok_or(OutputError::UnknownDevice(node))?;
+
+        let mut renderer = self
+            .backend_data
+            .gpu_manager
+            .single_renderer(&device.render_node)
+            .unwrap();
+        let render_formats = renderer
+            .as_mut()
+            .egl_context()
+            .dmabuf_render_formats()
+            .clone();
 
-        let position = (x, 0).into();
-        output.set_preferred(wl_mode);
-        output.change_current_state(Some(wl_mode), None, None, Some(position));
-        self.space.map_output(&output, position);
+        let connectors: Vec<_> = device
+            .scanner
+            .crtcs()
+            .filter(|(_, connector_crtc)| *connector_crtc == crtc)
+            .map(|(connector, _)| connector.handle())
+            .collect();
+        let vrr_capable = connectors
+            .first()
+            .and_then(|connector| drm_property(&device.drm, *connector, "vrr_capable"))
+            .map_or(false, |(_, value)| value == 1);
 
-        output.user_data().insert_if_missing(|| UdevOutputId {
-            crtc,
-            device_id: node,
-        });
+        // Create the drm surface
+        let surface = device
+            .drm
+            .create_surface(crtc, mode, &connectors)
+            .map_err(|err| OutputError::Surface(err.to_string()))?;
 
         let allocator = GbmAllocator::new(
             device.gbm.clone(),
@@ -383,38 +702,22 @@ impl Corrosion<UdevData> {
         // We initialize the compositor.
         let compositor = if std::env::var("CORROSION_DISABLE_HARDWARE_COMPOSITOR").is_ok() {
             tracing::info!("Creating software-rendered compositor");
-            let gbm_surface = match GbmBufferedSurface::new(
-                surface,
-                allocator,
-                SUPPORTED_FORMATS,
-                render_formats,
-            ) {
-                Ok(render_surface) => render_surface,
-                Err(err) => {
-                    tracing::error!("Error creating gbm rendering surface: {}", err);
-                    return;
-                }
-            };
+            let gbm_surface =
+                GbmBufferedSurface::new(surface, allocator, SUPPORTED_FORMATS, render_formats)
+                    .map_err(|err| OutputError::Compositor(err.to_string()))?;
             SurfaceComposition::Surface {
                 surface: gbm_surface,
-                damage_tracker: OutputDamageTracker::from_output(&output),
+                damage_tracker: OutputDamageTracker::from_output(output),
             }
         } else {
-            let drivers = match device.drm.get_driver() {
-                Ok(driver) => driver,
-                Err(err) => {
-                    tracing::error!("Unable to get device driver: {}", err);
-                    return;
-                }
-            };
+            let drivers = device
+                .drm
+                .get_driver()
+                .map_err(|err| OutputError::Driver(err.to_string()))?;
 
-            let mut planes = match surface.planes() {
-                Ok(planes) => planes,
-                Err(err) => {
-                    tracing::error!("Unable to get surface planes: {}", err);
-                    return;
-                }
-            };
+            let mut planes = surface
+                .planes()
+                .map_err(|err| OutputError::Planes(err.to_string()))?;
 
             if drivers
                 .name()
@@ -432,8 +735,8 @@ impl Corrosion<UdevData> {
                 planes.overlay = vec![];
             }
 
-            let mut compositor = match DrmCompositor::new(
-                &output,
+            let compositor = DrmCompositor::new(
+                output,
                 surface,
                 Some(planes),
                 allocator,
@@ -442,13 +745,8 @@ impl Corrosion<UdevData> {
                 render_formats,
                 device.drm.cursor_size(),
                 Some(device.gbm.clone()),
-            ) {
-                Ok(compositor) => compositor,
-                Err(err) => {
-                    tracing::error!("Error creating hardware-accelerated compositor: {}", err);
-                    return;
-                }
-            };
+            )
+            .map_err(|err| OutputError::Compositor(err.to_string()))?;
             SurfaceComposition::Compositor(compositor)
         };
 
@@ -459,21 +757,158 @@ impl Corrosion<UdevData> {
             &compositor,
         );
 
-        device.surfaces.insert(
-            crtc,
-            SurfaceData {
-                dh: self.display_handle.clone(),
-                compositor,
-                id: Some(global),
-                render_node: device.render_node,
-                device_node: node,
-                dmabuf_feedback,
-            },
-        );
+        Ok(SurfaceData {
+            dh: self.display_handle.clone(),
+            compositor,
+            id: None,
+            render_node: device.render_node,
+            device_node: node,
+            dmabuf_feedback,
+            render_timer: None,
+            frame_clock: FrameClock::new(WlMode::from(mode).refresh),
+            needs_render: false,
+            frame_pending: false,
+            vrr: OutputVrr::Off,
+            vrr_capable,
+            vrr_enabled: None,
+            pending_screencopies: Vec::new(),
+        })
+    }
 
+    // Hands a freshly created surface its global, maps the output and starts rendering
+    fn insert_surface(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        mut surface: SurfaceData,
+        global: GlobalId,
+        output: &Output,
+    ) {
+        surface.id = Some(global);
+        if let Some(device) = self.backend_data.backends.get_mut(&node) {
+            device.surfaces.insert(crtc, surface);
+        }
+        self.space.map_output(output, output.current_location());
         self.schedule_initial_render(node, crtc);
     }
 
+    // Sets the vrr policy of an output and applies it right away
+    pub fn set_output_vrr(&mut self, node: DrmNode, crtc: CrtcHandle, vrr: OutputVrr) {
+        let device = match self.backend_data.backends.get_mut(&node) {
+            Some(device) => device,
+            None => return,
+        };
+        if let Some(powered_off) = device.powered_off.get_mut(&crtc) {
+            // applied when it gets powered on again
+            powered_off.vrr = vrr;
+            return;
+        }
+        let surface = match device.surfaces.get_mut(&crtc) {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        if vrr != OutputVrr::Off && !surface.vrr_capable {
+            tracing::warn!("{:?} does not support variable refresh rate", crtc);
+        }
+        surface.vrr = vrr;
+        self.update_vrr(node, crtc);
+    }
+
+    // Turns vrr on the crtc on or off following the output's policy, the drm property is only
+    // touched when that changes. Called right before every frame, so failed attempts are retried
+    // with the next one
+    fn update_vrr(&mut self, node: DrmNode, crtc: CrtcHandle) {
+        let fullscreen = self
+            .output_for_crtc(node, crtc)
+            .map_or(false, |output| has_fullscreen_window(&self.space, &output));
+
+        let device = match self.backend_data.backends.get_mut(&node) {
+            Some(device) => device,
+            None => return,
+        };
+        let surface = match device.surfaces.get_mut(&crtc) {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        let enabled = surface.vrr_capable
+            && match surface.vrr {
+                OutputVrr::Off => false,
+                OutputVrr::On => true,
+                OutputVrr::FullscreenOnly => fullscreen,
+            };
+        if surface.vrr_enabled == Some(enabled) {
+            return;
+        }
+
+        let result = match drm_property(&device.drm, crtc, "VRR_ENABLED") {
+            // smithay has no way to add a property to the commits of a surface, so it gets an
+            // atomic commit of its own between two frames
+            Some((property, _)) if device.drm.is_atomic() => {
+                let mut request = AtomicModeReq::new();
+                request.add_property(crtc, property, property::Value::Boolean(enabled));
+                device
+                    .drm
+                    .atomic_commit(AtomicCommitFlags::NONBLOCK, request)
+            }
+            Some((property, _)) => device.drm.set_property(crtc, property, enabled as u64),
+            // Drivers without vrr don't have the property at all
+            None => Ok(()),
+        };
+        match result {
+            Ok(()) => {
+                tracing::info!(
+                    "Variable refresh rate {} on {:?}",
+                    if enabled { "enabled" } else { "disabled" },
+                    crtc
+                );
+                surface.vrr_enabled = Some(enabled);
+                surface.frame_clock.set_vrr(enabled);
+            }
+            // e.g. a page flip that is still pending
+            Err(err) if is_transient(&err) => {
+                tracing::debug!("Unable to set VRR_ENABLED on {:?} yet: {}", crtc, err);
+            }
+            Err(err) => {
+                tracing::error!("Unable to set VRR_ENABLED on {:?}: {}", crtc, err);
+                surface.vrr_capable = false;
+                surface.vrr_enabled = Some(false);
+                surface.frame_clock.set_vrr(false);
+            }
+        }
+    }
+
+    // Takes the surface of a crtc out, cancelling its pending repaint
+    fn remove_surface(&mut self, node: DrmNode, crtc: CrtcHandle) -> Option<SurfaceData> {
+        let mut surface = self
+            .backend_data
+            .backends
+            .get_mut(&node)?
+            .surfaces
+            .remove(&crtc)?;
+        if let Some(token) = surface.render_timer.take() {
+            self.handle.remove(token);
+        }
+        Some(surface)
+    }
+
+    // The [output] config of a connector, matched by name or edid
+    fn connector_output_config(
+        &self,
+        node: DrmNode,
+        connector: &connector::Info,
+    ) -> Option<OutputConfig> {
+        let device = self.backend_data.backends.get(&node)?;
+        let (make, model) = connector_make_model(&device.drm, connector);
+        Some(
+            self.config
+                .output_config(&connector_name(connector), &make, &model)
+                .cloned()
+                .unwrap_or_default(),
+        )
+    }
+
     // Gets called when the device changes
     pub fn device_changed(&mut self, node: DrmNode) {
         let device = if let Some(device) = self.backend_data.backends.get_mut(&node) {
@@ -500,6 +935,8 @@ impl Corrosion<UdevData> {
                 _ => (),
             };
         }
+
+        self.update_output_management();
     }
 
     pub fn device_removed(&mut self, node: DrmNode) {
@@ -528,6 +965,8 @@ impl Corrosion<UdevData> {
 
             tracing::debug!("Dropped device");
         }
+
+        self.update_output_management();
     }
 
     pub fn frame_finish(
@@ -564,17 +1003,25 @@ impl Corrosion<UdevData> {
             return;
         };
 
-        let schedule_render = match surface
+        match surface
             .compositor
             .frame_submitted()
             .map_err(Into::<SwapBuffersError>::into)
         {
-            Ok(user_data) => {
-                if let Some(mut feedback) = user_data.flatten() {
-                    let tp = meta.as_ref().and_then(|metadata| match metadata.time {
-                        smithay::backend::drm::DrmEventTime::Monotonic(time) => Some(time),
-                        smithay::backend::drm::DrmEventTime::Realtime(_) => None,
-                    });
+            // The vblank of a surface that got replaced, the new one runs its own repaint loop
+            Ok(None) => return,
+            Ok(Some(user_data)) => {
+                surface.frame_pending = false;
+
+                let tp = meta.as_ref().and_then(|metadata| match metadata.time {
+                    smithay::backend::drm::DrmEventTime::Monotonic(time) => Some(time),
+                    smithay::backend::drm::DrmEventTime::Realtime(_) => None,
+                });
+                surface
+                    .frame_clock
+                    .presented(tp.unwrap_or_else(|| self.clock.now().into()));
+
+                if let Some(mut feedback) = user_data {
                     let seq = meta.as_ref().map(|metadata| metadata.sequence).unwrap_or(0);
 
                     let (clock, flags) = if let Some(tp) = tp {
@@ -598,11 +1045,11 @@ impl Corrosion<UdevData> {
                         flags,
                     );
                 }
-                true
             }
             Err(err) => {
                 tracing::error!("Error occurred wile rendering: {}", err);
-                match err {
+                surface.frame_pending = false;
+                let retry = match err {
                     SwapBuffersError::AlreadySwapped => true,
                     SwapBuffersError::TemporaryFailure(err)
                         if matches!(
@@ -624,38 +1071,18 @@ impl Corrosion<UdevData> {
                     SwapBuffersError::ContextLost(err) => {
                         panic!("Rendering loop has been lost: {}", err)
                     }
+                };
+                if !retry {
+                    return;
                 }
+                // The failed frame never made it to the screen
+                surface.needs_render = true;
             }
         };
 
-        if schedule_render {
-            let output_refresh = match output.current_mode() {
-                Some(mode) => mode.refresh,
-                None => {
-                    return;
-                }
-            };
-
-            let repaint_delay =
-                Duration::from_millis(((1_000_000f32 / output_refresh as f32) * 0.6f32) as u64);
-            let timer = if self.backend_data.primary_gpu != surface.render_node {
-                tracing::info!("Scheduling repaint timer for {:?} immediately", crtc);
-                Timer::immediate()
-            } else {
-                tracing::info!(
-                    "Scheduling repaint timer for {:?} with a delay of {:?}",
-                    crtc,
-                    repaint_delay
-                );
-                Timer::from_duration(repaint_delay)
-            };
-
-            self.handle
-                .insert_source(timer, move |_, _, data| {
-                    data.state.render_surface(node, crtc);
-                    smithay::reexports::calloop::timer::TimeoutAction::Drop
-                })
-                .expect("Unable to insert rendering function into event loop");
+        // Damage that arrived while the frame was pending gets painted now
+        if surface.needs_render {
+            self.schedule_repaint(node, crtc);
         }
     }
 
@@ -677,24 +1104,21 @@ impl Corrosion<UdevData> {
             connector.interface_id()
         );
 
-        device.surfaces.remove(&crtc);
+        device.disabled_outputs.remove(&crtc);
+        device.powered_off.remove(&crtc);
+        self.remove_surface(node, crtc);
 
-        let output = self
-            .space
-            .outputs()
-            .find(|output| {
-                output
-                    .user_data()
-                    .get::<UdevOutputId>()
-                    .map(|id| id.device_id == node && id.crtc == crtc)
-                    .unwrap_or(false)
-            })
-            .cloned();
-        if let Some(output) = output {
+        if let Some(output) = self.output_for_crtc(node, crtc) {
             self.space.unmap_output(&output);
+            self.backend_data.output_power_state.output_removed(&output);
         }
     }
+
     pub fn render_surface(&mut self, node: DrmNode, crtc: CrtcHandle) {
+        self.update_vrr(node, crtc);
+        // taken before the renderer borrows the backend
+        let input_popups = self.input_popups();
+
         let device = if let Some(device) = self.backend_data.backends.get_mut(&node) {
             device
         } else {
@@ -706,6 +1130,10 @@ impl Corrosion<UdevData> {
         } else {
             return;
         };
+        // We are the timer, so it is gone after this
+        surface.render_timer = None;
+        surface.needs_render = false;
+        let render_start = Instant::now();
 
         let frame = self
             .backend_data
@@ -774,21 +1202,7 @@ impl Corrosion<UdevData> {
         let output_geometry = self.space.output_geometry(&output).unwrap();
         let scale = Scale::from(output.current_scale().fractional_scale());
         if output_geometry.to_f64().contains(self.pointer_location) {
-            let cursor_hotspot = if let CursorImageStatus::Surface(ref surface) =
-                *self.cursor_image_status.lock().unwrap()
-            {
-                compositor::with_states(surface, |states| {
-                    states
-                        .data_map
-                        .get::<Mutex<CursorImageAttributes>>()
-                        .unwrap()
-                        .lock()
-                        .unwrap()
-                        .hotspot
-                })
-            } else {
-                (0, 0).into()
-            };
+            let cursor_hotspot = cursor_hotspot(&self.cursor_image_status.lock().unwrap());
             let cursor_pos =
                 self.pointer_location - output_geometry.loc.to_f64() - cursor_hotspot.to_f64();
             let cursor_pos_scaled = cursor_pos.to_physical(scale).to_i32_round();
@@ -823,20 +1237,112 @@ impl Corrosion<UdevData> {
             ));
         }
 
-        elements.extend(
-            space::space_render_elements(&mut renderer, [&self.space], &output)
-                .expect("Output without mode")
-                .into_iter()
-                .map(|element| CustomRenderElements::Space(element)),
-        );
-        let (rendered, states) = surface
+        // a tablet tool has a cursor of its own while it is close to the tablet
+        if let Some(location) = self
+            .tablet_tool_location
+            .filter(|location| output_geometry.to_f64().contains(*location))
+        {
+            let status = {
+                let mut status = self.tablet_cursor_image_status.lock().unwrap();
+                if matches!(&*status, CursorImageStatus::Surface(surface) if !surface.alive()) {
+                    *status = CursorImageStatus::Default;
+                }
+                status.clone()
+            };
+            let position =
+                (location - output_geometry.loc.to_f64() - cursor_hotspot(&status).to_f64())
+                    .to_physical(scale)
+                    .to_i32_round();
+
+            let tablet_pointer_element = &mut self.backend_data.tablet_pointer_element;
+            tablet_pointer_element.set_texture(pointer_image.clone());
+            tablet_pointer_element.set_status(status);
+            elements.extend(tablet_pointer_element.render_elements(&mut renderer, position, scale));
+        }
+
+        // screencopy frames may leave the cursor out
+        let cursor_elements = elements.len();
+
+        // the drag and drop icon sits right under the cursor
+        let locked = self.session_lock_state.is_locked();
+        let dnd_icon = self.dnd_icon.as_ref().filter(|icon| icon.alive());
+        if let Some(icon) = dnd_icon.filter(|_| !locked) {
+            let location = (self.pointer_location - output_geometry.loc.to_f64())
+                .to_physical(scale)
+                .to_i32_round();
+            elements.extend(
+                render_elements_from_surface_tree(&mut renderer, icon, location, scale)
+                    .into_iter()
+                    .map(CustomRenderElements::DndIcon),
+            );
+        }
+
+        // input method popups go above the window they type into
+        if !locked {
+            for (popup, location) in input_popups {
+                let location = (location - output_geometry.loc).to_physical_precise_round(scale);
+                elements.extend(
+                    render_elements_from_surface_tree(&mut renderer, &popup, location, scale)
+                        .into_iter()
+                        .map(CustomRenderElements::InputPopup),
+                );
+            }
+        }
+
+        // A locked session only shows the lock surface, or a solid colour if there is none
+        let clear_color = if locked {
+            if let Some(lock_surface) = self.session_lock_state.lock_surface(&output) {
+                elements.extend(
+                    render_elements_from_surface_tree(
+                        &mut renderer,
+                        lock_surface.wl_surface(),
+                        (0, 0),
+                        scale,
+                    )
+                    .into_iter()
+                    .map(CustomRenderElements::LockSurface),
+                );
+            }
+            CLEAR_COLOR_LOCKED
+        } else {
+            // urgent windows get a border, drawn on top of whatever overlaps it
+            let urgent_border = self.backend_data.urgent_border.get_or_insert_with(|| {
+                TextureBuffer::from_memory(
+                    &mut renderer,
+                    &URGENT_BORDER_COLOR,
+                    Fourcc::Abgr8888,
+                    (1, 1),
+                    false,
+                    1,
+                    Transform::Normal,
+                    None,
+                )
+                .expect("Failed to import the urgent border")
+            });
+            for window in &self.urgent_windows {
+                if let Some(mut geometry) = self.space.element_geometry(window) {
+                    geometry.loc -= output_geometry.loc;
+                    elements.extend(
+                        border_elements(urgent_border, geometry, URGENT_BORDER_WIDTH, scale)
+                            .into_iter()
+                            .map(CustomRenderElements::Border),
+                    );
+                }
+            }
+
+            elements.extend(
+                space::space_render_elements(&mut renderer, [&self.space], &output)
+                    .expect("Output without mode")
+                    .into_iter()
+                    .map(|element| CustomRenderElements::Space(element)),
+            );
+            [0.2f32, 0.05f32, 0.6f32, 1.0f32]
+        };
+        let (damage, states) = surface
             .compositor
-            .render_frame::<_, _, GlesTexture>(
-                &mut renderer,
-                &elements,
-                [0.2f32, 0.05f32, 0.6f32, 1.0f32],
-            )
+            .render_frame::<_, _, GlesTexture>(&mut renderer, &elements, clear_color)
             .unwrap();
+        let rendered = damage.is_some();
 
         post_repaint(
             &output,
@@ -851,35 +1357,333 @@ impl Corrosion<UdevData> {
                 }),
             self.clock.now(),
         );
+        // Without damage nothing gets queued, the next commit or cursor move schedules a repaint
         if rendered {
             let output_feedback = take_presentation_feedback(&output, &self.space, &states);
             surface
                 .compositor
                 .queue_frame(Some(output_feedback))
                 .unwrap();
-        } else {
-            let output_refresh = match output.current_mode() {
-                Some(mode) => mode.refresh,
-                None => return,
+            surface.frame_pending = true;
+            surface.frame_clock.rendered(render_start.elapsed());
+        }
+
+        // copies with damage wait for a frame that actually changed something
+        let (screencopies, waiting): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut surface.pending_screencopies)
+                .into_iter()
+                .partition(|frame| rendered || !frame.with_damage());
+        surface.pending_screencopies = waiting;
+        for frame in screencopies {
+            let elements = if frame.overlay_cursor() {
+                &elements[..]
+            } else {
+                &elements[cursor_elements..]
+            };
+            match render_screencopy(&mut renderer, &frame, elements, clear_color) {
+                Ok(()) => frame.submit(
+                    damage.as_deref().unwrap_or_default(),
+                    self.clock.now().into(),
+                ),
+                Err(err) => tracing::warn!("Unable to copy {}: {}", output.name(), err),
+            }
+        }
+
+        self.send_lock_surface_frames(&output);
+        self.send_dnd_icon_frames(&output);
+        self.send_input_popup_frames(&output);
+    }
+
+    // Hands a screencopy frame to the surface of a crtc, false if there is none
+    pub fn queue_screencopy(&mut self, node: DrmNode, crtc: CrtcHandle, frame: Screencopy) -> bool {
+        match self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .and_then(|device| device.surfaces.get_mut(&crtc))
+        {
+            Some(surface) => {
+                surface.pending_screencopies.push(frame);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Marks the output on a crtc as damaged. The repaint is timed by the frame clock, or waits
+    // for the vblank of a frame that is still pending
+    pub fn schedule_repaint(&mut self, node: DrmNode, crtc: CrtcHandle) {
+        let now = self.clock.now().into();
+        let surface = match self
+            .backend_data
+            .backends
+            .get_mut(&node)
+            .and_then(|device| device.surfaces.get_mut(&crtc))
+        {
+            Some(surface) => surface,
+            None => return,
+        };
+
+        surface.needs_render = true;
+        if surface.frame_pending || surface.render_timer.is_some() {
+            return;
+        }
+
+        let delay = surface.frame_clock.repaint_delay(now);
+        tracing::trace!("Scheduling repaint timer for {:?} in {:?}", crtc, delay);
+        let token = self
+            .handle
+            .insert_source(Timer::from_duration(delay), move |_, _, data| {
+                data.state.render_surface(node, crtc);
+                TimeoutAction::Drop
+            })
+            .expect("Unable to insert rendering function into event loop");
+        surface.render_timer = Some(token);
+    }
+
+    // Re-applies the [output] config to every connector, called on config reload
+    pub fn apply_output_config(&mut self) {
+        for (node, connector, crtc) in self.connectors() {
+            let output_config = match self.connector_output_config(node, &connector) {
+                Some(output_config) => output_config,
+                None => continue,
             };
-            // If reschedule is true we either hit a temporary failure or more likely rendering
-            // did not cause any damage on the output. In this case we just re-schedule a repaint
-            // after approx. one frame to re-test for damage.
-            let reschedule_duration =
-                Duration::from_millis((1_000_000f32 / output_refresh as f32) as u64);
-            tracing::trace!(
-                "reschedule repaint timer with delay {:?} on {:?}",
-                reschedule_duration,
+            if let Err(err) = self.reconfigure_output(node, crtc, &connector, &output_config) {
+                tracing::error!(
+                    "Unable to apply config to {}: {}",
+                    connector_name(&connector),
+                    err
+                );
+            }
+        }
+        self.update_output_management();
+    }
+
+    // Every connected connector of every device, with the crtc the scanner assigned to it
+    pub fn connectors(&self) -> Vec<(DrmNode, connector::Info, CrtcHandle)> {
+        self.backend_data
+            .backends
+            .iter()
+            .flat_map(|(node, device)| {
+                device
+                    .scanner
+                    .crtcs()
+                    .map(move |(info, crtc)| (*node, info.clone(), crtc))
+            })
+            .collect()
+    }
+
+    // The state of a connector expressed as an output config, used to roll back changes
+    pub fn current_output_config(&self, node: DrmNode, crtc: CrtcHandle) -> OutputConfig {
+        let output = self.output_for_crtc(node, crtc);
+        match (output, self.crtc_state(node, crtc)) {
+            (Some(output), Some((drm_mode, vrr))) => {
+                let (width, height) = drm_mode.size();
+                OutputConfig {
+                    enabled: true,
+                    mode: Some(OutputMode {
+                        width: width as i32,
+                        height: height as i32,
+                        refresh: Some(WlMode::from(drm_mode).refresh as f64 / 1000.0),
+                    }),
+                    position: Some(OutputPosition {
+                        x: output.current_location().x,
+                        y: output.current_location().y,
+                    }),
+                    scale: Some(output.current_scale().fractional_scale()),
+                    transform: output.current_transform().into(),
+                    vrr,
+                }
+            }
+            _ => OutputConfig {
+                enabled: false,
+                ..Default::default()
+            },
+        }
+    }
+
+    // The single path every output change goes through: enabling, disabling, switching the
+    // mode, or just moving, scaling and rotating the output
+    pub fn reconfigure_output(
+        &mut self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        connector: &connector::Info,
+        output_config: &OutputConfig,
+    ) -> Result<(), OutputError> {
+        let current_mode = self.crtc_state(node, crtc).map(|(mode, _)| mode);
+
+        match (self.output_for_crtc(node, crtc), current_mode) {
+            (Some(output), Some(current_mode)) if output_config.enabled => {
+                let drm_mode = select_mode(connector, output_config.mode.as_ref());
+                if drm_mode != current_mode {
+                    // a powered off output has no surface to switch
+                    self.set_crtc_power(node, crtc, true)?;
+                    self.set_output_mode(node, crtc, drm_mode)?;
+                }
+                apply_output_state(&mut self.space, &output, output_config);
+                self.set_output_vrr(node, crtc, output_config.vrr);
+            }
+            (Some(_), _) => {
+                tracing::info!("Disabling output {}", connector_name(connector));
+                self.disable_output(node, crtc)?;
+            }
+            (None, _) if output_config.enabled => {
+                self.setup_output(node, crtc, connector, output_config)?;
+            }
+            (None, _) => (),
+        }
+        Ok(())
+    }
+
+    // Checks changes to several outputs without touching any of them. Everything that
+    // reconfigure_output could fail on is checked up front, and the driver is asked whether it
+    // can do all the modesets together
+    pub fn check_output_configs(
+        &self,
+        changes: &[(DrmNode, CrtcHandle, connector::Info, OutputConfig)],
+    ) -> Result<(), OutputError> {
+        let mut modesets: HashMap<DrmNode, Vec<Modeset>> = HashMap::new();
+        for (node, crtc, connector, output_config) in changes {
+            let modeset = self.check_output_config(*node, *crtc, connector, output_config)?;
+            if let Some(modeset) = modeset {
+                modesets.entry(*node).or_default().push(modeset);
+            }
+        }
+        for (node, modesets) in modesets {
+            self.test_modesets(node, &modesets)?;
+        }
+        Ok(())
+    }
+
+    // Checks an output config the way reconfigure_output would apply it, returns the modeset it
+    // needs if the crtc has to be turned on, off or switched to another mode
+    fn check_output_config(
+        &self,
+        node: DrmNode,
+        crtc: CrtcHandle,
+        connector: &connector::Info,
+        output_config: &OutputConfig,
+    ) -> Result<Option<Modeset>, OutputError> {
+        if !self.backend_data.backends.contains_key(&node) {
+            return Err(OutputError::UnknownDevice(node));
+        }
+        let current_mode = self.crtc_state(node, crtc).map(|(mode, _)| mode);
+
+        if !output_config.enabled {
+            return Ok(current_mode.map(|_| Modeset {
                 crtc,
-            );
-            let timer = Timer::from_duration(reschedule_duration);
-            self.handle
-                .insert_source(timer, move |_, _, data| {
-                    data.state.render_surface(node, crtc);
-                    smithay::reexports::calloop::timer::TimeoutAction::Drop
-                })
-                .expect("failed to schedule frame timer");
+                connector: connector.handle(),
+                mode: None,
+            }));
+        }
+
+        // unlike the config, a requested mode doesn't fall back to the preferred one
+        let mode = match &output_config.mode {
+            Some(mode) => find_mode(connector, mode)
+                .ok_or_else(|| OutputError::UnsupportedMode(String::from(*mode)))?,
+            None => select_mode(connector, None),
+        };
+        Ok((current_mode != Some(mode)).then_some(Modeset {
+            crtc,
+            connector: connector.handle(),
+            mode: Some(mode),
+        }))
+    }
+
+    // Asks the driver whether it could do all modesets of a device at once, with a TEST_ONLY
+    // commit that changes nothing. Legacy drivers can't test, they only fail on apply
+    fn test_modesets(&self, node: DrmNode, modesets: &[Modeset]) -> Result<(), OutputError> {
+        let device = self
+            .backend_data
+            .backends
+            .get(&node)
+            .ok_or(OutputError::UnknownDevice(node))?;
+        if !device.drm.is_atomic() || modesets.is_empty() {
+            return Ok(());
+        }
+
+        let mut request = AtomicModeReq::new();
+        // the test needs something to scan out, these are released again right after it
+        let mut buffers = Vec::new();
+        let mut framebuffers = Vec::new();
+        let mut blobs = Vec::new();
+        let result = modesets
+            .iter()
+            .try_for_each(|modeset| {
+                add_modeset(
+                    device,
+                    modeset,
+                    &mut request,
+                    &mut buffers,
+                    &mut framebuffers,
+                    &mut blobs,
+                )
+            })
+            .and_then(|()| {
+                device
+                    .drm
+                    .atomic_commit(
+                        AtomicCommitFlags::ALLOW_MODESET | AtomicCommitFlags::TEST_ONLY,
+                        request,
+                    )
+                    .map_err(|err| OutputError::Rejected(err.to_string()))
+            });
+
+        for framebuffer in framebuffers {
+            let _ = device.drm.destroy_framebuffer(framebuffer);
+        }
+        for blob in blobs {
+            let _ = device.drm.destroy_property_blob(blob);
+        }
+        drop(buffers);
+        result
+    }
+
+    // Applies all changes or none of them. Nothing is touched before every change passed the
+    // same checks a test gets, so the rollback is only for failures the driver didn't predict
+    pub fn reconfigure_outputs(
+        &mut self,
+        changes: Vec<(DrmNode, CrtcHandle, connector::Info, OutputConfig)>,
+    ) -> Result<(), OutputError> {
+        self.check_output_configs(&changes)?;
+
+        let mut applied = Vec::new();
+        for (node, crtc, connector, output_config) in changes {
+            let previous = self.current_output_config(node, crtc);
+            match self.reconfigure_output(node, crtc, &connector, &output_config) {
+                Ok(()) => applied.push((node, crtc, connector, previous)),
+                Err(err) => {
+                    // the failed output itself may be half torn down as well
+                    applied.push((node, crtc, connector, previous));
+                    for (node, crtc, connector, previous) in applied.into_iter().rev() {
+                        if let Err(err) = self.reconfigure_output(node, crtc, &connector, &previous)
+                        {
+                            tracing::error!(
+                                "Unable to roll back {}: {}",
+                                connector_name(&connector),
+                                err
+                            );
+                        }
+                    }
+                    return Err(err);
+                }
+            }
         }
+        Ok(())
+    }
+
+    pub fn output_for_crtc(&self, node: DrmNode, crtc: CrtcHandle) -> Option<Output> {
+        self.space
+            .outputs()
+            .find(|output| {
+                output
+                    .user_data()
+                    .get::<UdevOutputId>()
+                    .map(|id| id.device_id == node && id.crtc == crtc)
+                    .unwrap_or(false)
+            })
+            .cloned()
     }
 
     pub fn schedule_initial_render(&mut self, node: DrmNode, crtc: CrtcHandle) {
@@ -906,6 +1710,255 @@ impl Corrosion<UdevData> {
     }
 }
 
+pub fn connector_name(connector: &connector::Info) -> String {
+    format!(
+        "{}-{}",
+        connector.interface().as_str(),
+        connector.interface_id()
+    )
+}
+
+pub fn connector_make_model(drm: &DrmDevice, connector: &connector::Info) -> (String, String) {
+    EdidInfo::for_connector(drm, connector.handle())
+        .map(|info| (info.manufacturer, info.model))
+        .unwrap_or_else(|| ("Unknown".into(), "Unknown".into()))
+}
+
+// Picks the mode closest to the configured one, falling back to the preferred mode
+pub fn select_mode(connector: &connector::Info, wanted: Option<&OutputMode>) -> DrmMode {
+    let preferred = connector
+        .modes()
+        .iter()
+        .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+        .or_else(|| connector.modes().first())
+        .copied()
+        .expect("Connector without modes");
+
+    let wanted = match wanted {
+        Some(wanted) => wanted,
+        None => return preferred,
+    };
+
+    find_mode(connector, wanted).unwrap_or_else(|| {
+        tracing::warn!(
+            "Mode {} is not supported by {}, using the preferred mode",
+            String::from(*wanted),
+            connector_name(connector)
+        );
+        preferred
+    })
+}
+
+// Finds the connector mode with the wanted size and the closest refresh rate
+pub fn find_mode(connector: &connector::Info, wanted: &OutputMode) -> Option<DrmMode> {
+    let refresh_distance = |mode: &DrmMode| {
+        let refresh = WlMode::from(*mode).refresh;
+        match wanted.refresh {
+            Some(wanted) => (refresh - (wanted * 1000.0) as i32).abs(),
+            // without a refresh rate, prefer the fastest one
+            None => -refresh,
+        }
+    };
+
+    connector
+        .modes()
+        .iter()
+        .filter(|mode| {
+            let (w, h) = mode.size();
+            (w as i32, h as i32) == (wanted.width, wanted.height)
+        })
+        .min_by_key(|mode| refresh_distance(mode))
+        .copied()
+}
+
+// Where the image is held, cursor surfaces set it themselves
+fn cursor_hotspot(status: &CursorImageStatus) -> Point<i32, Logical> {
+    if let CursorImageStatus::Surface(surface) = status {
+        compositor::with_states(surface, |states| {
+            states
+                .data_map
+                .get::<Mutex<CursorImageAttributes>>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .hotspot
+        })
+    } else {
+        (0, 0).into()
+    }
+}
+
+// Looks up a drm property of a connector, crtc or plane by name, with its current value
+fn drm_property(
+    drm: &DrmDevice,
+    handle: impl ResourceHandle,
+    name: &str,
+) -> Option<(property::Handle, property::RawValue)> {
+    let properties = drm.get_properties(handle).ok()?;
+    let (handles, values) = properties.as_props_and_values();
+    handles.iter().zip(values).find_map(|(property, value)| {
+        let info = drm.get_property(*property).ok()?;
+        (info.name().to_str() == Ok(name)).then_some((*property, *value))
+    })
+}
+
+// A crtc that has to be turned on with a mode, or off with None
+struct Modeset {
+    crtc: CrtcHandle,
+    connector: connector::Handle,
+    mode: Option<DrmMode>,
+}
+
+// Adds the properties of a modeset to an atomic request. Enabled crtcs get a blank buffer on
+// their primary plane, a lot of drivers refuse active crtcs without one
+fn add_modeset(
+    device: &BackendData,
+    modeset: &Modeset,
+    request: &mut AtomicModeReq,
+    buffers: &mut Vec<BufferObject<()>>,
+    framebuffers: &mut Vec<framebuffer::Handle>,
+    blobs: &mut Vec<u64>,
+) -> Result<(), OutputError> {
+    use property::Value;
+
+    let drm = &device.drm;
+    let crtc = modeset.crtc;
+    let plane = primary_plane(drm, crtc)
+        .ok_or_else(|| OutputError::Planes(format!("no primary plane for {:?}", crtc)))?;
+
+    let (connector_properties, crtc_properties, plane_properties) = match modeset.mode {
+        Some(mode) => {
+            let (width, height) = mode.size();
+            let buffer = device
+                .gbm
+                .create_buffer_object::<()>(
+                    width as u32,
+                    height as u32,
+                    Fourcc::Xrgb8888,
+                    GbmBufferFlags::SCANOUT,
+                )
+                .map_err(|err| OutputError::Surface(err.to_string()))?;
+            let framebuffer = drm
+                .add_framebuffer(&buffer, 24, 32)
+                .map_err(|err| OutputError::Surface(err.to_string()))?;
+            buffers.push(buffer);
+            framebuffers.push(framebuffer);
+            let blob = drm
+                .create_property_blob(&mode)
+                .map_err(|err| OutputError::Surface(err.to_string()))?;
+            if let Value::Blob(id) = blob {
+                blobs.push(id);
+            }
+
+            let (width, height) = (width as u64, height as u64);
+            (
+                vec![("CRTC_ID", Value::CRTC(Some(crtc)))],
+                vec![("MODE_ID", blob), ("ACTIVE", Value::Boolean(true))],
+                vec![
+                    ("FB_ID", Value::Framebuffer(Some(framebuffer))),
+                    ("CRTC_ID", Value::CRTC(Some(crtc))),
+                    // the source rectangle is in 16.16 fixed point
+                    ("SRC_X", Value::UnsignedRange(0)),
+                    ("SRC_Y", Value::UnsignedRange(0)),
+                    ("SRC_W", Value::UnsignedRange(width << 16)),
+                    ("SRC_H", Value::UnsignedRange(height << 16)),
+                    ("CRTC_X", Value::SignedRange(0)),
+                    ("CRTC_Y", Value::SignedRange(0)),
+                    ("CRTC_W", Value::UnsignedRange(width)),
+                    ("CRTC_H", Value::UnsignedRange(height)),
+                ],
+            )
+        }
+        None => (
+            vec![("CRTC_ID", Value::CRTC(None))],
+            vec![
+                ("ACTIVE", Value::Boolean(false)),
+                ("MODE_ID", Value::Blob(0)),
+            ],
+            vec![
+                ("FB_ID", Value::Framebuffer(None)),
+                ("CRTC_ID", Value::CRTC(None)),
+            ],
+        ),
+    };
+
+    for (name, value) in connector_properties {
+        add_property(drm, request, modeset.connector, name, value)?;
+    }
+    for (name, value) in crtc_properties {
+        add_property(drm, request, crtc, name, value)?;
+    }
+    for (name, value) in plane_properties {
+        add_property(drm, request, plane, name, value)?;
+    }
+    Ok(())
+}
+
+// Adds a property to an atomic request by name
+fn add_property<H: ResourceHandle + Copy + std::fmt::Debug>(
+    drm: &DrmDevice,
+    request: &mut AtomicModeReq,
+    handle: H,
+    name: &str,
+    value: property::Value<'static>,
+) -> Result<(), OutputError> {
+    let (property, _) = drm_property(drm, handle, name)
+        .ok_or_else(|| OutputError::Rejected(format!("{:?} has no {} property", handle, name)))?;
+    request.add_property(handle, property, value);
+    Ok(())
+}
+
+// The primary plane that can be used with a crtc
+fn primary_plane(drm: &DrmDevice, crtc: CrtcHandle) -> Option<plane::Handle> {
+    let resources = drm.resource_handles().ok()?;
+    let planes = drm.plane_handles().ok()?;
+    planes.planes().iter().copied().find(|plane| {
+        let usable = drm.get_plane(*plane).map_or(false, |info| {
+            resources
+                .filter_crtcs(info.possible_crtcs())
+                .contains(&crtc)
+        });
+        // the type property is 1 for primary planes
+        usable && drm_property(drm, *plane, "type").map_or(false, |(_, value)| value == 1)
+    })
+}
+
+// Errors of drm calls that may work when tried again later
+fn is_transient(err: &std::io::Error) -> bool {
+    matches!(
+        err.raw_os_error().map(Errno::from_i32),
+        Some(Errno::EBUSY | Errno::EAGAIN | Errno::EINTR)
+    )
+}
+
+// Whether a window covers the whole output, which is what fullscreen-only vrr waits for
+fn has_fullscreen_window(space: &Space<Window>, output: &Output) -> bool {
+    let output_geometry = match space.output_geometry(output) {
+        Some(geometry) => geometry,
+        None => return false,
+    };
+    space.elements().any(|window| {
+        space
+            .element_geometry(window)
+            .map_or(false, |geometry| geometry.contains_rect(output_geometry))
+    })
+}
+
+// Applies the parts of the output config that don't require a new drm surface
+fn apply_output_state(space: &mut Space<Window>, output: &Output, config: &OutputConfig) {
+    let position = config
+        .position
+        .map(|position| (position.x, position.y).into())
+        .unwrap_or_else(|| output.current_location());
+    output.change_current_state(
+        None,
+        Some(config.transform.into()),
+        Some(config.scale()),
+        Some(position),
+    );
+    space.map_output(output, position);
+}
+
 fn initial_render(surface: &mut SurfaceData, renderer: &mut UdevRenderer<'_, '_>) {
     surface
         .compositor
@@ -917,4 +1970,7 @@ fn initial_render(surface: &mut SurfaceData, renderer: &mut UdevRenderer<'_, '_>
         .expect("Unable to render");
     surface.compositor.queue_frame(None).unwrap();
     surface.compositor.reset_buffers();
+    // The real first frame follows the vblank of this one
+    surface.frame_pending = true;
+    surface.needs_render = true;
 }
@@ -0,0 +1,146 @@
+use std::{collections::VecDeque, time::Duration};
+
+// How many recent render durations the prediction is based on
+const RENDER_HISTORY: usize = 16;
+// Headroom on top of the slowest recent render, for the page flip itself and timer jitter
+const RENDER_SLACK: Duration = Duration::from_millis(1);
+
+// Predicts the next vblank of an output from its presentation timestamps, so repaints can start
+// as late as possible while still making it in time
+pub struct FrameClock {
+    refresh_interval: Option<Duration>,
+    last_presentation: Option<Duration>,
+    render_durations: VecDeque<Duration>,
+    vrr: bool,
+}
+
+impl FrameClock {
+    // `refresh` is in mHz, like the refresh rate of smithay's output modes
+    pub fn new(refresh: i32) -> Self {
+        Self {
+            refresh_interval: (refresh > 0)
+                .then(|| Duration::from_nanos(1_000_000_000_000 / refresh as u64)),
+            last_presentation: None,
+            render_durations: VecDeque::with_capacity(RENDER_HISTORY),
+            vrr: false,
+        }
+    }
+
+    // With vrr the display waits for us, so there is no vblank to aim for
+    pub fn set_vrr(&mut self, vrr: bool) {
+        self.vrr = vrr;
+    }
+
+    // Records the monotonic time a frame was presented at
+    pub fn presented(&mut self, time: Duration) {
+        self.last_presentation = Some(time);
+    }
+
+    // Records how long a frame took from the start of the repaint until it was queued
+    pub fn rendered(&mut self, duration: Duration) {
+        if self.render_durations.len() == RENDER_HISTORY {
+            self.render_durations.pop_front();
+        }
+        self.render_durations.push_back(duration);
+    }
+
+    // The first vblank after `now`, extrapolated from the last presentation
+    pub fn next_presentation(&self, now: Duration) -> Option<Duration> {
+        let interval = self.refresh_interval?;
+        let last = self.last_presentation?;
+        if now < last {
+            return Some(last + interval);
+        }
+        let frames = (now - last).as_nanos() / interval.as_nanos() + 1;
+        Some(last + interval * frames as u32)
+    }
+
+    // How long to wait from `now` before repainting, so the frame is queued just before the
+    // next vblank. Without enough data to predict anything the repaint happens right away
+    pub fn repaint_delay(&self, now: Duration) -> Duration {
+        if self.vrr {
+            return Duration::ZERO;
+        }
+        let next_presentation = match self.next_presentation(now) {
+            Some(next_presentation) => next_presentation,
+            None => return Duration::ZERO,
+        };
+        let render_duration = match self.render_durations.iter().max() {
+            Some(render_duration) => *render_duration + RENDER_SLACK,
+            None => return Duration::ZERO,
+        };
+        next_presentation
+            .saturating_sub(render_duration)
+            .saturating_sub(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn next_presentation_is_extrapolated_from_the_last_one() {
+        // 100 Hz, so a vblank every 10 ms
+        let mut clock = FrameClock::new(100_000);
+        assert_eq!(clock.next_presentation(ms(5)), None);
+
+        clock.presented(ms(100));
+        assert_eq!(clock.next_presentation(ms(90)), Some(ms(110)));
+        assert_eq!(clock.next_presentation(ms(100)), Some(ms(110)));
+        assert_eq!(clock.next_presentation(ms(104)), Some(ms(110)));
+        assert_eq!(clock.next_presentation(ms(110)), Some(ms(120)));
+        assert_eq!(clock.next_presentation(ms(135)), Some(ms(140)));
+    }
+
+    #[test]
+    fn outputs_without_a_refresh_rate_have_no_vblank() {
+        let mut clock = FrameClock::new(0);
+        clock.presented(ms(100));
+        clock.rendered(ms(2));
+        assert_eq!(clock.next_presentation(ms(105)), None);
+        assert_eq!(clock.repaint_delay(ms(105)), Duration::ZERO);
+    }
+
+    #[test]
+    fn repaints_start_as_late_as_the_slowest_render_allows() {
+        let mut clock = FrameClock::new(100_000);
+        clock.presented(ms(100));
+        // nothing rendered yet, so there's nothing to go on
+        assert_eq!(clock.repaint_delay(ms(101)), Duration::ZERO);
+
+        clock.rendered(ms(2));
+        clock.rendered(ms(4));
+        clock.rendered(ms(3));
+        // the next vblank is at 110, the slowest render took 4 ms plus the slack
+        assert_eq!(clock.repaint_delay(ms(101)), ms(4));
+        // too late to wait for this one
+        assert_eq!(clock.repaint_delay(ms(107)), Duration::ZERO);
+    }
+
+    #[test]
+    fn old_render_durations_are_forgotten() {
+        let mut clock = FrameClock::new(100_000);
+        clock.presented(ms(100));
+        clock.rendered(ms(8));
+        for _ in 0..RENDER_HISTORY {
+            clock.rendered(ms(1));
+        }
+        assert_eq!(clock.repaint_delay(ms(100)), ms(8));
+    }
+
+    #[test]
+    fn vrr_repaints_right_away() {
+        let mut clock = FrameClock::new(100_000);
+        clock.presented(ms(100));
+        clock.rendered(ms(1));
+        clock.set_vrr(true);
+        assert_eq!(clock.repaint_delay(ms(101)), Duration::ZERO);
+        clock.set_vrr(false);
+        assert_eq!(clock.repaint_delay(ms(101)), ms(7));
+    }
+}
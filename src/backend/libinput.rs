@@ -0,0 +1,205 @@
+use smithay::reexports::input::{
+    AccelProfile, ClickMethod, Device, DeviceCapability, DeviceConfigResult, Led, ScrollMethod,
+};
+
+use super::UdevData;
+use crate::{
+    config::{InputAccelProfile, InputClickMethod, InputConfig, InputScrollMethod},
+    Corrosion,
+};
+
+impl Corrosion<UdevData> {
+    pub fn input_device_added(&mut self, device: &mut Device) {
+        tracing::info!("Input device added: {}", device.name());
+        self.configure_input_device(device);
+        if device.has_capability(DeviceCapability::Keyboard) {
+            device.led_update(self.backend_data.keyboard_leds);
+        }
+        self.backend_data.input_devices.push(device.clone());
+        self.update_seat_capabilities();
+    }
+
+    pub fn input_device_removed(&mut self, device: &Device) {
+        tracing::info!("Input device removed: {}", device.name());
+        self.backend_data
+            .input_devices
+            .retain(|known| known != device);
+        self.update_seat_capabilities();
+    }
+
+    // Virtual keyboards and pointers count as devices too
+    pub fn update_seat_capabilities(&mut self) {
+        let devices = &self.backend_data.input_devices;
+        let has = |capability| {
+            devices
+                .iter()
+                .any(|device| device.has_capability(capability))
+        };
+        let (keyboard, pointer, touch) = (
+            has(DeviceCapability::Keyboard) || !self.backend_data.virtual_keyboard_state.is_empty(),
+            has(DeviceCapability::Pointer) || !self.backend_data.virtual_pointer_state.is_empty(),
+            has(DeviceCapability::Touch),
+        );
+        self.set_seat_capabilities(keyboard, pointer, touch);
+    }
+
+    // Caps and num lock belong to the seat, so every keyboard shows them, not just the one they
+    // were pressed on
+    pub fn update_keyboard_leds(&mut self) {
+        let modifiers = match self.keyboard_modifiers() {
+            Some(modifiers) => modifiers,
+            None => return,
+        };
+        let mut leds = Led::empty();
+        if modifiers.caps_lock {
+            leds |= Led::CAPSLOCK;
+        }
+        if modifiers.num_lock {
+            leds |= Led::NUMLOCK;
+        }
+        if leds == self.backend_data.keyboard_leds {
+            return;
+        }
+
+        self.backend_data.keyboard_leds = leds;
+        for device in &mut self.backend_data.input_devices {
+            if device.has_capability(DeviceCapability::Keyboard) {
+                device.led_update(leds);
+            }
+        }
+    }
+
+    // Applies the [input] sections again, e.g. after a config reload
+    pub fn apply_input_config(&mut self) {
+        for mut device in self.backend_data.input_devices.clone() {
+            self.configure_input_device(&mut device);
+        }
+    }
+
+    fn configure_input_device(&self, device: &mut Device) {
+        // touchpads are the pointer devices that can tap
+        let kind = if device.config_tap_finger_count() > 0 {
+            Some("touchpad")
+        } else if device.has_capability(DeviceCapability::Pointer) {
+            Some("mouse")
+        } else {
+            None
+        };
+        let config = self.config.input_device_config(device.name(), kind);
+        apply_input_config(&config, device);
+    }
+}
+
+// Sets everything the config has for the device, and puts everything it leaves out back to the
+// libinput default, so removing a setting works on reload
+fn apply_input_config(config: &InputConfig, device: &mut Device) {
+    let name = device.name().to_string();
+
+    let default = device.config_accel_default_profile();
+    let profile = config.accel_profile.map(|profile| match profile {
+        InputAccelProfile::Flat => AccelProfile::Flat,
+        InputAccelProfile::Adaptive => AccelProfile::Adaptive,
+    });
+    set(&name, "accel_profile", profile, default, |profile| {
+        device.config_accel_set_profile(profile)
+    });
+
+    let default = device.config_accel_default_speed();
+    set(
+        &name,
+        "accel_speed",
+        config.accel_speed,
+        Some(default),
+        |speed| device.config_accel_set_speed(speed),
+    );
+
+    let default = device.config_scroll_default_natural_scroll_enabled();
+    set(
+        &name,
+        "natural_scroll",
+        config.natural_scroll,
+        Some(default),
+        |enabled| device.config_scroll_set_natural_scroll_enabled(enabled),
+    );
+
+    let default = device.config_tap_default_enabled();
+    set(&name, "tap", config.tap, Some(default), |enabled| {
+        device.config_tap_set_enabled(enabled)
+    });
+
+    let default = device.config_tap_default_drag_lock_enabled();
+    set(
+        &name,
+        "drag_lock",
+        config.drag_lock,
+        Some(default),
+        |enabled| device.config_tap_set_drag_lock_enabled(enabled),
+    );
+
+    let default = device.config_dwt_default_enabled();
+    set(
+        &name,
+        "disable_while_typing",
+        config.disable_while_typing,
+        Some(default),
+        |enabled| device.config_dwt_set_enabled(enabled),
+    );
+
+    let default = device.config_scroll_default_method();
+    let method = config.scroll_method.map(|method| match method {
+        InputScrollMethod::None => ScrollMethod::NoScroll,
+        InputScrollMethod::TwoFinger => ScrollMethod::TwoFinger,
+        InputScrollMethod::Edge => ScrollMethod::Edge,
+        InputScrollMethod::OnButtonDown => ScrollMethod::OnButtonDown,
+    });
+    set(&name, "scroll_method", method, default, |method| {
+        device.config_scroll_set_method(method)
+    });
+
+    let default = device.config_left_handed_default();
+    set(
+        &name,
+        "left_handed",
+        config.left_handed,
+        Some(default),
+        |enabled| device.config_left_handed_set(enabled),
+    );
+
+    let default = device.config_middle_emulation_default_enabled();
+    set(
+        &name,
+        "middle_emulation",
+        config.middle_emulation,
+        Some(default),
+        |enabled| device.config_middle_emulation_set_enabled(enabled),
+    );
+
+    let default = device.config_click_default_method();
+    let method = config.click_method.map(|method| match method {
+        InputClickMethod::ButtonAreas => ClickMethod::ButtonAreas,
+        InputClickMethod::Clickfinger => ClickMethod::Clickfinger,
+    });
+    set(&name, "click_method", method, default, |method| {
+        device.config_click_set_method(method)
+    });
+}
+
+// Sets the configured value, or else the default. Most devices don't support most settings, so
+// only a configured value is worth a warning
+fn set<T: Copy>(
+    device: &str,
+    setting: &str,
+    configured: Option<T>,
+    default: Option<T>,
+    apply: impl FnOnce(T) -> DeviceConfigResult,
+) {
+    let value = match configured.or(default) {
+        Some(value) => value,
+        None => return,
+    };
+    if let Err(err) = apply(value) {
+        if configured.is_some() {
+            tracing::warn!("{}: unable to set {}: {:?}", device, setting, err);
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
 
 #[cfg(feature = "egl")]
 use smithay::backend::renderer::ImportEgl;
@@ -11,6 +14,7 @@ use smithay::{
             Allocator,
         },
         drm::{DrmNode, NodeType},
+        input::InputEvent,
         libinput::{LibinputInputBackend, LibinputSessionInterface},
         renderer::{
             element::texture::TextureBuffer,
@@ -26,8 +30,8 @@ use smithay::{
     delegate_dmabuf,
     reexports::{
         ash::vk::ExtPhysicalDeviceDrmFn,
-        calloop::{EventLoop, LoopSignal},
-        input::Libinput,
+        calloop::{EventLoop, LoopSignal, RegistrationToken},
+        input::{self, Libinput},
         wayland_protocols::wp::linux_dmabuf::zv1::server::zwp_linux_dmabuf_feedback_v1,
         wayland_server::{protocol::wl_surface::WlSurface, Display},
     },
@@ -38,10 +42,28 @@ use smithay::{
 };
 
 use self::drm::{BackendData, SurfaceComposition, UdevOutputId};
-use crate::{cursor::Cursor, drawing::PointerElement, state::Backend, CalloopData, Corrosion};
+use crate::{
+    cursor::Cursor,
+    drawing::PointerElement,
+    handlers::keybindings::ScreenshotTarget,
+    protocols::{
+        output_management::OutputManagementState,
+        output_power_management::OutputPowerManagementState, screencopy::ScreencopyState,
+        virtual_keyboard::VirtualKeyboardState, virtual_pointer::VirtualPointerState,
+    },
+    state::Backend,
+    CalloopData, Corrosion, CorrosionConfig,
+};
 
 mod drm;
+mod frame_clock;
+mod libinput;
+mod output_management;
+mod output_power;
+mod screencopy;
+mod screenshot;
 mod utils;
+mod virtual_input;
 
 pub struct UdevData {
     pub loop_signal: LoopSignal,
@@ -52,8 +74,24 @@ pub struct UdevData {
     gpu_manager: GpuManager<GbmGlesBackend<GlesRenderer>>,
     backends: HashMap<DrmNode, BackendData>,
     pointer_element: PointerElement<MultiTexture>,
+    // the cursor of a tablet tool, drawn separately from the pointer
+    tablet_pointer_element: PointerElement<MultiTexture>,
     cursor_image: Cursor,
     cursor_images: Vec<(xcursor::parser::Image, TextureBuffer<MultiTexture>)>,
+    urgent_border: Option<TextureBuffer<MultiTexture>>,
+    output_management_state: OutputManagementState,
+    output_power_state: OutputPowerManagementState,
+    screencopy_state: ScreencopyState,
+    virtual_keyboard_state: VirtualKeyboardState,
+    virtual_pointer_state: VirtualPointerState,
+    last_activity: Instant,
+    idle_timer: Option<RegistrationToken>,
+    // every libinput device, so config reloads can reach them
+    input_devices: Vec<input::Device>,
+    // caps and num lock, as last shown on the keyboards
+    keyboard_leds: input::Led,
+    // the outputs were turned off by the idle timeout, and get woken up by input
+    idle_powered_off: bool,
 }
 
 impl DmabufHandler for Corrosion<UdevData> {
@@ -104,9 +142,34 @@ impl Backend for UdevData {
     fn seat_name(&self) -> String {
         self.session.seat()
     }
+
+    fn apply_config(state: &mut Corrosion<Self>) {
+        state.apply_output_config();
+        state.init_idle_timer();
+        state.apply_input_config();
+    }
+
+    fn schedule_render(state: &mut Corrosion<Self>, output: &smithay::output::Output) {
+        if let Some(id) = output.user_data().get::<UdevOutputId>() {
+            let (node, crtc) = (id.device_id, id.crtc);
+            state.schedule_repaint(node, crtc);
+        }
+    }
+
+    fn screenshot(state: &mut Corrosion<Self>, target: ScreenshotTarget) -> Result<(), String> {
+        state.take_screenshot(target)
+    }
+
+    fn set_output_power(
+        state: &mut Corrosion<Self>,
+        output: Option<&str>,
+        on: bool,
+    ) -> Result<(), String> {
+        state.set_outputs_power(output, on)
+    }
 }
 
-pub fn initialize_backend() {
+pub fn initialize_backend(config: CorrosionConfig) {
     let mut event_loop = EventLoop::try_new().expect("Unable to initialize event loop");
     let (session, mut _notifier) = match LibSeatSession::new() {
         Ok((session, notifier)) => (session, notifier),
@@ -149,9 +212,25 @@ pub fn initialize_backend() {
         backends: HashMap::new(),
         cursor_image: Cursor::load(),
         cursor_images: Vec::new(),
+        urgent_border: None,
         pointer_element: PointerElement::default(),
+        tablet_pointer_element: PointerElement::default(),
+        output_management_state: OutputManagementState::new::<Corrosion<UdevData>>(
+            &display.handle(),
+        ),
+        output_power_state: OutputPowerManagementState::new::<Corrosion<UdevData>>(
+            &display.handle(),
+        ),
+        screencopy_state: ScreencopyState::new::<Corrosion<UdevData>>(&display.handle()),
+        virtual_keyboard_state: VirtualKeyboardState::new::<Corrosion<UdevData>>(&display.handle()),
+        virtual_pointer_state: VirtualPointerState::new::<Corrosion<UdevData>>(&display.handle()),
+        last_activity: Instant::now(),
+        idle_timer: None,
+        input_devices: Vec::new(),
+        keyboard_leds: input::Led::empty(),
+        idle_powered_off: false,
     };
-    let mut state = Corrosion::new(event_loop.handle(), &mut display, data);
+    let mut state = Corrosion::new(event_loop.handle(), &mut display, data, config);
 
     let backend = match UdevBackend::new(&state.seat_name) {
         Ok(backend) => backend,
@@ -165,6 +244,9 @@ pub fn initialize_backend() {
         state.device_added(DrmNode::from_dev_id(dev).unwrap(), &path);
     }
 
+    // Turns the outputs off after the configured time without input
+    state.init_idle_timer();
+
     state.shm_state.update_formats(
         state
             .backend_data
@@ -210,7 +292,12 @@ pub fn initialize_backend() {
 
     state
         .handle
-        .insert_source(libinput_backend, move |event, _, data| {
+        .insert_source(libinput_backend, move |mut event, _, data| {
+            match &mut event {
+                InputEvent::DeviceAdded { device } => data.state.input_device_added(device),
+                InputEvent::DeviceRemoved { device } => data.state.input_device_removed(device),
+                _ => {}
+            }
             data.state.process_input_event(event);
         })
         .unwrap();
@@ -294,15 +381,12 @@ pub fn initialize_backend() {
     let mut calloop_data = CalloopData { state, display };
 
     event_loop
-        .run(
-            std::time::Duration::from_millis(16),
-            &mut calloop_data,
-            |data| {
-                data.state.space.refresh();
-                data.state.popup_manager.cleanup();
-                data.display.flush_clients().unwrap();
-            },
-        )
+        .run(None, &mut calloop_data, |data| {
+            data.state.space.refresh();
+            data.state.popup_manager.cleanup();
+            data.state.take_over_clipboard();
+            data.display.flush_clients().unwrap();
+        })
         .unwrap();
 }
 
@@ -0,0 +1,196 @@
+use smithay::{
+    output::Mode as WlMode,
+    reexports::drm::control::{connector, ModeTypeFlags},
+    utils::Transform,
+};
+
+use super::{
+    drm::{connector_make_model, connector_name, find_mode, OutputError},
+    UdevData,
+};
+use crate::{
+    config::{OutputConfig, OutputMode, OutputPosition},
+    delegate_output_management,
+    protocols::output_management::{
+        HeadConfiguration, HeadMode, HeadState, OutputManagementHandler, OutputManagementState,
+        RequestedMode,
+    },
+    Corrosion,
+};
+
+impl OutputManagementHandler for Corrosion<UdevData> {
+    fn output_management_state(&mut self) -> &mut OutputManagementState {
+        &mut self.backend_data.output_management_state
+    }
+
+    fn apply_output_configuration(
+        &mut self,
+        configuration: Vec<HeadConfiguration>,
+        test_only: bool,
+    ) -> bool {
+        let connectors = self.connectors();
+
+        let mut changes = Vec::new();
+        for head in configuration {
+            let (node, connector, crtc) = match connectors
+                .iter()
+                .find(|(_, connector, _)| connector_name(connector) == head.name)
+            {
+                Some(found) => found.clone(),
+                None => {
+                    tracing::warn!("Output configuration for unknown head {}", head.name);
+                    return false;
+                }
+            };
+
+            let current = self.current_output_config(node, crtc);
+            let output_config = match requested_output_config(&connector, current, &head) {
+                Ok(output_config) => output_config,
+                Err(err) => {
+                    tracing::warn!("Rejecting output configuration: {}", err);
+                    return false;
+                }
+            };
+            changes.push((node, crtc, connector, output_config));
+        }
+
+        // a test goes through the same checks as the real thing, short of applying it
+        if test_only {
+            return match self.check_output_configs(&changes) {
+                Ok(()) => true,
+                Err(err) => {
+                    tracing::info!("Output configuration test failed: {}", err);
+                    false
+                }
+            };
+        }
+
+        let result = self.reconfigure_outputs(changes);
+        self.update_output_management();
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::error!("Unable to apply output configuration: {}", err);
+                false
+            }
+        }
+    }
+}
+
+delegate_output_management!(Corrosion<UdevData>);
+
+impl Corrosion<UdevData> {
+    // Tells output management clients about the current state of every connector
+    pub fn update_output_management(&mut self) {
+        let heads = self.output_heads();
+        self.backend_data
+            .output_management_state
+            .update::<Self>(heads);
+    }
+
+    fn output_heads(&self) -> Vec<HeadState> {
+        self.connectors()
+            .into_iter()
+            .filter_map(|(node, connector, crtc)| {
+                let device = self.backend_data.backends.get(&node)?;
+                let name = connector_name(&connector);
+                let (make, model) = connector_make_model(&device.drm, &connector);
+                let current_mode = self.crtc_state(node, crtc).map(|(mode, _)| mode);
+                let output = self.output_for_crtc(node, crtc);
+
+                let modes = connector
+                    .modes()
+                    .iter()
+                    .map(|mode| {
+                        let (width, height) = mode.size();
+                        HeadMode {
+                            size: (width as i32, height as i32).into(),
+                            refresh: WlMode::from(*mode).refresh,
+                            preferred: mode.mode_type().contains(ModeTypeFlags::PREFERRED),
+                        }
+                    })
+                    .collect();
+
+                Some(HeadState {
+                    description: format!("{} {} ({})", make, model, name),
+                    name,
+                    make,
+                    model,
+                    physical_size: connector
+                        .size()
+                        .map(|(w, h)| (w as i32, h as i32))
+                        .unwrap_or((0, 0)),
+                    modes,
+                    current_mode: current_mode
+                        .and_then(|current| connector.modes().iter().position(|m| *m == current)),
+                    enabled: output.is_some(),
+                    position: output
+                        .as_ref()
+                        .map(|output| output.current_location())
+                        .unwrap_or_default(),
+                    transform: output
+                        .as_ref()
+                        .map(|output| output.current_transform())
+                        .unwrap_or(Transform::Normal),
+                    scale: output
+                        .as_ref()
+                        .map(|output| output.current_scale().fractional_scale())
+                        .unwrap_or(1.0),
+                })
+            })
+            .collect()
+    }
+}
+
+// Turns a client request into an output config on top of the current state
+fn requested_output_config(
+    connector: &connector::Info,
+    current: OutputConfig,
+    head: &HeadConfiguration,
+) -> Result<OutputConfig, OutputError> {
+    if !head.enabled {
+        return Ok(OutputConfig {
+            enabled: false,
+            ..current
+        });
+    }
+
+    let mode = match head.mode {
+        Some(RequestedMode::Advertised(mode)) => Some(OutputMode {
+            width: mode.size.w,
+            height: mode.size.h,
+            refresh: Some(mode.refresh as f64 / 1000.0),
+        }),
+        Some(RequestedMode::Custom { size, refresh }) => {
+            let mode = OutputMode {
+                width: size.w,
+                height: size.h,
+                refresh: (refresh > 0).then(|| refresh as f64 / 1000.0),
+            };
+            // we can't create modes, but a custom mode matching an existing one is fine
+            let supported = find_mode(connector, &mode).map_or(false, |found| {
+                refresh == 0 || (WlMode::from(found).refresh - refresh).abs() < 500
+            });
+            if !supported {
+                return Err(OutputError::UnsupportedMode(String::from(mode)));
+            }
+            Some(mode)
+        }
+        None => current.mode,
+    };
+
+    Ok(OutputConfig {
+        enabled: true,
+        mode,
+        position: head
+            .position
+            .map(|position| OutputPosition {
+                x: position.x,
+                y: position.y,
+            })
+            .or(current.position),
+        scale: head.scale.or(current.scale),
+        transform: head.transform.map(Into::into).unwrap_or(current.transform),
+        vrr: current.vrr,
+    })
+}
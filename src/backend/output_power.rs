@@ -0,0 +1,120 @@
+use std::time::Instant;
+
+use smithay::{
+    output::Output,
+    reexports::calloop::timer::{TimeoutAction, Timer},
+};
+
+use super::{
+    drm::{connector_name, UdevOutputId},
+    UdevData,
+};
+use crate::{
+    delegate_output_power_management,
+    protocols::output_power_management::{
+        OutputPowerManagementHandler, OutputPowerManagementState,
+    },
+    Corrosion,
+};
+
+impl OutputPowerManagementHandler for Corrosion<UdevData> {
+    fn output_power_management_state(&mut self) -> &mut OutputPowerManagementState {
+        &mut self.backend_data.output_power_state
+    }
+
+    fn output_power(&mut self, output: &Output) -> Option<bool> {
+        let id = output.user_data().get::<UdevOutputId>()?;
+        self.crtc_power(id.device_id, id.crtc)
+    }
+
+    fn set_output_power(&mut self, output: &Output, on: bool) -> bool {
+        let (node, crtc) = match output.user_data().get::<UdevOutputId>() {
+            Some(id) => (id.device_id, id.crtc),
+            None => return false,
+        };
+        match self.set_crtc_power(node, crtc, on) {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::error!("Unable to set the power of {}: {}", output.name(), err);
+                false
+            }
+        }
+    }
+}
+
+delegate_output_power_management!(Corrosion<UdevData>);
+
+impl Corrosion<UdevData> {
+    // Turns every enabled output, or only the one with the given connector name, on or off
+    pub fn set_outputs_power(&mut self, name: Option<&str>, on: bool) -> Result<(), String> {
+        let mut found = false;
+        for (node, connector, crtc) in self.connectors() {
+            if name.map_or(false, |name| name != connector_name(&connector)) {
+                continue;
+            }
+            found = true;
+            // disabled outputs have nothing to turn on or off
+            if self.crtc_power(node, crtc).is_some() {
+                self.set_crtc_power(node, crtc, on)
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+
+        match name {
+            Some(name) if !found => Err(format!("no output named {}", name)),
+            _ => Ok(()),
+        }
+    }
+
+    // Called for every input event, wakes up outputs the idle timeout turned off and resumes
+    // idle notifications
+    pub fn notify_activity(&mut self) {
+        self.backend_data.last_activity = Instant::now();
+        self.idle_notifier_state.notify_activity();
+        // a running check timer picks up the new activity by itself
+        if self.idle_check_timer.is_none() {
+            self.schedule_idle_check();
+        }
+        if self.backend_data.idle_powered_off {
+            self.backend_data.idle_powered_off = false;
+            if let Err(err) = self.set_outputs_power(None, true) {
+                tracing::error!("Unable to wake up the outputs: {}", err);
+            }
+        }
+    }
+
+    // (Re)starts the idle timer with the [idle] dpms timeout, on startup and config reload
+    pub fn init_idle_timer(&mut self) {
+        if let Some(token) = self.backend_data.idle_timer.take() {
+            self.handle.remove(token);
+        }
+        let timeout = match self.config.get_idle().dpms_timeout() {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        // The timer is not restarted on input, it just checks how long ago the last input was
+        let token = self
+            .handle
+            .insert_source(Timer::from_duration(timeout), move |_, _, data| {
+                // a visible idle inhibitor counts as activity
+                if data.state.idle_inhibited() {
+                    data.state.backend_data.last_activity = Instant::now();
+                }
+                let idle = data.state.backend_data.last_activity.elapsed();
+                if idle < timeout {
+                    return TimeoutAction::ToDuration(timeout - idle);
+                }
+                if !data.state.backend_data.idle_powered_off {
+                    tracing::info!("Idle for {:?}, turning the outputs off", idle);
+                    data.state.backend_data.idle_powered_off = true;
+                    if let Err(err) = data.state.set_outputs_power(None, false) {
+                        tracing::error!("Unable to turn the outputs off: {}", err);
+                    }
+                }
+                TimeoutAction::ToDuration(timeout)
+            })
+            .expect("Unable to insert the idle timer into the event loop");
+        self.backend_data.idle_timer = Some(token);
+    }
+}
@@ -0,0 +1,99 @@
+use smithay::{
+    backend::{
+        allocator::dmabuf::Dmabuf,
+        renderer::{
+            damage::OutputDamageTracker, element::RenderElement, gles::GlesTexture, Bind,
+            ExportMem, Offscreen, Renderer,
+        },
+    },
+    wayland::{dmabuf, shm},
+};
+
+use super::{drm::UdevOutputId, UdevData};
+use crate::{
+    delegate_screencopy,
+    protocols::screencopy::{Screencopy, ScreencopyHandler, ScreencopyState},
+    Corrosion,
+};
+
+impl ScreencopyHandler for Corrosion<UdevData> {
+    fn screencopy_state(&mut self) -> &mut ScreencopyState {
+        &mut self.backend_data.screencopy_state
+    }
+
+    fn frame(&mut self, frame: Screencopy) {
+        let (node, crtc) = match frame.output().user_data().get::<UdevOutputId>() {
+            Some(id) => (id.device_id, id.crtc),
+            None => return,
+        };
+        // copies are done by the next repaint of the output, the frame fails if it has none
+        let with_damage = frame.with_damage();
+        if !self.queue_screencopy(node, crtc, frame) {
+            return;
+        }
+        // without damage the copy is done right away, with damage it waits for the output to
+        // change
+        if !with_damage {
+            self.schedule_repaint(node, crtc);
+        }
+    }
+}
+
+delegate_screencopy!(Corrosion<UdevData>);
+
+// Renders `elements` into the buffer of `frame`, like they would be shown on its output.
+// Dmabufs are rendered to directly, shm buffers get a copy of the captured region
+pub fn render_screencopy<R, E>(
+    renderer: &mut R,
+    frame: &Screencopy,
+    elements: &[E],
+    clear_color: [f32; 4],
+) -> Result<(), String>
+where
+    R: Renderer + Bind<Dmabuf> + Bind<GlesTexture> + Offscreen<GlesTexture> + ExportMem,
+    <R as Renderer>::TextureId: 'static,
+    E: RenderElement<R>,
+{
+    let mut damage_tracker = OutputDamageTracker::from_output(frame.output());
+
+    if let Ok(dmabuf) = dmabuf::get_dmabuf(frame.buffer()) {
+        renderer.bind(dmabuf).map_err(|err| err.to_string())?;
+        damage_tracker
+            .render_output(renderer, 0, elements, clear_color)
+            .map_err(|err| err.to_string())?;
+        return Ok(());
+    }
+
+    let mode = frame
+        .output()
+        .current_mode()
+        .ok_or_else(|| String::from("the output has no mode"))?;
+    let texture: GlesTexture = renderer
+        .create_buffer((mode.size.w, mode.size.h).into())
+        .map_err(|err| err.to_string())?;
+    renderer.bind(texture).map_err(|err| err.to_string())?;
+    damage_tracker
+        .render_output(renderer, 0, elements, clear_color)
+        .map_err(|err| err.to_string())?;
+
+    let region = frame.region();
+    let mapping = renderer
+        .copy_framebuffer(region)
+        .map_err(|err| err.to_string())?;
+    let pixels = renderer
+        .map_texture(&mapping)
+        .map_err(|err| err.to_string())?;
+
+    // the mapping is tightly packed, the shm buffer may have a larger stride
+    let row = region.size.w as usize * 4;
+    shm::with_buffer_contents_mut(frame.buffer(), |ptr, len, data| {
+        let buffer = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+        for (y, src) in pixels.chunks_exact(row).enumerate() {
+            let start = data.offset as usize + y * data.stride as usize;
+            if let Some(dst) = buffer.get_mut(start..start + row) {
+                dst.copy_from_slice(src);
+            }
+        }
+    })
+    .map_err(|err| format!("{:?}", err))
+}
@@ -0,0 +1,284 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use image::{imageops, ImageOutputFormat, RgbaImage};
+use smithay::{
+    backend::renderer::{
+        damage::OutputDamageTracker,
+        element::{surface::WaylandSurfaceRenderElement, AsRenderElements, RenderElement},
+        gles::GlesTexture,
+        Bind, ExportMem, Offscreen, Renderer,
+    },
+    desktop::{space, Window},
+    output::Output,
+    reexports::calloop::channel,
+    utils::{Buffer, Logical, Physical, Point, Rectangle, Scale, Size, Transform},
+};
+
+use super::UdevData;
+use crate::{
+    handlers::{keybindings::ScreenshotTarget, selection::ServerSelection},
+    Corrosion,
+};
+
+const PNG_MIME_TYPE: &str = "image/png";
+
+impl Corrosion<UdevData> {
+    // Renders the target offscreen, encoding and saving the png happens on a worker thread since
+    // that takes a while for big outputs
+    pub fn take_screenshot(&mut self, target: ScreenshotTarget) -> Result<(), String> {
+        if self.is_locked() {
+            return Err(String::from("the session is locked"));
+        }
+
+        let image = match target {
+            ScreenshotTarget::Output => {
+                let output = self
+                    .space
+                    .output_under(self.pointer_location)
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| String::from("there is no output under the pointer"))?;
+                self.render_output_image(&output)?
+            }
+            ScreenshotTarget::Window => {
+                let focus = self
+                    .seat
+                    .get_keyboard()
+                    .and_then(|keyboard| keyboard.current_focus());
+                let window = self
+                    .space
+                    .elements()
+                    .find(|window| Some(window.toplevel().wl_surface()) == focus.as_ref())
+                    .cloned()
+                    .ok_or_else(|| String::from("no window is focused"))?;
+                self.render_window_image(&window)?
+            }
+            ScreenshotTarget::Region(region) => {
+                // regions spanning several outputs get cut off at the edge of the first one
+                let output = self
+                    .space
+                    .output_under(region.loc.to_f64())
+                    .next()
+                    .cloned()
+                    .ok_or_else(|| String::from("the region is not on any output"))?;
+                let geometry = self.space.output_geometry(&output).unwrap();
+                let scale = output.current_scale().fractional_scale();
+                let image = self.render_output_image(&output)?;
+
+                let mut local = region;
+                local.loc -= geometry.loc;
+                let local = local.to_f64().to_physical(scale).to_i32_round::<i32>();
+                let x = local.loc.x.max(0) as u32;
+                let y = local.loc.y.max(0) as u32;
+                let width = (local.size.w.max(0) as u32).min(image.width().saturating_sub(x));
+                let height = (local.size.h.max(0) as u32).min(image.height().saturating_sub(y));
+                if width == 0 || height == 0 {
+                    return Err(String::from("the region is empty"));
+                }
+                imageops::crop_imm(&image, x, y, width, height).to_image()
+            }
+        };
+
+        let screenshot_config = self.config.get_screenshot();
+        let directory = screenshot_config
+            .directory()
+            .ok_or_else(|| String::from("unable to expand the screenshot directory"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = directory.join(format!("screenshot-{}.png", timestamp(now)));
+
+        // the png comes back to be put on the clipboard once it's saved, the channel goes away
+        // with the worker
+        let (sender, receiver) = channel::channel();
+        if screenshot_config.clipboard {
+            self.handle
+                .insert_source(receiver, |event, _, data| {
+                    if let channel::Event::Msg(png) = event {
+                        let selection =
+                            ServerSelection::Data(vec![(String::from(PNG_MIME_TYPE), png)]);
+                        data.state.set_server_selection(Some(selection), false);
+                    }
+                })
+                .map_err(|err| err.error.to_string())?;
+        }
+        std::thread::spawn(move || match save_png(&image, &path) {
+            Ok(png) => {
+                tracing::info!("Saved screenshot to {}", path.display());
+                let _ = sender.send(Arc::new(png));
+            }
+            Err(err) => tracing::warn!("Unable to save screenshot: {}", err),
+        });
+        Ok(())
+    }
+
+    // The output like it's shown, but upright and without the cursor
+    fn render_output_image(&mut self, output: &Output) -> Result<RgbaImage, String> {
+        let geometry = self
+            .space
+            .output_geometry(output)
+            .ok_or_else(|| String::from("the output is not mapped"))?;
+        let scale = output.current_scale().fractional_scale();
+        let size = geometry.size.to_physical_precise_round(scale);
+
+        let primary_gpu = self.backend_data.primary_gpu;
+        let mut renderer = self
+            .backend_data
+            .gpu_manager
+            .single_renderer(&primary_gpu)
+            .map_err(|err| err.to_string())?;
+        let elements = space::space_render_elements(&mut renderer, [&self.space], output)
+            .map_err(|_| String::from("the output has no mode"))?;
+        render_image(&mut renderer, size, scale, &elements)
+    }
+
+    // Only the window's geometry, its shadows and popups are left out
+    fn render_window_image(&mut self, window: &Window) -> Result<RgbaImage, String> {
+        let scale = self
+            .space
+            .outputs_for_element(window)
+            .first()
+            .map_or(1.0, |output| output.current_scale().fractional_scale());
+        let geometry = window.geometry();
+        let size = geometry.size.to_physical_precise_round(scale);
+
+        let primary_gpu = self.backend_data.primary_gpu;
+        let mut renderer = self
+            .backend_data
+            .gpu_manager
+            .single_renderer(&primary_gpu)
+            .map_err(|err| err.to_string())?;
+        let elements: Vec<WaylandSurfaceRenderElement<_>> = window.render_elements(
+            &mut renderer,
+            Point::<i32, Logical>::from((-geometry.loc.x, -geometry.loc.y))
+                .to_physical_precise_round(scale),
+            Scale::from(scale),
+        );
+        render_image(&mut renderer, size, scale, &elements)
+    }
+}
+
+// Renders `elements` into an offscreen buffer of `size` and reads it back
+fn render_image<R, E>(
+    renderer: &mut R,
+    size: Size<i32, Physical>,
+    scale: f64,
+    elements: &[E],
+) -> Result<RgbaImage, String>
+where
+    R: Renderer + Bind<GlesTexture> + Offscreen<GlesTexture> + ExportMem,
+    <R as Renderer>::TextureId: 'static,
+    E: RenderElement<R>,
+{
+    let buffer_size = Size::<i32, Buffer>::from((size.w, size.h));
+    let texture: GlesTexture = renderer
+        .create_buffer(buffer_size)
+        .map_err(|err| err.to_string())?;
+    renderer.bind(texture).map_err(|err| err.to_string())?;
+
+    let mut damage_tracker = OutputDamageTracker::new(size, scale, Transform::Normal);
+    damage_tracker
+        .render_output(renderer, 0, elements, [0.0, 0.0, 0.0, 0.0])
+        .map_err(|err| err.to_string())?;
+
+    let mapping = renderer
+        .copy_framebuffer(Rectangle::from_loc_and_size((0, 0), buffer_size))
+        .map_err(|err| err.to_string())?;
+    let pixels = renderer
+        .map_texture(&mapping)
+        .map_err(|err| err.to_string())?;
+    RgbaImage::from_raw(size.w as u32, size.h as u32, pixels.to_vec())
+        .ok_or_else(|| String::from("the renderer returned a short buffer"))
+}
+
+// Encodes `image` and writes it to `path`, returns the png
+fn save_png(image: &RgbaImage, path: &Path) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+        .map_err(|err| err.to_string())?;
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory).map_err(|err| err.to_string())?;
+    }
+    fs::write(path, &png).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(png)
+}
+
+// YYYY-MM-DD_HH-MM-SS.mmm in UTC for `time` since the epoch, so screenshots sort by name and
+// taking two in a row doesn't overwrite the first
+fn timestamp(time: Duration) -> String {
+    let secs = time.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // days since the epoch to a civil date, from Howard Hinnant's date algorithms
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        time.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(timestamp(Duration::ZERO), "1970-01-01_00-00-00.000");
+        assert_eq!(
+            timestamp(Duration::from_secs(1_700_000_000)),
+            "2023-11-14_22-13-20.000"
+        );
+        assert_eq!(
+            timestamp(Duration::from_millis(1_735_689_599_999)),
+            "2024-12-31_23-59-59.999"
+        );
+    }
+
+    #[test]
+    fn leap_days_are_counted() {
+        assert_eq!(
+            timestamp(Duration::from_secs(951_782_400)),
+            "2000-02-29_00-00-00.000"
+        );
+        assert_eq!(
+            timestamp(Duration::from_secs(951_868_800)),
+            "2000-03-01_00-00-00.000"
+        );
+        // 2100 is not a leap year
+        assert_eq!(
+            timestamp(Duration::from_secs(4_107_542_400)),
+            "2100-03-01_00-00-00.000"
+        );
+    }
+
+    #[test]
+    fn screenshots_taken_in_the_same_second_get_different_names() {
+        let second = Duration::from_secs(1_700_000_000);
+        assert_ne!(
+            timestamp(second + Duration::from_millis(1)),
+            timestamp(second + Duration::from_millis(2))
+        );
+    }
+}
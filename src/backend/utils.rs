@@ -1,5 +1,8 @@
 use smithay::{
-    backend::renderer::{ImportAll, ImportMem},
+    backend::renderer::{
+        element::{surface::WaylandSurfaceRenderElement, texture::TextureRenderElement},
+        ImportAll, ImportMem, Renderer,
+    },
     desktop::space::SpaceRenderElements,
     render_elements,
 };
@@ -10,5 +13,9 @@ render_elements! {
     pub CustomRenderElements<R, E> where
         R: ImportAll + ImportMem;
     Pointer=PointerRenderElement<R>,
-    Space=SpaceRenderElements<R, E>
+    Space=SpaceRenderElements<R, E>,
+    LockSurface=WaylandSurfaceRenderElement<R>,
+    DndIcon=WaylandSurfaceRenderElement<R>,
+    InputPopup=WaylandSurfaceRenderElement<R>,
+    Border=TextureRenderElement<<R as Renderer>::TextureId>,
 }
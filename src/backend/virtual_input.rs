@@ -0,0 +1,165 @@
+use std::fs;
+
+use smithay::{
+    backend::input::{ButtonState, KeyState},
+    input::{keyboard::ModifiersState, pointer::AxisFrame},
+    output::Output,
+    reexports::{
+        wayland_protocols_misc::zwp_virtual_keyboard_v1::server::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1,
+        wayland_server::{Client, Resource},
+    },
+    utils::{Logical, Point},
+};
+
+use super::UdevData;
+use crate::{
+    delegate_virtual_keyboard, delegate_virtual_pointer,
+    protocols::{
+        virtual_keyboard::{VirtualKeyboardHandler, VirtualKeyboardState},
+        virtual_pointer::{VirtualPointerHandler, VirtualPointerState},
+    },
+    Corrosion,
+};
+
+impl Corrosion<UdevData> {
+    // Checked against [virtual_input], by the path of the executable the client runs. It's easy
+    // to get around, anything running as the user can start a listed program and drive it
+    fn virtual_input_allowed(&self, client: &Client) -> bool {
+        let virtual_input = match self.config.get_virtual_input() {
+            Some(virtual_input) => virtual_input,
+            None => return true,
+        };
+        let executable = client
+            .get_credentials(&self.display_handle)
+            .ok()
+            .and_then(|credentials| fs::read_link(format!("/proc/{}/exe", credentials.pid)).ok());
+        let allowed = virtual_input.allows(executable.as_deref());
+        if !allowed {
+            tracing::warn!(
+                "{} is not allowed to create virtual input devices",
+                executable.map_or("unknown client".into(), |path| path.display().to_string())
+            );
+        }
+        allowed
+    }
+}
+
+impl VirtualKeyboardHandler for Corrosion<UdevData> {
+    fn virtual_keyboard_state(&mut self) -> &mut VirtualKeyboardState {
+        &mut self.backend_data.virtual_keyboard_state
+    }
+
+    fn virtual_keyboard_allowed(&mut self, client: &Client) -> bool {
+        self.virtual_input_allowed(client)
+    }
+
+    fn virtual_key(
+        &mut self,
+        keyboard: &ZwpVirtualKeyboardV1,
+        keymap: &str,
+        modifiers: ModifiersState,
+        time: u32,
+        keycode: u32,
+        state: KeyState,
+    ) {
+        self.notify_activity();
+        let device = format!("virtual keyboard {}", keyboard.id().protocol_id());
+        self.use_virtual_keyboard(&device, keymap);
+        // the input method's own keyboard mustn't be grabbed by it again
+        let to_input_method = !self
+            .input_method_state
+            .is_input_method_client(&keyboard.id());
+        // the seat keyboard only knows the keys, not the modifiers the client set itself
+        self.send_keyboard_modifiers(modifiers, to_input_method);
+        self.keyboard_key(keycode, state, time, to_input_method);
+        self.schedule_render();
+    }
+
+    fn virtual_modifiers(
+        &mut self,
+        keyboard: &ZwpVirtualKeyboardV1,
+        _keymap: &str,
+        modifiers: ModifiersState,
+    ) {
+        // the modifiers are for the keymap of this keyboard, the seat only has it while this
+        // keyboard is the last one that typed. Otherwise they're sent along with its next key
+        let device = format!("virtual keyboard {}", keyboard.id().protocol_id());
+        if self.virtual_keymap.is_none() || self.keyboard_device.as_deref() != Some(&device) {
+            return;
+        }
+        let to_input_method = !self
+            .input_method_state
+            .is_input_method_client(&keyboard.id());
+        self.send_keyboard_modifiers(modifiers, to_input_method);
+    }
+
+    fn virtual_keyboards_changed(&mut self) {
+        self.update_seat_capabilities();
+    }
+}
+
+delegate_virtual_keyboard!(Corrosion<UdevData>);
+
+impl VirtualPointerHandler for Corrosion<UdevData> {
+    fn virtual_pointer_state(&mut self) -> &mut VirtualPointerState {
+        &mut self.backend_data.virtual_pointer_state
+    }
+
+    fn virtual_pointer_allowed(&mut self, client: &Client) -> bool {
+        self.virtual_input_allowed(client)
+    }
+
+    fn virtual_pointer_motion(&mut self, time: u32, delta: Point<f64, Logical>) {
+        self.notify_activity();
+        // there is no acceleration to take away
+        self.pointer_motion(delta, delta, time as u64 * 1000);
+        self.schedule_render();
+    }
+
+    fn virtual_pointer_motion_absolute(
+        &mut self,
+        output: Option<&Output>,
+        time: u32,
+        x: f64,
+        y: f64,
+    ) {
+        let geometry = match output {
+            Some(output) => self.space.output_geometry(output),
+            None => self
+                .space
+                .outputs()
+                .filter_map(|output| self.space.output_geometry(output))
+                .reduce(|all, geometry| all.merge(geometry)),
+        };
+        let geometry = match geometry {
+            Some(geometry) => geometry,
+            None => return,
+        };
+
+        self.notify_activity();
+        let location = geometry.loc.to_f64()
+            + Point::from((geometry.size.w as f64 * x, geometry.size.h as f64 * y));
+        self.pointer_motion_absolute(location, time);
+        self.schedule_render();
+    }
+
+    fn virtual_pointer_button(&mut self, time: u32, button: u32, state: ButtonState) {
+        self.notify_activity();
+        self.pointer_button(button, state, time);
+        self.schedule_render();
+    }
+
+    fn virtual_pointer_axis(&mut self, frame: AxisFrame) {
+        self.notify_activity();
+        if let Some(pointer) = self.seat.get_pointer() {
+            pointer.axis(self, frame);
+        }
+        self.schedule_render();
+    }
+
+    fn virtual_pointers_changed(&mut self) {
+        self.update_seat_capabilities();
+    }
+}
+
+delegate_virtual_pointer!(Corrosion<UdevData>);
@@ -1,81 +1,1202 @@
-use serde_derive::Deserialize;
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+use serde::{de, Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
+use smithay::{input::keyboard::XkbConfig, output::Scale, utils::Transform};
 
+use std::collections::HashMap;
 use std::env;
 use std::fs::{self, create_dir_all, read_to_string};
-use std::path::Path;
-use std::process::Command;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 //The default configuration
 const DEFAULT_CONFIG: &str = r#"# This is the default corrosionwm config
+# Other files can be merged over this one, later files win:
+# include = ["outputs.toml", { path = "host/${HOSTNAME}.toml", optional = true }]
+
 [defaults]
 terminal = "kitty"
 launcher = "wofi --show drun"
+
+[idle]
+# seconds without input before the outputs are turned off, 0 keeps them on
+dpms_timeout = 0
+
+[screenshot]
+# where screenshots are saved, ${VAR}s are expanded
+directory = "${HOME}/Pictures"
+# also copy every screenshot to the clipboard
+clipboard = false
+
+[clipboard]
+# keep a copy of the clipboard, so it survives the app it was copied from quitting. Only text,
+# links and png images are kept
+persist = false
+
+# Outputs are matched by connector name or by "<make> <model>" from their EDID
+# [output."DP-1"]
+# mode = "2560x1440@143.97"
+# position = { x = 0, y = 0 }
+# scale = 1.5
+# transform = "normal" # "90", "180", "270", "flipped", "flipped-90", ...
+# vrr = "off" # "on", "fullscreen-only"
+# enabled = true
+
+# Touchpad gestures are bound by kind, finger count and direction, swipes go left, right, up or
+# down and pinches in or out. Finger counts without bindings go to the app under the pointer.
+# Left out, three finger swipes left and right switch workspaces
+# [gestures]
+# "swipe-3-left" = "next-workspace"
+# "swipe-3-right" = "previous-workspace"
+# "swipe-3-up" = { spawn = "wofi --show drun" }
+# "pinch-4-in" = "screenshot"
+
+# Settings for all touchpads and all other pointer devices, anything left out is up to libinput
+# [input.touchpad]
+# tap = true
+# natural_scroll = true
+# disable_while_typing = true
+# drag_lock = false
+# scroll_method = "two-finger" # "edge", "on-button-down", "none"
+# click_method = "clickfinger" # "button-areas"
+# [input.mouse]
+# accel_profile = "flat" # "adaptive"
+# accel_speed = 0.0 # from -1.0 to 1.0
+# left_handed = false
+# middle_emulation = false
+
+# [input.keyboard]
+# layout = "us,de" # mod+space goes to the next layout
+# variant = ",nodeadkeys"
+# model = "pc105"
+# options = "grp:alt_shift_toggle,caps:escape"
+# repeat_delay = 500 # in milliseconds
+# repeat_rate = 25 # per second
+
+# Single devices are matched by name, `libinput list-devices` shows them. Their settings go on top
+# of [input.touchpad], [input.mouse] or [input.keyboard]
+# [input."ELAN Touchscreen"]
+# map_to_output = "eDP-1" # touchscreens and tablets cover this output instead of the first one
+
+# Programs that may type and move the pointer as virtual devices, by the full path of their
+# executable as /proc shows it, symlinks resolved. Every program may if this is left out, once it's
+# there only the listed ones may. This keeps out programs that aren't meant to, it's no security
+# boundary: anything running as you can still get in, e.g. by running a listed program
+# [virtual_input]
+# allowed = ["/usr/bin/wtype", "/usr/bin/wayvnc"]
 "#;
 
 //top level data struct
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
 pub struct CorrosionConfig {
-    defaults: Defaults, //[defaults]
+    include: Vec<Include>,  // include = ["outputs.toml", "host/${HOSTNAME}.toml"]
+    defaults: Defaults,     //[defaults]
+    idle: Idle,             //[idle]
+    screenshot: Screenshot, //[screenshot]
+    clipboard: Clipboard,   //[clipboard]
+    #[serde(rename = "output")]
+    outputs: HashMap<String, OutputConfig>, //[output."DP-1"] or [output."Make Model"]
+    #[serde(rename = "input")]
+    inputs: HashMap<String, InputConfig>, //[input.keyboard], [input.touchpad], [input."Name"], ...
+    gestures: Gestures,     //[gestures] "swipe-3-left" = ...
+    virtual_input: Option<VirtualInput>, //[virtual_input]
+
+    // every file this config was merged from, including missing optional ones
+    #[serde(skip)]
+    sources: Vec<PathBuf>,
+}
+
+// an included file, either a plain path or { path = "...", optional = true }
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Include {
+    Path(String),
+    Table {
+        path: String,
+        #[serde(default)]
+        optional: bool,
+    },
+}
+
+impl Include {
+    fn path(&self) -> &str {
+        match self {
+            Include::Path(path) | Include::Table { path, .. } => path,
+        }
+    }
+
+    fn optional(&self) -> bool {
+        matches!(self, Include::Table { optional: true, .. })
+    }
 }
 
 //TODO: add more config options here e.g [misc], [config], [keybinds]
 //[defaults]
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Defaults {
     pub terminal: String,
     pub launcher: String,
 }
 
+impl Default for Defaults {
+    fn default() -> Self {
+        Self {
+            terminal: String::from("kitty"),
+            launcher: String::from("wofi --show drun"),
+        }
+    }
+}
+
+//[idle]
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct Idle {
+    pub dpms_timeout: u64, // in seconds, 0 never turns the outputs off
+}
+
+impl Idle {
+    pub fn dpms_timeout(&self) -> Option<Duration> {
+        (self.dpms_timeout > 0).then(|| Duration::from_secs(self.dpms_timeout))
+    }
+}
+
+//[screenshot]
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+pub struct Screenshot {
+    pub directory: String, // ${VAR}s are expanded, like in includes
+    pub clipboard: bool,
+}
+
+impl Default for Screenshot {
+    fn default() -> Self {
+        Self {
+            directory: String::from("${HOME}/Pictures"),
+            clipboard: false,
+        }
+    }
+}
+
+impl Screenshot {
+    pub fn directory(&self) -> Option<PathBuf> {
+        expand_vars(&self.directory).map(PathBuf::from)
+    }
+}
+
+//[clipboard]
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct Clipboard {
+    pub persist: bool,
+}
+
+//[output."name"]
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub enabled: bool,
+    pub mode: Option<OutputMode>, // "2560x1440" or "2560x1440@143.97"
+    pub position: Option<OutputPosition>,
+    #[serde(deserialize_with = "positive_scale")]
+    pub scale: Option<f64>,
+    pub transform: OutputTransform,
+    pub vrr: OutputVrr,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mode: None,
+            position: None,
+            scale: None,
+            transform: OutputTransform::Normal,
+            vrr: OutputVrr::Off,
+        }
+    }
+}
+
+impl OutputConfig {
+    // whole numbers are sent to clients as integer scales, anything else as fractional.
+    // no scale means 1, so dropping it from the config undoes a previous scale on reload
+    pub fn scale(&self) -> Scale {
+        match self.scale {
+            Some(scale) if scale.fract() == 0.0 => Scale::Integer(scale as i32),
+            Some(scale) => Scale::Fractional(scale),
+            None => Scale::Integer(1),
+        }
+    }
+}
+
+// a scale of 0 or less would make the output infinitely large, or turn it inside out
+fn positive_scale<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    let scale = f64::deserialize(deserializer)?;
+    if scale > 0.0 {
+        Ok(Some(scale))
+    } else {
+        Err(de::Error::custom(format!(
+            "invalid scale {}, it has to be positive",
+            scale
+        )))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct OutputMode {
+    pub width: i32,
+    pub height: i32,
+    pub refresh: Option<f64>, // in Hz
+}
+
+impl TryFrom<String> for OutputMode {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid mode '{}', expected WIDTHxHEIGHT[@REFRESH]", value);
+        let (size, refresh) = match value.split_once('@') {
+            Some((size, refresh)) => (size, Some(refresh.parse().map_err(|_| invalid())?)),
+            None => (value.as_str(), None),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        Ok(Self {
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+            refresh,
+        })
+    }
+}
+
+impl From<OutputMode> for String {
+    fn from(mode: OutputMode) -> Self {
+        match mode.refresh {
+            Some(refresh) => format!("{}x{}@{}", mode.width, mode.height, refresh),
+            None => format!("{}x{}", mode.width, mode.height),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct OutputPosition {
+    pub x: i32,
+    pub y: i32,
+}
+
+// variable refresh rate, only used by outputs that support it
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputVrr {
+    Off,
+    On,
+    FullscreenOnly, // only while a window covers the whole output
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputTransform {
+    Normal,
+    #[serde(rename = "90")]
+    _90,
+    #[serde(rename = "180")]
+    _180,
+    #[serde(rename = "270")]
+    _270,
+    Flipped,
+    #[serde(rename = "flipped-90")]
+    Flipped90,
+    #[serde(rename = "flipped-180")]
+    Flipped180,
+    #[serde(rename = "flipped-270")]
+    Flipped270,
+}
+
+impl From<OutputTransform> for Transform {
+    fn from(transform: OutputTransform) -> Self {
+        match transform {
+            OutputTransform::Normal => Transform::Normal,
+            OutputTransform::_90 => Transform::_90,
+            OutputTransform::_180 => Transform::_180,
+            OutputTransform::_270 => Transform::_270,
+            OutputTransform::Flipped => Transform::Flipped,
+            OutputTransform::Flipped90 => Transform::Flipped90,
+            OutputTransform::Flipped180 => Transform::Flipped180,
+            OutputTransform::Flipped270 => Transform::Flipped270,
+        }
+    }
+}
+
+impl From<Transform> for OutputTransform {
+    fn from(transform: Transform) -> Self {
+        match transform {
+            Transform::Normal => OutputTransform::Normal,
+            Transform::_90 => OutputTransform::_90,
+            Transform::_180 => OutputTransform::_180,
+            Transform::_270 => OutputTransform::_270,
+            Transform::Flipped => OutputTransform::Flipped,
+            Transform::Flipped90 => OutputTransform::Flipped90,
+            Transform::Flipped180 => OutputTransform::Flipped180,
+            Transform::Flipped270 => OutputTransform::Flipped270,
+        }
+    }
+}
+
+//[input."name"]
+#[derive(Deserialize, Serialize, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct InputConfig {
+    pub map_to_output: Option<String>, // connector name, for touchscreens and tablets
+    pub accel_profile: Option<InputAccelProfile>,
+    pub accel_speed: Option<f64>, // from -1.0 to 1.0
+    pub natural_scroll: Option<bool>,
+    pub tap: Option<bool>,
+    pub drag_lock: Option<bool>,
+    pub disable_while_typing: Option<bool>,
+    pub scroll_method: Option<InputScrollMethod>,
+    pub left_handed: Option<bool>,
+    pub middle_emulation: Option<bool>,
+    pub click_method: Option<InputClickMethod>,
+    // xkb names, e.g. layout = "us,de" and options = "grp:alt_shift_toggle,caps:escape"
+    pub rules: Option<String>,
+    pub model: Option<String>,
+    pub layout: Option<String>,
+    pub variant: Option<String>,
+    pub options: Option<String>,
+    pub repeat_delay: Option<i32>, // in milliseconds
+    pub repeat_rate: Option<i32>,  // per second
+}
+
+impl InputConfig {
+    // `self` with everything it leaves out taken from `base`
+    fn or(&self, base: &InputConfig) -> InputConfig {
+        InputConfig {
+            map_to_output: self
+                .map_to_output
+                .clone()
+                .or_else(|| base.map_to_output.clone()),
+            accel_profile: self.accel_profile.or(base.accel_profile),
+            accel_speed: self.accel_speed.or(base.accel_speed),
+            natural_scroll: self.natural_scroll.or(base.natural_scroll),
+            tap: self.tap.or(base.tap),
+            drag_lock: self.drag_lock.or(base.drag_lock),
+            disable_while_typing: self.disable_while_typing.or(base.disable_while_typing),
+            scroll_method: self.scroll_method.or(base.scroll_method),
+            left_handed: self.left_handed.or(base.left_handed),
+            middle_emulation: self.middle_emulation.or(base.middle_emulation),
+            click_method: self.click_method.or(base.click_method),
+            rules: self.rules.clone().or_else(|| base.rules.clone()),
+            model: self.model.clone().or_else(|| base.model.clone()),
+            layout: self.layout.clone().or_else(|| base.layout.clone()),
+            variant: self.variant.clone().or_else(|| base.variant.clone()),
+            options: self.options.clone().or_else(|| base.options.clone()),
+            repeat_delay: self.repeat_delay.or(base.repeat_delay),
+            repeat_rate: self.repeat_rate.or(base.repeat_rate),
+        }
+    }
+
+    // the keyboard settings, with the defaults for everything left out
+    pub fn keyboard(&self) -> KeyboardConfig {
+        KeyboardConfig {
+            rules: self.rules.clone().unwrap_or_default(),
+            model: self.model.clone().unwrap_or_default(),
+            layout: self.layout.clone().unwrap_or_default(),
+            variant: self.variant.clone().unwrap_or_default(),
+            options: self.options.clone(),
+            repeat_delay: self.repeat_delay.unwrap_or(500),
+            repeat_rate: self.repeat_rate.unwrap_or(500),
+        }
+    }
+}
+
+// a keyboard's part of its [input] sections, empty strings are left to xkb
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct KeyboardConfig {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: Option<String>,
+    pub repeat_delay: i32,
+    pub repeat_rate: i32,
+}
+
+impl KeyboardConfig {
+    pub fn xkb_config(&self) -> XkbConfig<'_> {
+        XkbConfig {
+            rules: &self.rules,
+            model: &self.model,
+            layout: &self.layout,
+            variant: &self.variant,
+            options: self.options.clone(),
+        }
+    }
+
+    pub fn layout_count(&self) -> u32 {
+        self.layout.split(',').count() as u32
+    }
+
+    // whether both compile to the same keymap, the repeat settings don't matter
+    pub fn same_keymap(&self, other: &KeyboardConfig) -> bool {
+        KeyboardConfig {
+            repeat_delay: other.repeat_delay,
+            repeat_rate: other.repeat_rate,
+            ..self.clone()
+        } == *other
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputAccelProfile {
+    Flat,
+    Adaptive,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputScrollMethod {
+    None,
+    TwoFinger,
+    Edge,
+    OnButtonDown,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputClickMethod {
+    ButtonAreas,
+    Clickfinger,
+}
+
+//[gestures]
+#[derive(Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Gestures(HashMap<String, GestureAction>);
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Self(HashMap::from([
+            ("swipe-3-left".to_string(), GestureAction::NextWorkspace),
+            (
+                "swipe-3-right".to_string(),
+                GestureAction::PreviousWorkspace,
+            ),
+        ]))
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum GestureAction {
+    Spawn(String),     // { spawn = "command" }
+    Screenshot,        // "screenshot", of the output under the pointer
+    NextWorkspace,     // "next-workspace", swipes follow the fingers
+    PreviousWorkspace, // "previous-workspace"
+}
+
+//[virtual_input], without it every program is allowed
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct VirtualInput {
+    pub allowed: Vec<PathBuf>, // full executable paths, nothing else gets in
+}
+
+impl VirtualInput {
+    // whether a client running `executable` may create virtual devices, clients that can't be
+    // traced back to one never do. Only a check against honest programs, the executable of a
+    // client says nothing about who is in control of it
+    pub fn allows(&self, executable: Option<&Path>) -> bool {
+        executable.map_or(false, |executable| {
+            self.allowed.iter().any(|path| path == executable)
+        })
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Neither $XDG_CONFIG_HOME nor $HOME is set")]
+    NoConfigDirectory,
+    #[error("Error accessing '{}': {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{}:{line}:{column}: {message}", path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    #[error("Include '{0}' could not be found")]
+    MissingInclude(PathBuf),
+    #[error("Error merging included config files: {0}")]
+    Merge(#[source] toml::de::Error),
+}
+
 impl CorrosionConfig {
     //initialize corrosion config
     pub fn new() -> Self {
-        // use $XDG_CONFIG_HOME, or fallback to $HOME/.config
-        let config_directory = match env::var("XDG_CONFIG_HOME") {
-            Ok(val) => format!("{}/corrosionwm", val),
-            Err(_) => format!("{}/.config/corrosionwm", env::var("HOME").unwrap()),
-        };
+        let mut sources = Vec::new();
+        match Self::load(&mut sources) {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::error!("{}", err);
+                tracing::error!("Falling back to default hardcoded config.");
+                // keep watching the files that were read so fixing them gets picked up
+                Self {
+                    sources,
+                    ..Self::default()
+                }
+            }
+        }
+    }
 
-        let config_file = format!("{}/config.toml", config_directory);
+    // loads the config file, creating it if it does not exist yet. `sources` gets every file that
+    // was read, also when loading fails halfway, so the broken file can be watched too
+    pub fn load(sources: &mut Vec<PathBuf>) -> Result<Self, Error> {
+        let config_directory = config_directory()?;
+        let config_file = config_directory.join("config.toml");
 
         //check for ~/.config/corrosionwm
-        if !Path::new(&config_directory).exists() {
+        if !config_directory.exists() {
             tracing::info!(
                 "Config folder not found, Creating at '{}'.",
-                config_directory
+                config_directory.display()
             );
-            create_dir_all(config_directory).unwrap();
+            create_dir_all(&config_directory).map_err(|source| Error::Io {
+                path: config_directory.clone(),
+                source,
+            })?;
         }
 
         //check for ~/.config/corrosionwm/config.toml
-        if !Path::new(&config_file).exists() {
-            tracing::info!("Config file not found, Creating at '{}'.", config_file);
-            fs::write(&config_file, DEFAULT_CONFIG).unwrap();
+        if !config_file.exists() {
+            tracing::info!(
+                "Config file not found, Creating at '{}'.",
+                config_file.display()
+            );
+            fs::write(&config_file, DEFAULT_CONFIG).map_err(|source| Error::Io {
+                path: config_file.clone(),
+                source,
+            })?;
         }
 
-        return match toml::from_str(&read_to_string(&config_file).unwrap()) {
-            Ok(c) => {
-                tracing::info!("Loaded config from {}", config_file);
-                c
+        let mut merged = toml::Value::Table(Default::default());
+        Self::merge_file(&config_file, false, &mut merged, sources)?;
+
+        let mut config: Self = merged.try_into().map_err(Error::Merge)?;
+        config.sources = sources.clone();
+        tracing::info!("Loaded config from {}", config_file.display());
+        Ok(config)
+    }
+
+    // deep-merges a file and everything it includes into `merged`, later files win
+    fn merge_file(
+        path: &Path,
+        optional: bool,
+        merged: &mut toml::Value,
+        sources: &mut Vec<PathBuf>,
+    ) -> Result<(), Error> {
+        if sources.iter().any(|source| source == path) {
+            tracing::warn!("{} is included more than once, skipping", path.display());
+            return Ok(());
+        }
+        sources.push(path.to_path_buf());
+
+        if !path.exists() {
+            if optional {
+                tracing::debug!("Optional include {} not found, skipping", path.display());
+                return Ok(());
             }
-            Err(_) => {
-                tracing::error!(
-                    "Config file is not valid, falling back to default hardcoded config."
-                );
-                tracing::info!("Loaded hardcoded config file");
-                toml::from_str(DEFAULT_CONFIG).unwrap()
+            return Err(Error::MissingInclude(path.to_path_buf()));
+        }
+
+        let (config, raw) = Self::parse_file(path)?;
+        deep_merge(merged, raw);
+
+        let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+        for include in &config.include {
+            let expanded = match expand_vars(include.path()) {
+                Some(expanded) => expanded,
+                None => {
+                    tracing::warn!(
+                        "{}: unable to expand variables in include '{}', skipping",
+                        path.display(),
+                        include.path()
+                    );
+                    continue;
+                }
+            };
+            Self::merge_file(&parent.join(expanded), include.optional(), merged, sources)?;
+        }
+
+        Ok(())
+    }
+
+    // parses a single config file, unknown keys are reported as warnings
+    fn parse_file(path: &Path) -> Result<(Self, toml::Value), Error> {
+        let contents = read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let config: Self =
+            toml::from_str(&contents).map_err(|err| parse_error(path, &contents, err))?;
+        let raw: toml::Value =
+            toml::from_str(&contents).map_err(|err| parse_error(path, &contents, err))?;
+
+        // anything that does not survive a round trip through the schema was never read
+        if let Ok(known) = toml::Value::try_from(&config) {
+            for key in unknown_keys(&raw, &known, String::new()) {
+                tracing::warn!("{}: unknown config key '{}'", path.display(), key);
             }
-        };
+        }
+
+        Ok((config, raw))
     }
 
     //fetches the [defaults] section and returns it
     pub fn get_defaults(&self) -> &Defaults {
         &self.defaults
     }
+
+    //fetches the [idle] section and returns it
+    pub fn get_idle(&self) -> &Idle {
+        &self.idle
+    }
+
+    //fetches the [screenshot] section and returns it
+    pub fn get_screenshot(&self) -> &Screenshot {
+        &self.screenshot
+    }
+
+    //fetches the [clipboard] section and returns it
+    pub fn get_clipboard(&self) -> &Clipboard {
+        &self.clipboard
+    }
+
+    //fetches the [virtual_input] section and returns it, if there is one
+    pub fn get_virtual_input(&self) -> Option<&VirtualInput> {
+        self.virtual_input.as_ref()
+    }
+
+    //fetches the [output] section matching a connector name, or else its edid make and model
+    pub fn output_config(&self, name: &str, make: &str, model: &str) -> Option<&OutputConfig> {
+        self.outputs
+            .get(name)
+            .or_else(|| self.outputs.get(&format!("{} {}", make, model)))
+    }
+
+    //fetches the [input] section matching a device name
+    pub fn input_config(&self, name: &str) -> Option<&InputConfig> {
+        self.inputs.get(name)
+    }
+
+    //the settings for a device, its own [input] section on top of the one for its kind, e.g.
+    //[input.touchpad]
+    pub fn input_device_config(&self, name: &str, kind: Option<&str>) -> InputConfig {
+        let base = kind
+            .and_then(|kind| self.inputs.get(kind))
+            .cloned()
+            .unwrap_or_default();
+        match self.inputs.get(name) {
+            Some(device) => device.or(&base),
+            None => base,
+        }
+    }
+
+    //fetches the action bound to a gesture, e.g. ("swipe", 3, "left")
+    pub fn gesture_binding(
+        &self,
+        kind: &str,
+        fingers: u32,
+        direction: &str,
+    ) -> Option<&GestureAction> {
+        self.gestures
+            .0
+            .get(&format!("{}-{}-{}", kind, fingers, direction))
+    }
+
+    //whether any gesture of this kind and finger count is bound
+    pub fn has_gesture_bindings(&self, kind: &str, fingers: u32) -> bool {
+        let prefix = format!("{}-{}-", kind, fingers);
+        self.gestures.0.keys().any(|key| key.starts_with(&prefix))
+    }
+
+    //the keyboard settings for a device, or for every keyboard with None
+    pub fn keyboard_config(&self, device: Option<&str>) -> KeyboardConfig {
+        match device {
+            Some(device) => self.input_device_config(device, Some("keyboard")),
+            None => self.inputs.get("keyboard").cloned().unwrap_or_default(),
+        }
+        .keyboard()
+    }
+
+    //every file the config was loaded from
+    pub fn sources(&self) -> &[PathBuf] {
+        &self.sources
+    }
 }
 
-impl Default for CorrosionConfig {
-    fn default() -> Self {
-        // return the default config
-        toml::from_str(DEFAULT_CONFIG).unwrap()
+// watches the directories of every config source with inotify, editors often replace a file
+// instead of writing to it
+pub struct ConfigWatcher {
+    inotify: Inotify,
+    directories: HashMap<WatchDescriptor, PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl ConfigWatcher {
+    pub fn new(files: &[PathBuf]) -> io::Result<Self> {
+        let mut watcher = Self {
+            inotify: Inotify::init()?,
+            directories: HashMap::new(),
+            files: Vec::new(),
+        };
+        watcher.watch(files);
+        Ok(watcher)
+    }
+
+    // replaces the watched files
+    pub fn watch(&mut self, files: &[PathBuf]) {
+        let mut watches = self.inotify.watches();
+        for (descriptor, _) in self.directories.drain() {
+            let _ = watches.remove(descriptor);
+        }
+
+        self.files = files.to_vec();
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        for file in &self.files {
+            let directory = match file.parent() {
+                Some(directory) => directory,
+                None => continue,
+            };
+            if self
+                .directories
+                .values()
+                .any(|watched| watched == directory)
+            {
+                continue;
+            }
+            match watches.add(directory, mask) {
+                Ok(descriptor) => {
+                    self.directories.insert(descriptor, directory.to_path_buf());
+                }
+                // e.g. the directory of an optional include that doesn't exist
+                Err(err) => tracing::debug!(
+                    "Unable to watch {} for config changes: {}",
+                    directory.display(),
+                    err
+                ),
+            }
+        }
+    }
+
+    // reads every pending event, returns true if any of them was about a watched file
+    pub fn changed(&mut self) -> bool {
+        let mut buffer = [0; 4096];
+        let mut changed = false;
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    tracing::warn!("Unable to read config file events: {}", err);
+                    break;
+                }
+            };
+            for event in events {
+                // events of removed watches don't have a directory anymore
+                if let (Some(directory), Some(name)) = (self.directories.get(&event.wd), event.name)
+                {
+                    changed |= self.files.contains(&directory.join(name));
+                }
+            }
+        }
+        changed
+    }
+}
+
+impl AsRawFd for ConfigWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+// use $XDG_CONFIG_HOME, or fallback to $HOME/.config
+fn config_directory() -> Result<PathBuf, Error> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(val) if !val.is_empty() => Ok(PathBuf::from(val).join("corrosionwm")),
+        _ => env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map(|home| PathBuf::from(home).join(".config/corrosionwm"))
+            .ok_or(Error::NoConfigDirectory),
+    }
+}
+
+// tables are merged key by key, everything else is replaced by the newer value
+fn deep_merge(base: &mut toml::Value, other: toml::Value) {
+    match (base, other) {
+        (toml::Value::Table(base), toml::Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+// expands ${VAR} with environment variables, HOSTNAME falls back to the kernel hostname
+fn expand_vars(input: &str) -> Option<String> {
+    let mut output = String::new();
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find('}')? + start;
+        let name = &rest[start + 2..end];
+        let value = env::var(name).ok().or_else(|| match name {
+            "HOSTNAME" => read_to_string("/proc/sys/kernel/hostname")
+                .ok()
+                .map(|hostname| hostname.trim().to_string()),
+            _ => None,
+        })?;
+        output.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+// turns a toml error into an error pointing at the offending line and column
+fn parse_error(path: &Path, contents: &str, err: toml::de::Error) -> Error {
+    let (line, column) = err
+        .span()
+        .map(|span| line_and_column(contents, span.start))
+        .unwrap_or((0, 0));
+
+    Error::Parse {
+        path: path.to_path_buf(),
+        line,
+        column,
+        message: err.message().to_string(),
+    }
+}
+
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, column)
+}
+
+fn unknown_keys(raw: &toml::Value, known: &toml::Value, prefix: String) -> Vec<String> {
+    let (raw, known) = match (raw.as_table(), known.as_table()) {
+        (Some(raw), Some(known)) => (raw, known),
+        _ => return Vec::new(),
+    };
+
+    raw.iter()
+        .flat_map(|(key, value)| {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            match known.get(key) {
+                Some(known) => unknown_keys(value, known, path),
+                None => vec![path],
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory for the files of one test
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            env::temp_dir().join(format!("corrosionwm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn merge(path: &Path) -> (Result<CorrosionConfig, Error>, Vec<PathBuf>) {
+        let mut merged = toml::Value::Table(Default::default());
+        let mut sources = Vec::new();
+        let config = CorrosionConfig::merge_file(path, false, &mut merged, &mut sources)
+            .and_then(|()| merged.try_into().map_err(Error::Merge));
+        (config, sources)
+    }
+
+    #[test]
+    fn deep_merge_merges_tables_and_replaces_values() {
+        let mut base: toml::Value = toml::from_str(
+            r#"
+            list = [1, 2]
+            [table]
+            kept = "base"
+            replaced = "base"
+            "#,
+        )
+        .unwrap();
+        let other: toml::Value = toml::from_str(
+            r#"
+            list = [3]
+            [table]
+            replaced = "other"
+            added = "other"
+            "#,
+        )
+        .unwrap();
+        deep_merge(&mut base, other);
+
+        let expected: toml::Value = toml::from_str(
+            r#"
+            list = [3]
+            [table]
+            kept = "base"
+            replaced = "other"
+            added = "other"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn includes_are_merged_over_the_including_file() {
+        let directory = test_directory("includes");
+        fs::write(
+            directory.join("config.toml"),
+            r#"
+            include = ["terminal.toml", { path = "missing.toml", optional = true }]
+            [defaults]
+            terminal = "kitty"
+            launcher = "fuzzel"
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            directory.join("terminal.toml"),
+            "[defaults]\nterminal = \"foot\"\n",
+        )
+        .unwrap();
+
+        let (config, sources) = merge(&directory.join("config.toml"));
+        let config = config.unwrap();
+        assert_eq!(config.get_defaults().terminal, "foot");
+        assert_eq!(config.get_defaults().launcher, "fuzzel");
+        // optional includes are watched in case they show up later
+        assert_eq!(
+            sources,
+            vec![
+                directory.join("config.toml"),
+                directory.join("terminal.toml"),
+                directory.join("missing.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn includes_are_only_merged_once() {
+        let directory = test_directory("include-cycle");
+        fs::write(
+            directory.join("config.toml"),
+            "include = [\"other.toml\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("other.toml"),
+            "include = [\"config.toml\"]\n",
+        )
+        .unwrap();
+
+        let (config, sources) = merge(&directory.join("config.toml"));
+        assert!(config.is_ok());
+        assert_eq!(sources.len(), 2);
+    }
+
+    #[test]
+    fn missing_includes_are_errors_unless_optional() {
+        let directory = test_directory("include-missing");
+        fs::write(
+            directory.join("config.toml"),
+            "include = [\"missing.toml\"]\n",
+        )
+        .unwrap();
+
+        let (config, _) = merge(&directory.join("config.toml"));
+        assert!(
+            matches!(config, Err(Error::MissingInclude(path)) if path == directory.join("missing.toml"))
+        );
+    }
+
+    #[test]
+    fn broken_includes_are_still_sources() {
+        let directory = test_directory("include-broken");
+        fs::write(
+            directory.join("config.toml"),
+            "include = [\"broken.toml\"]\n",
+        )
+        .unwrap();
+        fs::write(directory.join("broken.toml"), "[defaults\n").unwrap();
+
+        let (config, sources) = merge(&directory.join("config.toml"));
+        assert!(matches!(config, Err(Error::Parse { line: 1, .. })));
+        assert!(sources.contains(&directory.join("broken.toml")));
+    }
+
+    #[test]
+    fn watcher_only_reports_watched_files() {
+        let directory = test_directory("watcher");
+        let config = directory.join("config.toml");
+        fs::write(&config, "").unwrap();
+        let mut watcher = ConfigWatcher::new(std::slice::from_ref(&config)).unwrap();
+        assert!(!watcher.changed());
+
+        fs::write(directory.join("other.toml"), "").unwrap();
+        assert!(!watcher.changed());
+        // editors that save by renaming a new file over the old one
+        fs::write(directory.join("config.toml.new"), "").unwrap();
+        fs::rename(directory.join("config.toml.new"), &config).unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+    }
+
+    #[test]
+    fn keymaps_leave_out_the_repeat_settings() {
+        let keyboard = KeyboardConfig {
+            layout: "us,de".to_string(),
+            options: Some("grp:alt_shift_toggle".to_string()),
+            repeat_delay: 500,
+            repeat_rate: 25,
+            ..Default::default()
+        };
+        let faster = KeyboardConfig {
+            repeat_rate: 50,
+            ..keyboard.clone()
+        };
+        let swapped = KeyboardConfig {
+            layout: "de,us".to_string(),
+            ..keyboard.clone()
+        };
+        let no_options = KeyboardConfig {
+            options: None,
+            ..keyboard.clone()
+        };
+        assert!(keyboard.same_keymap(&faster));
+        assert!(!keyboard.same_keymap(&swapped));
+        assert!(!keyboard.same_keymap(&no_options));
+    }
+
+    #[test]
+    fn virtual_input_needs_the_full_path() {
+        let config: CorrosionConfig =
+            toml::from_str("[virtual_input]\nallowed = [\"/usr/bin/wtype\"]\n").unwrap();
+        let virtual_input = config.get_virtual_input().unwrap();
+        assert!(virtual_input.allows(Some(Path::new("/usr/bin/wtype"))));
+        assert!(!virtual_input.allows(Some(Path::new("/tmp/wtype"))));
+        assert!(!virtual_input.allows(Some(Path::new("wtype"))));
+        assert!(!virtual_input.allows(None));
+    }
+
+    #[test]
+    fn virtual_input_is_denied_once_the_section_exists() {
+        let config: CorrosionConfig = toml::from_str("").unwrap();
+        assert!(config.get_virtual_input().is_none());
+        let config: CorrosionConfig = toml::from_str("[virtual_input]\n").unwrap();
+        let virtual_input = config.get_virtual_input().unwrap();
+        assert!(!virtual_input.allows(Some(Path::new("/usr/bin/wtype"))));
+    }
+
+    #[test]
+    fn output_modes_are_parsed() {
+        let mode = |value: &str| OutputMode::try_from(value.to_string());
+        assert_eq!(
+            mode("2560x1440@143.97"),
+            Ok(OutputMode {
+                width: 2560,
+                height: 1440,
+                refresh: Some(143.97),
+            })
+        );
+        assert_eq!(
+            mode("1920x1080"),
+            Ok(OutputMode {
+                width: 1920,
+                height: 1080,
+                refresh: None,
+            })
+        );
+        assert!(mode("1920").is_err());
+        assert!(mode("1920x").is_err());
+        assert!(mode("axb").is_err());
+        assert!(mode("1920x1080@fast").is_err());
+        assert_eq!(
+            String::from(mode("1920x1080@60").unwrap()),
+            "1920x1080@60".to_string()
+        );
+    }
+
+    #[test]
+    fn output_scales_have_to_be_positive() {
+        let output = |toml: &str| toml::from_str::<OutputConfig>(toml);
+        assert_eq!(
+            output("scale = 1.5").unwrap().scale(),
+            Scale::Fractional(1.5)
+        );
+        assert_eq!(output("scale = 2").unwrap().scale(), Scale::Integer(2));
+        assert_eq!(output("").unwrap().scale(), Scale::Integer(1));
+        assert!(output("scale = 0").is_err());
+        assert!(output("scale = -1.0").is_err());
+        assert!(output("scale = nan").is_err());
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_line() {
+        assert_eq!(line_and_column("a = 1\nb = ?\n", 10), (2, 5));
+        assert_eq!(line_and_column("a = 1", 0), (1, 1));
+    }
+
+    #[test]
+    fn unknown_keys_are_found() {
+        let raw: toml::Value = toml::from_str(
+            "[defaults]\nterminal = \"foot\"\ntermnal = \"foot\"\n[idel]\ndpms_timeout = 1\n",
+        )
+        .unwrap();
+        let config: CorrosionConfig = raw.clone().try_into().unwrap();
+        let known = toml::Value::try_from(&config).unwrap();
+        let mut unknown = unknown_keys(&raw, &known, String::new());
+        unknown.sort();
+        assert_eq!(unknown, vec!["defaults.termnal", "idel"]);
+    }
+
+    #[test]
+    fn variables_are_expanded() {
+        env::set_var("CORROSIONWM_TEST_VAR", "value");
+        assert_eq!(
+            expand_vars("a/${CORROSIONWM_TEST_VAR}/b").as_deref(),
+            Some("a/value/b")
+        );
+        assert_eq!(expand_vars("no variables").as_deref(), Some("no variables"));
+        assert_eq!(expand_vars("${CORROSIONWM_TEST_UNSET_VAR}"), None);
+        assert_eq!(expand_vars("${unterminated"), None);
     }
 }
@@ -11,11 +11,16 @@ use smithay::{
     },
     input::pointer::CursorImageStatus,
     render_elements,
-    utils::{Physical, Point, Scale},
+    utils::{Logical, Physical, Point, Rectangle, Scale},
 };
 
 pub static CLEAR_COLOR: [f32; 4] = [0.8, 0.8, 0.9, 1.0];
 pub static CLEAR_COLOR_FULLSCREEN: [f32; 4] = [0.0, 0.0, 0.0, 0.0];
+// shown while the session is locked, wherever there is no lock surface (e.g. the locker crashed)
+pub static CLEAR_COLOR_LOCKED: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
+// the border around windows that want attention, as rgba bytes
+pub static URGENT_BORDER_COLOR: [u8; 4] = [0xe0, 0x40, 0x40, 0xff];
+pub const URGENT_BORDER_WIDTH: i32 = 3;
 
 pub struct PointerElement<T: Texture> {
     texture: Option<TextureBuffer<T>>,
@@ -103,3 +108,31 @@ where
         }
     }
 }
+
+// A border of `width` around `geometry`, made of a single pixel texture stretched four times.
+// `geometry` is relative to the output
+pub fn border_elements<T: Texture + Clone + 'static>(
+    texture: &TextureBuffer<T>,
+    geometry: Rectangle<i32, Logical>,
+    width: i32,
+    scale: Scale<f64>,
+) -> Vec<TextureRenderElement<T>> {
+    let (loc, size) = (geometry.loc, geometry.size);
+    [
+        Rectangle::from_loc_and_size((loc.x - width, loc.y - width), (size.w + 2 * width, width)),
+        Rectangle::from_loc_and_size((loc.x - width, loc.y + size.h), (size.w + 2 * width, width)),
+        Rectangle::from_loc_and_size((loc.x - width, loc.y), (width, size.h)),
+        Rectangle::from_loc_and_size((loc.x + size.w, loc.y), (width, size.h)),
+    ]
+    .into_iter()
+    .map(|rect: Rectangle<i32, Logical>| {
+        TextureRenderElement::from_texture_buffer(
+            rect.loc.to_f64().to_physical(scale),
+            texture,
+            None,
+            None,
+            Some(rect.size),
+        )
+    })
+    .collect()
+}
@@ -30,8 +30,12 @@ impl<BackendData: Backend + 'static> PointerGrab<Corrosion<BackendData>>
 
         let delta = event.location - self.start_data.location;
         let new_location = self.initial_window_location.to_f64() + delta;
+        // both the outputs it leaves and the ones it moves onto need a repaint
+        let surface = self.window.toplevel().wl_surface().clone();
+        data.schedule_render_for_surface(&surface);
         data.space
             .map_element(self.window.clone(), new_location.to_i32_round(), true);
+        data.schedule_render_for_surface(&surface);
     }
 
     fn relative_motion(
@@ -62,6 +66,8 @@ impl<BackendData: Backend + 'static> PointerGrab<Corrosion<BackendData>>
         {
             // No more buttons are pressed, release the grab.
             handle.unset_grab(data, event.serial, event.time);
+            // the window may be on other outputs now
+            data.refresh_foreign_toplevels();
         }
     }
 
@@ -25,17 +25,26 @@ impl<BackendData: Backend> CompositorHandler for Corrosion<BackendData> {
             while let Some(parent) = get_parent(&root) {
                 root = parent;
             }
-            if let Some(window) = self
-                .space
-                .elements()
-                .find(|w| w.toplevel().wl_surface() == &root)
-            {
+            // minimized windows still have to keep up with their surface
+            if let Some(window) = self.window_for_surface(&root) {
                 window.on_commit();
             }
         };
 
+        // input method popups move above the text cursor once they're too big to fit below
+        if self
+            .input_method_state
+            .popups()
+            .iter()
+            .any(|popup| popup.wl_surface() == surface)
+        {
+            self.update_input_popups();
+        }
+
+        self.pointer_constraints_state.commit(surface);
         xdg_shell::handle_commit(&self.space, surface);
         resize_grab::handle_commit(&mut self.space, surface);
+        self.schedule_render_for_surface(surface);
     }
 }
 
@@ -0,0 +1,56 @@
+use smithay::{
+    desktop::utils::send_frames_surface_tree,
+    input::Seat,
+    output::Output,
+    reexports::wayland_server::protocol::{wl_data_source::WlDataSource, wl_surface::WlSurface},
+    utils::IsAlive,
+    wayland::data_device::{ClientDndGrabHandler, ServerDndGrabHandler},
+};
+
+use crate::state::{Backend, Corrosion};
+
+impl<BackendData: Backend + 'static> ClientDndGrabHandler for Corrosion<BackendData> {
+    // the icon follows the pointer until the drag is over
+    fn started(
+        &mut self,
+        _source: Option<WlDataSource>,
+        icon: Option<WlSurface>,
+        _seat: Seat<Self>,
+    ) {
+        self.dnd_icon = icon;
+        self.schedule_render();
+    }
+
+    // called for cancelled drags too
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+        self.schedule_render();
+    }
+}
+
+// the compositor never starts drags itself, but a stuck icon should still go away
+impl<BackendData: Backend + 'static> ServerDndGrabHandler for Corrosion<BackendData> {
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+    }
+
+    fn cancelled(&mut self, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+    }
+}
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // The icon of the running drag, if its client didn't destroy it
+    pub fn dnd_icon(&self) -> Option<&WlSurface> {
+        self.dnd_icon.as_ref().filter(|icon| icon.alive())
+    }
+
+    // The icon isn't part of the space, so its frame callbacks are sent here
+    pub fn send_dnd_icon_frames(&self, output: &Output) {
+        if let Some(icon) = self.dnd_icon() {
+            send_frames_surface_tree(icon, output, self.start_time.elapsed(), None, |_, _| {
+                Some(output.clone())
+            });
+        }
+    }
+}
@@ -0,0 +1,236 @@
+// Touchpad gestures. The ones bound in the config run their action, everything else goes to the
+// client under the pointer
+
+use smithay::utils::{Logical, Point, SERIAL_COUNTER};
+
+use crate::{
+    backend::UdevData,
+    config::GestureAction,
+    delegate_pointer_gestures,
+    handlers::keybindings::{KeyAction, ScreenshotTarget},
+    protocols::pointer_gestures::{PointerGesturesHandler, PointerGesturesState},
+    state::{Backend, Corrosion},
+};
+
+// How far fingers have to move before a swipe counts, in touchpad units
+const SWIPE_THRESHOLD: f64 = 100.0;
+// How much a pinch has to scale before it counts, either way
+const PINCH_THRESHOLD: f64 = 1.25;
+// How far fingers move for a whole workspace switch, in touchpad units
+const WORKSPACE_SWIPE_DISTANCE: f64 = 300.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureKind {
+    Swipe,
+    Pinch,
+    Hold,
+}
+
+impl GestureKind {
+    // how the kind is written in the config
+    fn name(self) -> &'static str {
+        match self {
+            GestureKind::Swipe => "swipe",
+            GestureKind::Pinch => "pinch",
+            GestureKind::Hold => "hold",
+        }
+    }
+}
+
+// A gesture with a binding for its finger count, clients never see it
+pub struct CapturedGesture {
+    kind: GestureKind,
+    fingers: u32,
+    delta: Point<f64, Logical>,
+    scale: f64,
+}
+
+impl CapturedGesture {
+    // Where the fingers went, None if they didn't go far enough
+    fn direction(&self) -> Option<&'static str> {
+        let Point { x, y, .. } = self.delta;
+        match self.kind {
+            GestureKind::Swipe if x.abs().max(y.abs()) < SWIPE_THRESHOLD => None,
+            GestureKind::Swipe if x.abs() > y.abs() => Some(if x < 0.0 { "left" } else { "right" }),
+            GestureKind::Swipe => Some(if y < 0.0 { "up" } else { "down" }),
+            GestureKind::Pinch if self.scale < 1.0 / PINCH_THRESHOLD => Some("in"),
+            GestureKind::Pinch if self.scale > PINCH_THRESHOLD => Some("out"),
+            _ => None,
+        }
+    }
+}
+
+// Whether an action goes to the next workspace or the previous one, None if it isn't a switch
+fn workspace_step(action: Option<&GestureAction>) -> Option<bool> {
+    match action? {
+        GestureAction::NextWorkspace => Some(true),
+        GestureAction::PreviousWorkspace => Some(false),
+        _ => None,
+    }
+}
+
+impl<BackendData: Backend + 'static> PointerGesturesHandler for Corrosion<BackendData> {
+    fn pointer_gestures_state(&mut self) -> &mut PointerGesturesState {
+        &mut self.pointer_gestures_state
+    }
+}
+
+delegate_pointer_gestures!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl Corrosion<UdevData> {
+    pub fn gesture_begin(&mut self, kind: GestureKind, fingers: u32, time: u32) {
+        // holds don't go anywhere, so they can't be bound
+        if kind != GestureKind::Hold
+            && !self.is_locked()
+            && self.config.has_gesture_bindings(kind.name(), fingers)
+        {
+            self.captured_gesture = Some(CapturedGesture {
+                kind,
+                fingers,
+                delta: (0.0, 0.0).into(),
+                scale: 1.0,
+            });
+            return;
+        }
+
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        let surface = match self.surface_under_pointer(&pointer) {
+            Some((surface, _)) => surface,
+            None => return,
+        };
+        let serial = SERIAL_COUNTER.next_serial();
+        let gestures = &mut self.pointer_gestures_state;
+        match kind {
+            GestureKind::Swipe => gestures.swipe_begin(&surface, serial, time, fingers),
+            GestureKind::Pinch => gestures.pinch_begin(&surface, serial, time, fingers),
+            GestureKind::Hold => gestures.hold_begin(&surface, serial, time, fingers),
+        }
+    }
+
+    // `scale` and `rotation` are only used by pinches
+    pub fn gesture_update(
+        &mut self,
+        kind: GestureKind,
+        time: u32,
+        delta: Point<f64, Logical>,
+        scale: f64,
+        rotation: f64,
+    ) {
+        if let Some(gesture) = &mut self.captured_gesture {
+            gesture.delta += delta;
+            gesture.scale = scale;
+            let (kind, fingers, Point { x, y, .. }) =
+                (gesture.kind, gesture.fingers, gesture.delta);
+
+            // horizontal swipes bound to a workspace switch slide the workspaces along
+            let step = match kind {
+                GestureKind::Swipe if x.abs() > y.abs() => {
+                    let direction = if x < 0.0 { "left" } else { "right" };
+                    workspace_step(self.config.gesture_binding(kind.name(), fingers, direction))
+                }
+                _ => None,
+            };
+            match step {
+                Some(next) => self.update_workspace_swipe(
+                    self.workspaces.neighbour(next),
+                    x / WORKSPACE_SWIPE_DISTANCE,
+                ),
+                None => self.cancel_workspace_swipe(),
+            }
+            return;
+        }
+
+        match kind {
+            GestureKind::Swipe => self.pointer_gestures_state.swipe_update(time, delta),
+            GestureKind::Pinch => self
+                .pointer_gestures_state
+                .pinch_update(time, delta, scale, rotation),
+            GestureKind::Hold => {}
+        }
+    }
+
+    pub fn gesture_end(&mut self, kind: GestureKind, time: u32, cancelled: bool) {
+        if let Some(gesture) = self.captured_gesture.take() {
+            let action = gesture
+                .direction()
+                .filter(|_| !cancelled)
+                .and_then(|direction| {
+                    self.config
+                        .gesture_binding(gesture.kind.name(), gesture.fingers, direction)
+                })
+                .cloned();
+            // a switch snaps the swipe to the other workspace, anything else puts it back
+            if let Some(next) = workspace_step(action.as_ref()) {
+                if let Some(target) = self.workspaces.neighbour(next) {
+                    self.switch_workspace(target);
+                }
+            }
+            self.cancel_workspace_swipe();
+            match action {
+                Some(GestureAction::Spawn(command)) => {
+                    self.parse_keybindings(KeyAction::Spawn(command))
+                }
+                Some(GestureAction::Screenshot) => self.parse_keybindings(KeyAction::Screenshot {
+                    target: ScreenshotTarget::Output,
+                }),
+                Some(GestureAction::NextWorkspace | GestureAction::PreviousWorkspace) | None => {}
+            }
+            return;
+        }
+
+        let serial = SERIAL_COUNTER.next_serial();
+        let gestures = &mut self.pointer_gestures_state;
+        match kind {
+            GestureKind::Swipe => gestures.swipe_end(serial, time, cancelled),
+            GestureKind::Pinch => gestures.pinch_end(serial, time, cancelled),
+            GestureKind::Hold => gestures.hold_end(serial, time, cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gesture(kind: GestureKind, delta: (f64, f64), scale: f64) -> CapturedGesture {
+        CapturedGesture {
+            kind,
+            fingers: 3,
+            delta: delta.into(),
+            scale,
+        }
+    }
+
+    #[test]
+    fn swipes_go_where_the_fingers_went_most() {
+        let swipe = |x, y| gesture(GestureKind::Swipe, (x, y), 1.0).direction();
+        assert_eq!(swipe(-150.0, 40.0), Some("left"));
+        assert_eq!(swipe(150.0, -40.0), Some("right"));
+        assert_eq!(swipe(40.0, -150.0), Some("up"));
+        assert_eq!(swipe(-40.0, 150.0), Some("down"));
+    }
+
+    #[test]
+    fn short_swipes_go_nowhere() {
+        let swipe = |x, y| gesture(GestureKind::Swipe, (x, y), 1.0).direction();
+        assert_eq!(swipe(0.0, 0.0), None);
+        assert_eq!(swipe(-99.0, 60.0), None);
+        assert_eq!(swipe(100.0, 0.0), Some("right"));
+    }
+
+    #[test]
+    fn pinches_go_by_scale() {
+        let pinch = |scale| gesture(GestureKind::Pinch, (300.0, 0.0), scale).direction();
+        assert_eq!(pinch(0.5), Some("in"));
+        assert_eq!(pinch(2.0), Some("out"));
+        assert_eq!(pinch(1.1), None);
+        assert_eq!(pinch(0.9), None);
+        assert_eq!(
+            gesture(GestureKind::Hold, (300.0, 0.0), 2.0).direction(),
+            None
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use smithay::{
+    desktop::{layer_map_for_output, WindowSurfaceType},
+    reexports::{
+        calloop::timer::{TimeoutAction, Timer},
+        wayland_server::Resource,
+    },
+    wayland::compositor::get_parent,
+};
+
+use crate::{
+    delegate_idle_inhibit, delegate_idle_notify,
+    protocols::{
+        idle_inhibit::{IdleInhibitHandler, IdleInhibitState},
+        idle_notify::{IdleNotifierHandler, IdleNotifierState},
+    },
+    state::{Backend, Corrosion},
+};
+
+impl<BackendData: Backend + 'static> IdleNotifierHandler for Corrosion<BackendData> {
+    fn idle_notifier_state(&mut self) -> &mut IdleNotifierState {
+        &mut self.idle_notifier_state
+    }
+
+    fn notification_created(&mut self) {
+        self.schedule_idle_check();
+    }
+}
+
+delegate_idle_notify!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> IdleInhibitHandler for Corrosion<BackendData> {
+    fn idle_inhibit_state(&mut self) -> &mut IdleInhibitState {
+        &mut self.idle_inhibit_state
+    }
+
+    fn inhibitors_changed(&mut self) {
+        self.schedule_idle_check();
+    }
+}
+
+delegate_idle_inhibit!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Whether an inhibitor keeps us from going idle, inhibitors only count while their surface
+    // is shown on some output
+    pub fn idle_inhibited(&self) -> bool {
+        self.idle_inhibit_state
+            .surfaces()
+            .filter(|surface| surface.is_alive())
+            .any(|surface| {
+                let mut root = surface.clone();
+                while let Some(parent) = get_parent(&root) {
+                    root = parent;
+                }
+                let in_window = self
+                    .space
+                    .elements()
+                    .find(|window| window.toplevel().wl_surface() == &root)
+                    .map_or(false, |window| {
+                        !self.space.outputs_for_element(window).is_empty()
+                    });
+                in_window
+                    || self.space.outputs().any(|output| {
+                        layer_map_for_output(output)
+                            .layer_for_surface(&root, WindowSurfaceType::ALL)
+                            .is_some()
+                    })
+            })
+    }
+
+    // Sends out due idle notifications, returns how long until the next one is due
+    fn check_idle(&mut self) -> Option<Duration> {
+        if self.idle_inhibited() {
+            self.idle_notifier_state.notify_activity();
+        }
+        self.idle_notifier_state.poll()
+    }
+
+    // (Re)starts the timer that sends idle notifications, needed whenever a notification or
+    // inhibitor shows up or goes away
+    pub fn schedule_idle_check(&mut self) {
+        if let Some(token) = self.idle_check_timer.take() {
+            self.handle.remove(token);
+        }
+        let next = match self.check_idle() {
+            Some(next) => next,
+            None => return,
+        };
+
+        let token = self
+            .handle
+            .insert_source(Timer::from_duration(next), |_, _, data| {
+                match data.state.check_idle() {
+                    Some(next) => TimeoutAction::ToDuration(next),
+                    None => {
+                        data.state.idle_check_timer = None;
+                        TimeoutAction::Drop
+                    }
+                }
+            })
+            .expect("Unable to insert the idle timer into the event loop");
+        self.idle_check_timer = Some(token);
+    }
+}
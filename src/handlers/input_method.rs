@@ -0,0 +1,152 @@
+// Text inputs and the input method. The input method follows the keyboard focus, and its popups
+// sit under the text cursor of the focused window
+
+use smithay::{
+    desktop::utils::{bbox_from_surface_tree, send_frames_surface_tree},
+    output::Output,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{IsAlive, Logical, Point, Rectangle},
+};
+
+use crate::{
+    delegate_input_method, delegate_text_input,
+    protocols::{
+        input_method::{InputMethodHandler, InputMethodState, InputPopup},
+        text_input::{TextChange, TextInputHandler, TextInputState},
+    },
+    state::{Backend, Corrosion},
+};
+
+impl<BackendData: Backend + 'static> TextInputHandler for Corrosion<BackendData> {
+    fn text_input_state(&mut self) -> &mut TextInputState {
+        &mut self.text_input_state
+    }
+
+    fn text_input_updated(&mut self) {
+        self.update_input_method();
+    }
+}
+
+delegate_text_input!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> InputMethodHandler for Corrosion<BackendData> {
+    fn input_method_state(&mut self) -> &mut InputMethodState {
+        &mut self.input_method_state
+    }
+
+    // a new input method starts out with whatever text input is active
+    fn input_method_changed(&mut self) {
+        self.update_input_method();
+    }
+
+    fn input_method_commit(&mut self, change: TextChange) {
+        self.text_input_state.send_change(&change);
+    }
+
+    fn keyboard_grabbed(&mut self) {
+        self.send_input_method_keymap();
+    }
+
+    fn popups_changed(&mut self) {
+        self.update_input_popups();
+    }
+}
+
+delegate_input_method!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Passes the state of the active text input on to the input method, the focus or the text
+    // input changed
+    pub fn update_input_method(&mut self) {
+        let properties = self.text_input_state.active_properties();
+        self.input_method_state.set_text_input(properties.as_ref());
+        self.update_input_popups();
+    }
+
+    // The seat keyboard got another keymap, the grab has to read keys with it too
+    pub fn send_input_method_keymap(&mut self) {
+        if let Some(keymap) = self.keymap_string() {
+            let (rate, delay) = (
+                self.keyboard_config.repeat_rate,
+                self.keyboard_config.repeat_delay,
+            );
+            self.input_method_state.send_keymap(&keymap, rate, delay);
+        }
+    }
+
+    // The text cursor of the active text input in global coordinates, only windows have one
+    fn text_cursor_rectangle(&self) -> Option<Rectangle<i32, Logical>> {
+        let cursor = self
+            .text_input_state
+            .active_properties()?
+            .cursor_rectangle?;
+        let window = self.window_for_surface(self.text_input_state.focus()?)?;
+        let surface_location = self.space.element_location(&window)? - window.geometry().loc;
+        Some(Rectangle::from_loc_and_size(
+            cursor.loc + surface_location,
+            cursor.size,
+        ))
+    }
+
+    // Right below the text cursor, or above it if the popup doesn't fit on the output below
+    fn input_popup_location(
+        &self,
+        popup: &InputPopup,
+        cursor: Rectangle<i32, Logical>,
+    ) -> Point<i32, Logical> {
+        let size = bbox_from_surface_tree(popup.wl_surface(), (0, 0)).size;
+        let below = Point::from((cursor.loc.x, cursor.loc.y + cursor.size.h));
+        let output_bottom = self
+            .space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .find(|geometry| geometry.contains(cursor.loc))
+            .map(|geometry| geometry.loc.y + geometry.size.h);
+        match output_bottom {
+            Some(bottom) if below.y + size.h > bottom => {
+                (cursor.loc.x, cursor.loc.y - size.h).into()
+            }
+            _ => below,
+        }
+    }
+
+    // The popups with their global location, none while there is no text cursor to put them at
+    pub fn input_popups(&self) -> Vec<(WlSurface, Point<i32, Logical>)> {
+        let cursor = match self.text_cursor_rectangle() {
+            Some(cursor) => cursor,
+            None => return Vec::new(),
+        };
+        self.input_method_state
+            .popups()
+            .iter()
+            .filter(|popup| popup.wl_surface().alive())
+            .map(|popup| {
+                let location = self.input_popup_location(popup, cursor);
+                (popup.wl_surface().clone(), location)
+            })
+            .collect()
+    }
+
+    // Tells the popups where the text cursor is relative to them, after they or the cursor moved
+    pub fn update_input_popups(&mut self) {
+        if let Some(cursor) = self.text_cursor_rectangle() {
+            for popup in self.input_method_state.popups() {
+                let location = self.input_popup_location(popup, cursor);
+                popup.set_text_input_rectangle(Rectangle::from_loc_and_size(
+                    cursor.loc - location,
+                    cursor.size,
+                ));
+            }
+        }
+        self.schedule_render();
+    }
+
+    // The popups aren't part of the space, so their frame callbacks are sent here
+    pub fn send_input_popup_frames(&self, output: &Output) {
+        for (surface, _) in self.input_popups() {
+            send_frames_surface_tree(&surface, output, self.start_time.elapsed(), None, |_, _| {
+                Some(output.clone())
+            });
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use smithay::backend::session::Session;
 use smithay::input::keyboard::ModifiersState;
+use smithay::utils::{Logical, Rectangle};
 use std::process::Command;
 
 use crate::backend::UdevData;
@@ -34,6 +35,19 @@ pub enum KeyAction {
     Spawn(String),
     _Launcher(String),
     VTSwitch(i32),
+    Screenshot { target: ScreenshotTarget },
+    // the next layout of [input.keyboard]
+    NextLayout,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScreenshotTarget {
+    // the output under the pointer
+    Output,
+    // the focused window
+    Window,
+    // a rectangle in global coordinates, e.g. from slurp
+    Region(Rectangle<i32, Logical>),
 }
 
 impl Corrosion<UdevData> {
@@ -88,6 +102,12 @@ impl Corrosion<UdevData> {
                     tracing::error!("Error in switching virtual terminal: {}", err);
                 }
             }
+            KeyAction::Screenshot { target } => {
+                if let Err(err) = self.take_screenshot(target) {
+                    tracing::error!("Unable to take a screenshot: {}", err);
+                }
+            }
+            KeyAction::NextLayout => self.next_keyboard_layout(),
         };
     }
 }
@@ -0,0 +1,246 @@
+// The keymap and repeat rate of the seat keyboard. Keyboards can have their own [input] section,
+// so the keymap follows whichever keyboard was typed on last
+
+use smithay::{
+    input::keyboard::{xkb, KeyboardTarget, ModifiersState},
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{IsAlive, SERIAL_COUNTER},
+};
+
+use crate::{
+    config::KeyboardConfig,
+    state::{Backend, Corrosion},
+};
+
+// Lock and Mod2, where xkb keymaps put caps lock and num lock
+const CAPS_LOCK: u32 = 1 << 1;
+const NUM_LOCK: u32 = 1 << 4;
+
+// What the seat keyboard shows clients on top of smithay's xkb state, which only changes by
+// typing. Layouts are switched here, and the locked modifiers and layout carry over when the
+// keymap is reloaded
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyboardLocks {
+    // layouts to go on from the one xkb has locked
+    layout: u32,
+    // locked modifiers that are toggled from what xkb has
+    modifiers: u32,
+}
+
+// The modifiers as wl_keyboard.modifiers sends them
+pub fn serialize_modifiers(modifiers: &ModifiersState) -> (u32, u32, u32, u32) {
+    let mods = &modifiers.serialized;
+    (
+        mods.depressed,
+        mods.latched,
+        mods.locked,
+        mods.layout_locked,
+    )
+}
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // The first keyboard got plugged in. It starts out with the keymap of the last one that was
+    // used, and gets back the focus the last keyboard had
+    pub fn add_seat_keyboard(&mut self) {
+        let mut config = self.config.keyboard_config(self.keyboard_device.as_deref());
+        let keyboard = match self.seat.add_keyboard(
+            config.xkb_config(),
+            config.repeat_delay,
+            config.repeat_rate,
+        ) {
+            Ok(keyboard) => keyboard,
+            Err(err) => {
+                tracing::error!("Unable to load the keymap, using the default one: {}", err);
+                config = KeyboardConfig {
+                    repeat_delay: config.repeat_delay,
+                    repeat_rate: config.repeat_rate,
+                    ..Default::default()
+                };
+                self.seat
+                    .add_keyboard(config.xkb_config(), config.repeat_delay, config.repeat_rate)
+                    .unwrap()
+            }
+        };
+        if !config.same_keymap(&self.keyboard_config) {
+            self.keyboard_locks.layout = 0;
+        }
+        self.keyboard_config = config;
+        self.virtual_keymap = None;
+        self.send_input_method_keymap();
+
+        if let Some(focus) = self.keyboard_focus.take().filter(|focus| focus.alive()) {
+            keyboard.set_focus(self, Some(focus), SERIAL_COUNTER.next_serial());
+            self.resend_keyboard_locks(false);
+        }
+    }
+
+    // The last keyboard is gone, clients lose their wl_keyboards
+    pub fn remove_seat_keyboard(&mut self) {
+        if let Some(keyboard) = self.seat.get_keyboard() {
+            self.keep_keyboard_locks();
+            self.keyboard_focus = keyboard.current_focus();
+            self.seat.remove_keyboard();
+        }
+    }
+
+    // Kept for later while no keyboard is plugged in
+    pub fn set_keyboard_focus(&mut self, focus: Option<WlSurface>) {
+        match self.seat.get_keyboard() {
+            Some(keyboard) => {
+                keyboard.set_focus(self, focus, SERIAL_COUNTER.next_serial());
+                self.resend_keyboard_locks(false);
+            }
+            None => self.keyboard_focus = focus,
+        }
+    }
+
+    // A key was pressed on `device`, switches to its keymap if it has another one
+    pub fn use_keyboard(&mut self, device: &str) {
+        if self.virtual_keymap.is_none() && self.keyboard_device.as_deref() == Some(device) {
+            return;
+        }
+        self.keyboard_device = Some(device.to_string());
+        self.apply_keyboard_config();
+    }
+
+    // A virtual keyboard typed, its keys only make sense with the keymap it brought
+    pub fn use_virtual_keyboard(&mut self, device: &str, keymap: &str) {
+        if self.virtual_keymap.is_some() && self.keyboard_device.as_deref() == Some(device) {
+            return;
+        }
+        let keyboard = match self.seat.get_keyboard() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+        self.keep_keyboard_locks();
+        if let Err(err) = keyboard.set_keymap_from_string(self, keymap.to_string()) {
+            tracing::error!("Unable to load the keymap of {}: {}", device, err);
+            return;
+        }
+        self.keyboard_device = Some(device.to_string());
+        self.virtual_keymap = Some(keymap.to_string());
+        self.send_input_method_keymap();
+    }
+
+    // Picks up config changes. The keymap is only reloaded if it changed, the locked modifiers
+    // and layout stay, held keys don't
+    pub fn apply_keyboard_config(&mut self) {
+        let config = self.config.keyboard_config(self.keyboard_device.as_deref());
+        let keyboard = match self.seat.get_keyboard() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+
+        if self.virtual_keymap.is_some() || !config.same_keymap(&self.keyboard_config) {
+            self.keep_keyboard_locks();
+            if !config.same_keymap(&self.keyboard_config) {
+                self.keyboard_locks.layout = 0;
+            }
+            if let Err(err) = keyboard.set_xkb_config(self, config.xkb_config()) {
+                tracing::error!("Unable to load the keymap for '{}': {}", config.layout, err);
+                return;
+            }
+            self.virtual_keymap = None;
+            self.send_input_method_keymap();
+            self.resend_keyboard_locks(true);
+        }
+        keyboard.change_repeat_info(config.repeat_rate, config.repeat_delay);
+        self.keyboard_config = config;
+    }
+
+    // Locks the next layout of the keymap. Virtual keymaps have their own layouts, which are up
+    // to the program that typed
+    pub fn next_keyboard_layout(&mut self) {
+        if self.virtual_keymap.is_some() {
+            return;
+        }
+        let layouts = self.keyboard_config.layout_count();
+        self.keyboard_locks.layout = (self.keyboard_locks.layout + 1) % layouts;
+        if let Some(modifiers) = self.keyboard_modifiers() {
+            self.send_keyboard_modifiers(modifiers, true);
+        }
+    }
+
+    // The modifiers clients see, smithay's with the locks on top
+    pub fn keyboard_modifiers(&self) -> Option<ModifiersState> {
+        let modifiers = self.seat.get_keyboard()?.modifier_state();
+        Some(self.with_keyboard_locks(modifiers))
+    }
+
+    // Also for the modifiers an input filter gets, the keyboard can't be asked from in there
+    pub fn with_keyboard_locks(&self, mut modifiers: ModifiersState) -> ModifiersState {
+        if self.virtual_keymap.is_some() {
+            return modifiers;
+        }
+        let locks = self.keyboard_locks;
+        modifiers.serialized.locked ^= locks.modifiers;
+        modifiers.serialized.layout_locked = (modifiers.serialized.layout_locked + locks.layout)
+            % self.keyboard_config.layout_count();
+        modifiers.caps_lock ^= locks.modifiers & CAPS_LOCK != 0;
+        modifiers.num_lock ^= locks.modifiers & NUM_LOCK != 0;
+        modifiers
+    }
+
+    // Sends modifiers smithay doesn't know about to the focused client, or to the input method
+    // while it grabs the keyboard
+    pub fn send_keyboard_modifiers(&mut self, modifiers: ModifiersState, to_input_method: bool) {
+        let serial = SERIAL_COUNTER.next_serial();
+        if to_input_method && self.input_method_state.has_grab() && !self.is_locked() {
+            self.input_method_state
+                .send_modifiers(serial, serialize_modifiers(&modifiers));
+            return;
+        }
+        let focus = match self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+        {
+            Some(focus) => focus,
+            None => return,
+        };
+        let seat = self.seat.clone();
+        KeyboardTarget::modifiers(&focus, &seat, self, modifiers, serial);
+    }
+
+    // smithay sent its own modifiers, to a new focus or along with a key, they lack the locks
+    pub fn resend_keyboard_locks(&mut self, to_input_method: bool) {
+        if self.virtual_keymap.is_some() || self.keyboard_locks == KeyboardLocks::default() {
+            return;
+        }
+        if let Some(modifiers) = self.keyboard_modifiers() {
+            self.send_keyboard_modifiers(modifiers, to_input_method);
+        }
+    }
+
+    // The keymap is about to be reloaded and xkb starts over, so the locks take over what it had
+    fn keep_keyboard_locks(&mut self) {
+        if self.virtual_keymap.is_some() {
+            return;
+        }
+        if let Some(modifiers) = self.keyboard_modifiers() {
+            self.keyboard_locks = KeyboardLocks {
+                layout: modifiers.serialized.layout_locked,
+                modifiers: modifiers.serialized.locked,
+            };
+        }
+    }
+
+    // The keymap the seat keyboard has now, as text for sharing it with clients
+    pub fn keymap_string(&self) -> Option<String> {
+        if let Some(keymap) = &self.virtual_keymap {
+            return Some(keymap.clone());
+        }
+        let config = &self.keyboard_config;
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            &config.rules,
+            &config.model,
+            &config.layout,
+            &config.variant,
+            config.options.clone(),
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )?;
+        Some(keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1))
+    }
+}
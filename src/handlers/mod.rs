@@ -3,8 +3,19 @@
 
 // modules
 mod compositor;
+mod dnd;
+pub mod gestures;
+mod idle;
+mod input_method;
 pub mod keybindings;
+pub mod keyboard;
+mod pointer;
+pub mod selection;
+mod session_lock;
+mod window_management;
 mod wlr_layer;
+pub mod workspaces;
+mod xdg_activation;
 mod xdg_shell;
 
 // imports
@@ -14,11 +25,9 @@ use crate::Corrosion;
 // Wl Seat
 
 use smithay::input::{SeatHandler, SeatState};
-use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
-use smithay::wayland::data_device::{
-    ClientDndGrabHandler, DataDeviceHandler, ServerDndGrabHandler,
-};
-use smithay::{delegate_data_device, delegate_output, delegate_seat};
+use smithay::reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource};
+use smithay::wayland::{data_device::set_data_device_focus, primary_selection::set_primary_focus};
+use smithay::{delegate_output, delegate_seat, delegate_tablet_manager};
 
 impl<BackendData: Backend + 'static> SeatHandler for Corrosion<BackendData> {
     type KeyboardFocus = WlSurface;
@@ -35,25 +44,51 @@ impl<BackendData: Backend + 'static> SeatHandler for Corrosion<BackendData> {
     ) {
         *self.cursor_image_status.lock().unwrap() = image;
     }
-    fn focus_changed(&mut self, _seat: &smithay::input::Seat<Self>, _focused: Option<&WlSurface>) {}
-}
-
-delegate_seat!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+    fn focus_changed(&mut self, seat: &smithay::input::Seat<Self>, focused: Option<&WlSurface>) {
+        // only the focused client gets to see the selections
+        let client = focused.and_then(|surface| self.display_handle.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display_handle, seat, client.clone());
+        set_primary_focus(&self.display_handle, seat, client);
 
-//
-// Wl Data Device
-//
-
-impl<BackendData: Backend + 'static> DataDeviceHandler for Corrosion<BackendData> {
-    fn data_device_state(&self) -> &smithay::wayland::data_device::DataDeviceState {
-        &self.data_device_state
+        // a window stops being urgent once the user gets to it
+        if let Some(window) = focused.and_then(|surface| self.window_for_surface(surface)) {
+            self.set_urgent(&window, false);
+        }
+        // taskbars show which window is active
+        self.refresh_foreign_toplevels();
+        // the input method types into the focused surface
+        self.text_input_state.set_focus(focused);
+        self.update_input_method();
     }
 }
 
-impl<BackendData: Backend + 'static> ClientDndGrabHandler for Corrosion<BackendData> {}
-impl<BackendData: Backend + 'static> ServerDndGrabHandler for Corrosion<BackendData> {}
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // The seat only has the capabilities of the devices that are plugged in
+    pub fn set_seat_capabilities(&mut self, keyboard: bool, pointer: bool, touch: bool) {
+        match (keyboard, self.seat.get_keyboard().is_some()) {
+            (true, false) => self.add_seat_keyboard(),
+            (false, true) => self.remove_seat_keyboard(),
+            _ => {}
+        }
+        match (pointer, self.seat.get_pointer().is_some()) {
+            (true, false) => {
+                self.seat.add_pointer();
+            }
+            (false, true) => self.seat.remove_pointer(),
+            _ => {}
+        }
+        match (touch, self.seat.get_touch().is_some()) {
+            (true, false) => {
+                self.seat.add_touch();
+            }
+            (false, true) => self.seat.remove_touch(),
+            _ => {}
+        }
+    }
+}
 
-delegate_data_device!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+delegate_seat!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+delegate_tablet_manager!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
 
 //
 // Wl Output & Xdg Output
@@ -0,0 +1,124 @@
+use smithay::{
+    delegate_relative_pointer,
+    desktop::utils::bbox_from_surface_tree,
+    input::pointer::MotionEvent,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    utils::{Logical, Point, SERIAL_COUNTER},
+};
+
+use crate::{
+    delegate_pointer_constraints,
+    protocols::pointer_constraints::{
+        ConstraintKind, PointerConstraintsHandler, PointerConstraintsState,
+    },
+    state::{Backend, Corrosion},
+};
+
+impl<BackendData: Backend + 'static> PointerConstraintsHandler for Corrosion<BackendData> {
+    fn pointer_constraints_state(&mut self) -> &mut PointerConstraintsState {
+        &mut self.pointer_constraints_state
+    }
+
+    fn new_constraint(&mut self, _surface: &WlSurface) {
+        self.update_pointer_constraint();
+    }
+
+    fn cursor_position_hint(&mut self, surface: &WlSurface, location: Point<f64, Logical>) {
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        // the lock kept the pointer on the surface, so it should still be under it
+        let surface_location = match self.surface_under_pointer(&pointer) {
+            Some((under, surface_location)) if &under == surface => surface_location,
+            _ => return,
+        };
+
+        self.pointer_location = surface_location.to_f64() + location;
+        let under = self.surface_under_pointer(&pointer);
+        pointer.motion(
+            self,
+            under,
+            &MotionEvent {
+                location: self.pointer_location,
+                serial: SERIAL_COUNTER.next_serial(),
+                time: self.start_time.elapsed().as_millis() as u32,
+            },
+        );
+        self.schedule_render();
+    }
+}
+
+delegate_pointer_constraints!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+delegate_relative_pointer!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Where relative motion to `target` actually takes the pointer. A locked pointer stays where
+    // it is, a confined one gets clamped to its surface and region
+    pub fn constrain_pointer_motion(
+        &self,
+        under: Option<&(WlSurface, Point<i32, Logical>)>,
+        target: Point<f64, Logical>,
+    ) -> Point<f64, Logical> {
+        let (surface, surface_location) = match under {
+            Some(under) => under,
+            None => return target,
+        };
+        let constraint = match self.pointer_constraints_state.constraint(surface) {
+            Some(constraint) if constraint.is_active() => constraint,
+            _ => return target,
+        };
+
+        let current = self.pointer_location;
+        match constraint.kind() {
+            ConstraintKind::Locked => current,
+            ConstraintKind::Confined => {
+                let bbox = bbox_from_surface_tree(surface, *surface_location);
+                let target: Point<f64, Logical> = (
+                    target
+                        .x
+                        .max(bbox.loc.x as f64)
+                        .min((bbox.loc.x + bbox.size.w - 1) as f64),
+                    target
+                        .y
+                        .max(bbox.loc.y as f64)
+                        .min((bbox.loc.y + bbox.size.h - 1) as f64),
+                )
+                    .into();
+                // slide along the edge of the region if only one direction leaves it
+                let origin = surface_location.to_f64();
+                [
+                    target,
+                    (target.x, current.y).into(),
+                    (current.x, target.y).into(),
+                ]
+                .into_iter()
+                .find(|point| constraint.region_contains(*point - origin))
+                .unwrap_or(current)
+            }
+        }
+    }
+
+    // Applies the constraint of the surface under the pointer once the pointer is in its region,
+    // and lets go of every other one
+    pub fn update_pointer_constraint(&mut self) {
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+        let under = self.surface_under_pointer(&pointer);
+        self.pointer_constraints_state
+            .deactivate_except(under.as_ref().map(|(surface, _)| surface));
+
+        if let Some((surface, surface_location)) = under {
+            let location = self.pointer_location - surface_location.to_f64();
+            let inside = self
+                .pointer_constraints_state
+                .constraint(&surface)
+                .map_or(false, |constraint| constraint.region_contains(location));
+            if inside {
+                self.pointer_constraints_state.activate(&surface);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+// The clipboard and primary selection. Regular clients go through smithay, clipboard managers
+// through data control, and sometimes the compositor owns a selection itself
+
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    os::unix::{io::OwnedFd, net::UnixStream},
+    sync::Arc,
+};
+
+use smithay::{
+    delegate_data_device, delegate_primary_selection,
+    reexports::{
+        calloop::{generic::Generic, Interest, Mode, PostAction},
+        wayland_protocols::wp::primary_selection::zv1::server::zwp_primary_selection_source_v1::ZwpPrimarySelectionSourceV1,
+        wayland_server::protocol::wl_data_source::WlDataSource,
+    },
+    utils::{IsAlive, Serial, SERIAL_COUNTER},
+    wayland::{
+        data_device::{
+            self, clear_data_device_selection, request_data_device_client_selection,
+            set_data_device_selection, DataDeviceHandler, DataDeviceState,
+        },
+        primary_selection::{
+            self, clear_primary_selection, request_primary_client_selection, set_primary_selection,
+            PrimarySelectionHandler, PrimarySelectionState,
+        },
+    },
+};
+
+use crate::{
+    delegate_data_control,
+    protocols::data_control::{DataControlHandler, DataControlSource, DataControlState},
+    state::{Backend, Corrosion},
+};
+
+// Anything bigger than this isn't worth keeping around when its client quits
+const MAX_PERSISTED_SIZE: usize = 64 * 1024 * 1024;
+// Text, links and pictures, the rest is left to clipboard managers
+const PERSISTED_MIME_TYPES: [&str; 8] = [
+    "text/plain;charset=utf-8",
+    "text/plain",
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+    "text/uri-list",
+    "text/html",
+    "image/png",
+];
+
+// A selection that doesn't come from a regular client
+pub enum ServerSelection {
+    // data the compositor has itself, e.g. a screenshot or a kept copy of the clipboard
+    Data(Vec<(String, Arc<Vec<u8>>)>),
+    // a clipboard manager's source
+    DataControl(DataControlSource),
+}
+
+impl ServerSelection {
+    fn mime_types(&self) -> Vec<String> {
+        match self {
+            ServerSelection::Data(data) => data
+                .iter()
+                .map(|(mime_type, _)| mime_type.clone())
+                .collect(),
+            ServerSelection::DataControl(source) => source.mime_types(),
+        }
+    }
+
+    // Writes the data for `mime_type` to `fd`
+    fn send(&self, mime_type: String, fd: OwnedFd) {
+        match self {
+            ServerSelection::Data(data) => {
+                let data = match data.iter().find(|(offered, _)| *offered == mime_type) {
+                    Some((_, data)) => data.clone(),
+                    None => return,
+                };
+                // the reader may be slow, so don't block the compositor on it
+                std::thread::spawn(move || {
+                    if let Err(err) = File::from(fd).write_all(&data) {
+                        tracing::warn!("Unable to send the selection: {}", err);
+                    }
+                });
+            }
+            ServerSelection::DataControl(source) => source.send(mime_type, fd),
+        }
+    }
+
+    fn cancel(&self) {
+        if let ServerSelection::DataControl(source) = self {
+            source.cancel();
+        }
+    }
+}
+
+// A copy of a client's clipboard, offered in its place once `source` is gone
+pub struct ClipboardCopy {
+    serial: Serial,
+    source: WlDataSource,
+    // mime types that are still being read
+    pending: usize,
+    data: Vec<(String, Arc<Vec<u8>>)>,
+}
+
+impl<BackendData: Backend + 'static> DataDeviceHandler for Corrosion<BackendData> {
+    fn data_device_state(&self) -> &DataDeviceState {
+        &self.data_device_state
+    }
+
+    // a client took over the clipboard
+    fn new_selection(&mut self, source: Option<WlDataSource>) {
+        if let Some(old) = self.server_selection.take() {
+            old.cancel();
+        }
+        self.clipboard_copy = None;
+        let mime_types = source.as_ref().and_then(|source| {
+            data_device::with_source_metadata(source, |metadata| metadata.mime_types.clone()).ok()
+        });
+        self.data_control_state.set_selection::<Self>(
+            &self.display_handle,
+            mime_types.clone(),
+            false,
+        );
+
+        if let (Some(source), Some(mime_types)) = (source, mime_types) {
+            let mime_types: Vec<String> = mime_types
+                .into_iter()
+                .filter(|mime_type| PERSISTED_MIME_TYPES.contains(&mime_type.as_str()))
+                .collect();
+            if self.config.get_clipboard().persist && !mime_types.is_empty() {
+                // the new source only becomes the selection once this returns
+                self.handle
+                    .insert_idle(move |data| data.state.persist_clipboard(source, mime_types));
+            }
+        }
+    }
+
+    // a client pastes a selection we offered, e.g. a screenshot
+    fn send_selection(&mut self, mime_type: String, fd: OwnedFd) {
+        if let Some(selection) = &self.server_selection {
+            selection.send(mime_type, fd);
+        }
+    }
+}
+
+delegate_data_device!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> PrimarySelectionHandler for Corrosion<BackendData> {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+
+    // a client selected something, middle click pastes it
+    fn new_selection(&mut self, source: Option<ZwpPrimarySelectionSourceV1>) {
+        if let Some(old) = self.server_primary_selection.take() {
+            old.cancel();
+        }
+        let mime_types = source.and_then(|source| {
+            primary_selection::with_source_metadata(&source, |metadata| metadata.mime_types.clone())
+                .ok()
+        });
+        self.data_control_state
+            .set_selection::<Self>(&self.display_handle, mime_types, true);
+    }
+
+    fn send_selection(&mut self, mime_type: String, fd: OwnedFd) {
+        if let Some(selection) = &self.server_primary_selection {
+            selection.send(mime_type, fd);
+        }
+    }
+}
+
+delegate_primary_selection!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> DataControlHandler for Corrosion<BackendData> {
+    fn data_control_state(&mut self) -> &mut DataControlState {
+        &mut self.data_control_state
+    }
+
+    fn set_selection(&mut self, source: Option<DataControlSource>, primary: bool) {
+        self.set_server_selection(source.map(ServerSelection::DataControl), primary);
+    }
+
+    fn receive_selection(&mut self, mime_type: String, fd: OwnedFd, primary: bool) {
+        let server_selection = if primary {
+            &self.server_primary_selection
+        } else {
+            &self.server_selection
+        };
+        if let Some(selection) = server_selection {
+            selection.send(mime_type, fd);
+            return;
+        }
+
+        let result = if primary {
+            request_primary_client_selection(&self.seat, mime_type, fd)
+                .map_err(|err| err.to_string())
+        } else {
+            request_data_device_client_selection(&self.seat, mime_type, fd)
+                .map_err(|err| err.to_string())
+        };
+        if let Err(err) = result {
+            tracing::warn!(
+                "Unable to read the selection for a clipboard manager: {}",
+                err
+            );
+        }
+    }
+}
+
+delegate_data_control!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Makes the compositor own the clipboard or primary selection, None clears it
+    pub fn set_server_selection(&mut self, selection: Option<ServerSelection>, primary: bool) {
+        let mime_types = selection.as_ref().map(ServerSelection::mime_types);
+        let old = if primary {
+            std::mem::replace(&mut self.server_primary_selection, selection)
+        } else {
+            self.clipboard_copy = None;
+            std::mem::replace(&mut self.server_selection, selection)
+        };
+        if let Some(old) = old {
+            old.cancel();
+        }
+
+        let dh = &self.display_handle;
+        match (mime_types.clone(), primary) {
+            (Some(mime_types), false) => set_data_device_selection(dh, &self.seat, mime_types),
+            (Some(mime_types), true) => set_primary_selection(dh, &self.seat, mime_types),
+            (None, false) => clear_data_device_selection(dh, &self.seat),
+            (None, true) => clear_primary_selection(dh, &self.seat),
+        }
+        self.data_control_state
+            .set_selection::<Self>(dh, mime_types, primary);
+    }
+
+    // Reads a client's clipboard while the client is still there to send it, so it can be
+    // offered once the client quits. The client keeps the clipboard until then
+    fn persist_clipboard(&mut self, source: WlDataSource, mime_types: Vec<String>) {
+        let serial = SERIAL_COUNTER.next_serial();
+        self.clipboard_copy = Some(ClipboardCopy {
+            serial,
+            source,
+            pending: mime_types.len(),
+            data: Vec::new(),
+        });
+
+        for mime_type in mime_types {
+            let (reader, writer) = match UnixStream::pair().and_then(|(reader, writer)| {
+                reader.set_nonblocking(true)?;
+                Ok((reader, writer))
+            }) {
+                Ok(pair) => pair,
+                Err(err) => {
+                    tracing::warn!("Unable to copy the clipboard: {}", err);
+                    self.clipboard_copy = None;
+                    return;
+                }
+            };
+            if let Err(err) =
+                request_data_device_client_selection(&self.seat, mime_type.clone(), writer.into())
+            {
+                tracing::warn!("Unable to copy the clipboard: {}", err);
+                self.clipboard_copy = None;
+                return;
+            }
+
+            let mut data = Vec::new();
+            let result = self.handle.insert_source(
+                Generic::new(reader, Interest::READ, Mode::Level),
+                move |_, reader, calloop_data| {
+                    let mut chunk = [0u8; 4096];
+                    let complete = loop {
+                        match reader.read(&mut chunk) {
+                            Ok(0) => break true,
+                            Ok(read) => {
+                                data.extend_from_slice(&chunk[..read]);
+                                if data.len() > MAX_PERSISTED_SIZE {
+                                    break false;
+                                }
+                            }
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                                return Ok(PostAction::Continue)
+                            }
+                            Err(_) => break false,
+                        }
+                    };
+                    let data = complete.then(|| std::mem::take(&mut data));
+                    calloop_data
+                        .state
+                        .clipboard_copied(serial, mime_type.clone(), data);
+                    Ok(PostAction::Remove)
+                },
+            );
+            if let Err(err) = result {
+                tracing::error!(
+                    "Unable to insert the clipboard copy into the event loop: {}",
+                    err
+                );
+                self.clipboard_copy = None;
+                return;
+            }
+        }
+    }
+
+    // One mime type of a clipboard copy is done, None if it couldn't be read
+    fn clipboard_copied(&mut self, serial: Serial, mime_type: String, data: Option<Vec<u8>>) {
+        let copy = match &mut self.clipboard_copy {
+            // the clipboard changed in the meantime
+            Some(copy) if copy.serial == serial => copy,
+            _ => return,
+        };
+        copy.pending -= 1;
+        if let Some(data) = data {
+            copy.data.push((mime_type, Arc::new(data)));
+        }
+
+        if copy.pending == 0 && copy.data.is_empty() {
+            self.clipboard_copy = None;
+        }
+        // the client may have quit while it was read
+        self.take_over_clipboard();
+    }
+
+    // Offers the copy of the clipboard once the source it came from is gone. This runs whenever
+    // the event loop wakes up, smithay doesn't tell us when a source is destroyed
+    pub fn take_over_clipboard(&mut self) {
+        match &self.clipboard_copy {
+            Some(copy) if copy.pending == 0 && !copy.source.alive() => {}
+            _ => return,
+        }
+        let copy = self.clipboard_copy.take().unwrap();
+        tracing::debug!("Keeping a copy of the clipboard");
+        self.set_server_selection(Some(ServerSelection::Data(copy.data)), false);
+    }
+}
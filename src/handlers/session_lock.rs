@@ -0,0 +1,91 @@
+use smithay::{desktop::utils::send_frames_surface_tree, output::Output};
+
+use crate::{
+    delegate_session_lock,
+    protocols::session_lock::{LockSurface, SessionLockHandler, SessionLockState},
+    state::{Backend, Corrosion},
+};
+
+impl<BackendData: Backend + 'static> SessionLockHandler for Corrosion<BackendData> {
+    fn session_lock_state(&mut self) -> &mut SessionLockState {
+        &mut self.session_lock_state
+    }
+
+    fn lock(&mut self) {
+        tracing::info!("Locking the session");
+        self.set_keyboard_focus(None);
+        self.schedule_render();
+    }
+
+    fn unlock(&mut self) {
+        tracing::info!("Unlocking the session");
+        // give the focus back to the topmost window
+        let focus = self
+            .space
+            .elements()
+            .last()
+            .map(|window| window.toplevel().wl_surface().clone());
+        self.set_keyboard_focus(focus);
+        self.schedule_render();
+    }
+
+    fn new_lock_surface(&mut self, surface: LockSurface) {
+        if let Some(geometry) = self.space.output_geometry(surface.output()) {
+            surface.configure(geometry.size);
+        }
+        // the first lock surface, or the one under the pointer, gets the keyboard
+        let focused = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus())
+            .is_some();
+        let under_pointer = self
+            .space
+            .output_geometry(surface.output())
+            .map_or(false, |geometry| {
+                geometry.to_f64().contains(self.pointer_location)
+            });
+        if !focused || under_pointer {
+            self.set_keyboard_focus(Some(surface.wl_surface().clone()));
+        }
+        self.schedule_render();
+    }
+
+    fn lock_surface_destroyed(&mut self, surface: LockSurface) {
+        let focused = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus());
+        if focused.as_ref() == Some(surface.wl_surface()) {
+            let focus = self
+                .space
+                .outputs()
+                .find_map(|output| self.session_lock_state.lock_surface(output))
+                .map(|surface| surface.wl_surface().clone());
+            self.set_keyboard_focus(focus);
+        }
+        self.schedule_render();
+    }
+}
+
+delegate_session_lock!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // While locked only lock surfaces are shown and get input
+    pub fn is_locked(&self) -> bool {
+        self.session_lock_state.is_locked()
+    }
+
+    // Lock surfaces aren't part of the space, so their frame callbacks are sent here
+    pub fn send_lock_surface_frames(&self, output: &Output) {
+        if let Some(surface) = self.session_lock_state.lock_surface(output) {
+            send_frames_surface_tree(
+                surface.wl_surface(),
+                output,
+                self.start_time.elapsed(),
+                None,
+                |_, _| Some(output.clone()),
+            );
+        }
+    }
+}
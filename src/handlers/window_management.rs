@@ -0,0 +1,299 @@
+use std::sync::Mutex;
+
+use smithay::{
+    desktop::{layer_map_for_output, Window},
+    output::Output,
+    reexports::{
+        wayland_protocols::xdg::shell::server::xdg_toplevel,
+        wayland_server::protocol::wl_surface::WlSurface,
+    },
+    utils::{Logical, Rectangle, SERIAL_COUNTER},
+    wayland::{compositor::with_states, shell::xdg::XdgToplevelSurfaceData},
+};
+
+use crate::{
+    delegate_foreign_toplevel,
+    protocols::foreign_toplevel::{ForeignToplevelHandler, ForeignToplevelState, ToplevelInfo},
+    state::{Backend, Corrosion},
+};
+
+// Where a maximized or fullscreen window was before, so it can go back there
+#[derive(Default)]
+struct RestoreGeometry(Mutex<Option<Rectangle<i32, Logical>>>);
+
+impl<BackendData: Backend + 'static> ForeignToplevelHandler for Corrosion<BackendData> {
+    fn foreign_toplevel_state(&mut self) -> &mut ForeignToplevelState {
+        &mut self.foreign_toplevel_state
+    }
+
+    fn activate(&mut self, surface: WlSurface) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            self.set_window_minimized(&window, false);
+            self.focus_window(&window);
+        }
+    }
+
+    fn close(&mut self, surface: WlSurface) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            window.toplevel().send_close();
+        }
+    }
+
+    fn set_maximized(&mut self, surface: WlSurface, maximized: bool) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            self.set_window_minimized(&window, false);
+            self.set_window_maximized(&window, maximized);
+        }
+    }
+
+    fn set_minimized(&mut self, surface: WlSurface, minimized: bool) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            self.set_window_minimized(&window, minimized);
+        }
+    }
+
+    fn set_fullscreen(&mut self, surface: WlSurface, output: Option<Output>, fullscreen: bool) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            self.set_window_minimized(&window, false);
+            self.set_window_fullscreen(&window, output, fullscreen);
+        }
+    }
+}
+
+delegate_foreign_toplevel!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // The window of a toplevel surface, minimized or not
+    pub fn window_for_surface(&self, surface: &WlSurface) -> Option<Window> {
+        self.space
+            .elements()
+            .chain(self.minimized_windows.iter().map(|(window, _)| window))
+            .chain(self.workspaces.hidden_windows())
+            .find(|window| window.toplevel().wl_surface() == surface)
+            .cloned()
+    }
+
+    // Raises the window and gives it the keyboard, switching to its workspace first
+    pub fn focus_window(&mut self, window: &Window) {
+        if self.is_locked() {
+            return;
+        }
+        self.show_workspace_of(window);
+        self.space.raise_element(window, true);
+        self.set_keyboard_focus(Some(window.toplevel().wl_surface().clone()));
+        self.space.elements().for_each(|window| {
+            window.toplevel().send_configure();
+        });
+        self.refresh_foreign_toplevels();
+        self.schedule_render();
+    }
+
+    pub fn set_window_maximized(&mut self, window: &Window, maximized: bool) {
+        let geometry = match self.window_output(window, None) {
+            // maximized windows leave room for panels
+            Some(output) if maximized => {
+                let location = self.space.output_geometry(&output).unwrap().loc;
+                let mut zone = layer_map_for_output(&output).non_exclusive_zone();
+                zone.loc += location;
+                Some(zone)
+            }
+            _ => None,
+        };
+        self.set_window_state(window, xdg_toplevel::State::Maximized, geometry);
+    }
+
+    pub fn set_window_fullscreen(
+        &mut self,
+        window: &Window,
+        output: Option<Output>,
+        fullscreen: bool,
+    ) {
+        let geometry = match self.window_output(window, output) {
+            Some(output) if fullscreen => self.space.output_geometry(&output),
+            _ => None,
+        };
+        self.set_window_state(window, xdg_toplevel::State::Fullscreen, geometry);
+    }
+
+    // Minimized windows are taken out of the space until they get restored
+    pub fn set_window_minimized(&mut self, window: &Window, minimized: bool) {
+        let index = self
+            .minimized_windows
+            .iter()
+            .position(|(other, _)| other == window);
+        match (index, minimized) {
+            (None, true) => {
+                let location = match self.space.element_location(window) {
+                    Some(location) => location,
+                    None => return,
+                };
+                self.space.unmap_elem(window);
+                self.minimized_windows.push((window.clone(), location));
+
+                let focused = self
+                    .seat
+                    .get_keyboard()
+                    .and_then(|keyboard| keyboard.current_focus());
+                if focused.as_ref() == Some(window.toplevel().wl_surface()) {
+                    if let Some(keyboard) = self.seat.get_keyboard() {
+                        keyboard.set_focus(self, None, SERIAL_COUNTER.next_serial());
+                    }
+                }
+            }
+            (Some(index), false) => {
+                let (window, location) = self.minimized_windows.remove(index);
+                self.space.map_element(window.clone(), location, false);
+                self.focus_window(&window);
+            }
+            _ => return,
+        }
+        self.refresh_foreign_toplevels();
+        self.schedule_render();
+    }
+
+    // Sets or unsets a maximized/fullscreen state. With a geometry the window is moved there and
+    // its old place is remembered, without one it goes back to that place
+    fn set_window_state(
+        &mut self,
+        window: &Window,
+        state: xdg_toplevel::State,
+        geometry: Option<Rectangle<i32, Logical>>,
+    ) {
+        window
+            .user_data()
+            .insert_if_missing_threadsafe(RestoreGeometry::default);
+        let mut restore = window
+            .user_data()
+            .get::<RestoreGeometry>()
+            .unwrap()
+            .0
+            .lock()
+            .unwrap();
+
+        let target = match geometry {
+            Some(geometry) => {
+                if restore.is_none() {
+                    let location = self.space.element_location(window).unwrap_or_default();
+                    *restore = Some(Rectangle::from_loc_and_size(
+                        location,
+                        window.geometry().size,
+                    ));
+                }
+                Some(geometry)
+            }
+            None => {
+                let other = match state {
+                    xdg_toplevel::State::Maximized => xdg_toplevel::State::Fullscreen,
+                    _ => xdg_toplevel::State::Maximized,
+                };
+                // leaving fullscreen for a maximized window keeps the old place around
+                if window.toplevel().current_state().states.contains(other) {
+                    None
+                } else {
+                    restore.take()
+                }
+            }
+        };
+        drop(restore);
+
+        window.toplevel().with_pending_state(|pending| {
+            if geometry.is_some() {
+                pending.states.set(state);
+            } else {
+                pending.states.unset(state);
+            }
+            if let Some(target) = target {
+                pending.size = Some(target.size);
+            }
+        });
+        window.toplevel().send_configure();
+        if let Some(target) = target {
+            self.space.map_element(window.clone(), target.loc, true);
+        }
+        self.refresh_foreign_toplevels();
+        self.schedule_render();
+    }
+
+    // The output to put a window on: the requested one, the one it's on, or the one the
+    // pointer is on
+    fn window_output(&self, window: &Window, output: Option<Output>) -> Option<Output> {
+        output
+            .or_else(|| self.space.outputs_for_element(window).into_iter().next())
+            .or_else(|| {
+                self.space
+                    .output_under(self.pointer_location)
+                    .next()
+                    .cloned()
+            })
+            .or_else(|| self.space.outputs().next().cloned())
+    }
+
+    // Tells taskbars about the current windows, called whenever one of them might have changed
+    pub fn refresh_foreign_toplevels(&mut self) {
+        self.minimized_windows
+            .retain(|(window, _)| window.toplevel().alive());
+        self.workspaces.forget_dead_windows();
+        let toplevels = self.toplevel_infos();
+        self.foreign_toplevel_state
+            .refresh::<Self>(&self.display_handle, toplevels);
+    }
+
+    // Every window there is, the ones on the active workspace first
+    pub fn toplevel_infos(&self) -> Vec<ToplevelInfo> {
+        let focus = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus());
+
+        let mapped = self.space.elements().map(|window| (window, false));
+        let minimized = self
+            .minimized_windows
+            .iter()
+            .map(|(window, _)| (window, true));
+        // the ones a workspace swipe brought in are in the space already
+        let hidden = self
+            .workspaces
+            .hidden_windows()
+            .filter(|window| self.space.element_location(window).is_none())
+            .map(|window| (window, false));
+        mapped
+            .chain(minimized)
+            .chain(hidden)
+            .filter(|(window, _)| window.toplevel().alive())
+            .map(|(window, minimized)| {
+                let surface = window.toplevel().wl_surface().clone();
+                let (title, app_id) = with_states(&surface, |states| {
+                    let data = states
+                        .data_map
+                        .get::<XdgToplevelSurfaceData>()
+                        .unwrap()
+                        .lock()
+                        .unwrap();
+                    (data.title.clone(), data.app_id.clone())
+                });
+                // what we asked for, the client may not have committed it yet and nothing
+                // refreshes the list once it does
+                let (maximized, fullscreen) = window.toplevel().with_pending_state(|state| {
+                    (
+                        state.states.contains(xdg_toplevel::State::Maximized),
+                        state.states.contains(xdg_toplevel::State::Fullscreen),
+                    )
+                });
+                ToplevelInfo {
+                    activated: focus.as_ref() == Some(&surface),
+                    surface,
+                    title: title.unwrap_or_default(),
+                    app_id: app_id.unwrap_or_default(),
+                    maximized,
+                    minimized,
+                    fullscreen,
+                    outputs: if minimized {
+                        Vec::new()
+                    } else {
+                        self.space.outputs_for_element(window)
+                    },
+                }
+            })
+            .collect()
+    }
+}
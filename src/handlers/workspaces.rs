@@ -0,0 +1,193 @@
+// Workspaces. Only the windows of the active one are in the space, the others are kept aside
+// with their places like minimized windows are
+// TODO: make the number of workspaces configurable
+
+use smithay::{
+    desktop::Window,
+    utils::{IsAlive, Logical, Point},
+};
+
+use crate::state::{Backend, Corrosion};
+
+const WORKSPACE_COUNT: usize = 4;
+
+pub struct Workspaces {
+    active: usize,
+    // the windows of every workspace but the active one, with where they go once it's shown
+    hidden: Vec<Vec<(Window, Point<i32, Logical>)>>,
+    swipe: Option<WorkspaceSwipe>,
+}
+
+// A switch that follows the fingers, the windows of both workspaces are in the space until the
+// fingers are lifted
+struct WorkspaceSwipe {
+    // None while the fingers point to where there is no workspace
+    target: Option<usize>,
+    // where the windows of the active workspace were when it started
+    origin: Vec<(Window, Point<i32, Logical>)>,
+    // how far the windows go for a whole switch, the width of the output under the pointer
+    width: i32,
+}
+
+impl Default for Workspaces {
+    fn default() -> Self {
+        Self {
+            active: 0,
+            hidden: vec![Vec::new(); WORKSPACE_COUNT],
+            swipe: None,
+        }
+    }
+}
+
+impl Workspaces {
+    // The workspace after or before the active one, None past the last or first
+    pub fn neighbour(&self, next: bool) -> Option<usize> {
+        if next {
+            Some(self.active + 1).filter(|workspace| *workspace < self.hidden.len())
+        } else {
+            self.active.checked_sub(1)
+        }
+    }
+
+    // Windows that aren't shown because their workspace isn't active
+    pub fn hidden_windows(&self) -> impl Iterator<Item = &Window> {
+        self.hidden.iter().flatten().map(|(window, _)| window)
+    }
+
+    // The workspace of a hidden window
+    fn workspace_of(&self, window: &Window) -> Option<usize> {
+        self.hidden
+            .iter()
+            .position(|windows| windows.iter().any(|(other, _)| other == window))
+    }
+
+    pub fn forget_dead_windows(&mut self) {
+        for windows in &mut self.hidden {
+            windows.retain(|(window, _)| window.alive());
+        }
+    }
+}
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Shows another workspace right away, the topmost window on it gets the keyboard
+    pub fn switch_workspace(&mut self, target: usize) {
+        self.cancel_workspace_swipe();
+        let active = self.workspaces.active;
+        if target == active || target >= self.workspaces.hidden.len() {
+            return;
+        }
+
+        let shown: Vec<Window> = self.space.elements().cloned().collect();
+        for window in shown {
+            let location = self.space.element_location(&window).unwrap_or_default();
+            self.space.unmap_elem(&window);
+            self.workspaces.hidden[active].push((window, location));
+        }
+        for (window, location) in std::mem::take(&mut self.workspaces.hidden[target]) {
+            self.space.map_element(window, location, false);
+        }
+        self.workspaces.active = target;
+
+        match self.space.elements().last().cloned() {
+            Some(window) => self.focus_window(&window),
+            None => {
+                self.set_keyboard_focus(None);
+                self.refresh_foreign_toplevels();
+                self.schedule_render();
+            }
+        }
+    }
+
+    // Switches to the workspace of a window that is on another one
+    pub fn show_workspace_of(&mut self, window: &Window) {
+        if let Some(workspace) = self.workspaces.workspace_of(window) {
+            if workspace != self.workspaces.active {
+                self.switch_workspace(workspace);
+            }
+        }
+    }
+
+    // Slides the active workspace `progress` output widths to the side, negative is left, and
+    // brings `target` in from the other side. The first update starts the swipe
+    pub fn update_workspace_swipe(&mut self, target: Option<usize>, progress: f64) {
+        let mut swipe = match self.workspaces.swipe.take() {
+            Some(swipe) => swipe,
+            None => {
+                let width = self
+                    .space
+                    .output_under(self.pointer_location)
+                    .next()
+                    .or_else(|| self.space.outputs().next())
+                    .and_then(|output| self.space.output_geometry(output))
+                    .map_or(0, |geometry| geometry.size.w);
+                let origin = self
+                    .space
+                    .elements()
+                    .map(|window| {
+                        let location = self.space.element_location(window).unwrap_or_default();
+                        (window.clone(), location)
+                    })
+                    .collect();
+                WorkspaceSwipe {
+                    target: None,
+                    origin,
+                    width,
+                }
+            }
+        };
+
+        // the fingers went back past where they started
+        if swipe.target != target {
+            if let Some(old) = swipe.target {
+                for (window, _) in &self.workspaces.hidden[old] {
+                    self.space.unmap_elem(window);
+                }
+            }
+            swipe.target = target;
+        }
+
+        // mapping in stacking order keeps the stacking order
+        let offset = (progress.clamp(-1.0, 1.0) * swipe.width as f64).round() as i32;
+        for (window, location) in swipe.origin.iter().filter(|(window, _)| window.alive()) {
+            self.space
+                .map_element(window.clone(), *location + Point::from((offset, 0)), false);
+        }
+        if let Some(target) = target {
+            let side = if progress < 0.0 {
+                swipe.width
+            } else {
+                -swipe.width
+            };
+            for (window, location) in &self.workspaces.hidden[target] {
+                self.space.map_element(
+                    window.clone(),
+                    *location + Point::from((offset + side, 0)),
+                    false,
+                );
+            }
+        }
+
+        self.workspaces.swipe = Some(swipe);
+        self.schedule_render();
+    }
+
+    // Puts the windows of a swipe back where they were before it started
+    pub fn cancel_workspace_swipe(&mut self) {
+        let swipe = match self.workspaces.swipe.take() {
+            Some(swipe) => swipe,
+            None => return,
+        };
+
+        if let Some(target) = swipe.target {
+            for (window, _) in &self.workspaces.hidden[target] {
+                self.space.unmap_elem(window);
+            }
+        }
+        for (window, location) in swipe.origin {
+            if window.alive() {
+                self.space.map_element(window, location, false);
+            }
+        }
+        self.schedule_render();
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use smithay::{
+    delegate_xdg_activation,
+    desktop::Window,
+    input::Seat,
+    reexports::wayland_server::protocol::wl_surface::WlSurface,
+    wayland::xdg_activation::{
+        XdgActivationHandler, XdgActivationState, XdgActivationToken, XdgActivationTokenData,
+    },
+};
+
+use crate::state::{Backend, Corrosion};
+
+// Launchers hand their token to the app they start, which may take a while to show up
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl<BackendData: Backend + 'static> XdgActivationHandler for Corrosion<BackendData> {
+    fn activation_state(&mut self) -> &mut XdgActivationState {
+        &mut self.xdg_activation_state
+    }
+
+    fn request_activation(
+        &mut self,
+        token: XdgActivationToken,
+        token_data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        if let Some(window) = self.window_for_surface(&surface) {
+            if self.activation_valid(&token_data) {
+                self.set_window_minimized(&window, false);
+                self.focus_window(&window);
+            } else {
+                // no stealing the focus, but the user gets to know the window wants it
+                self.set_urgent(&window, true);
+            }
+        }
+        self.xdg_activation_state.remove_request(&token);
+    }
+
+    fn destroy_activation(
+        &mut self,
+        _token: XdgActivationToken,
+        _token_data: XdgActivationTokenData,
+        _surface: WlSurface,
+    ) {
+    }
+}
+
+delegate_xdg_activation!(@<BackendData: Backend + 'static> Corrosion<BackendData>);
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // A token is only good if it was made from the last thing the user pressed, on our seat, not
+    // too long ago. Anything the user did in between means they moved on
+    fn activation_valid(&self, token_data: &XdgActivationTokenData) -> bool {
+        if self.is_locked() || token_data.timestamp.elapsed() > ACTIVATION_TIMEOUT {
+            return false;
+        }
+        match &token_data.serial {
+            Some((serial, seat)) => {
+                Seat::<Self>::from_resource(seat).as_ref() == Some(&self.seat)
+                    && Some(*serial) == self.last_input_serial
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_urgent(&self, window: &Window) -> bool {
+        self.urgent_windows.contains(window)
+    }
+
+    // Urgent windows get a border until they are focused
+    pub fn set_urgent(&mut self, window: &Window, urgent: bool) {
+        if urgent == self.is_urgent(window) {
+            return;
+        }
+        if urgent {
+            self.urgent_windows.push(window.clone());
+        } else {
+            self.urgent_windows.retain(|other| other != window);
+        }
+        self.schedule_render();
+    }
+}
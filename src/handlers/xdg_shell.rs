@@ -5,10 +5,11 @@ use smithay::{
         pointer::{Focus, GrabStartData as PointerGrabStartData},
         Seat,
     },
+    output::Output,
     reexports::{
         wayland_protocols::xdg::shell::server::xdg_toplevel::{self},
         wayland_server::{
-            protocol::{wl_seat, wl_surface::WlSurface},
+            protocol::{wl_output::WlOutput, wl_seat, wl_surface::WlSurface},
             Resource,
         },
     },
@@ -45,6 +46,7 @@ impl<BackendData: Backend> XdgShellHandler for Corrosion<BackendData> {
         });
         window.toplevel().send_configure();
         self.space.map_element(window, (0, 0), false);
+        self.refresh_foreign_toplevels();
     }
 
     fn new_popup(&mut self, surface: PopupSurface, _positioner: PositionerState) {
@@ -121,6 +123,61 @@ impl<BackendData: Backend> XdgShellHandler for Corrosion<BackendData> {
         }
     }
 
+    // taskbars show these
+    fn title_changed(&mut self, _surface: ToplevelSurface) {
+        self.refresh_foreign_toplevels();
+    }
+
+    fn app_id_changed(&mut self, _surface: ToplevelSurface) {
+        self.refresh_foreign_toplevels();
+    }
+
+    fn toplevel_destroyed(&mut self, surface: ToplevelSurface) {
+        self.minimized_windows
+            .retain(|(window, _)| window.toplevel() != &surface);
+        self.urgent_windows
+            .retain(|window| window.toplevel() != &surface);
+        self.refresh_foreign_toplevels();
+        // nothing commits anymore, but the window still has to disappear from the screen
+        self.schedule_render();
+    }
+
+    fn maximize_request(&mut self, surface: ToplevelSurface) {
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_window_maximized(&window, true),
+            // a configure is owed either way
+            None => surface.send_configure(),
+        }
+    }
+
+    fn unmaximize_request(&mut self, surface: ToplevelSurface) {
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_window_maximized(&window, false),
+            None => surface.send_configure(),
+        }
+    }
+
+    fn fullscreen_request(&mut self, surface: ToplevelSurface, output: Option<WlOutput>) {
+        let output = output.as_ref().and_then(Output::from_resource);
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_window_fullscreen(&window, output, true),
+            None => surface.send_configure(),
+        }
+    }
+
+    fn unfullscreen_request(&mut self, surface: ToplevelSurface) {
+        match self.window_for_surface(surface.wl_surface()) {
+            Some(window) => self.set_window_fullscreen(&window, None, false),
+            None => surface.send_configure(),
+        }
+    }
+
+    fn minimize_request(&mut self, surface: ToplevelSurface) {
+        if let Some(window) = self.window_for_surface(surface.wl_surface()) {
+            self.set_window_minimized(&window, true);
+        }
+    }
+
     fn grab(&mut self, _surface: PopupSurface, _seat: wl_seat::WlSeat, _serial: Serial) {
         // TODO popup grabs
     }
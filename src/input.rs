@@ -1,110 +1,51 @@
 use smithay::{
     backend::input::{
-        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Event, InputBackend, InputEvent,
-        KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
+        AbsolutePositionEvent, Axis, AxisSource, ButtonState, Device, DeviceCapability, Event,
+        GestureBeginEvent, GestureEndEvent, GesturePinchUpdateEvent, GestureSwipeUpdateEvent,
+        InputBackend, InputEvent, KeyState, KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent,
+        PointerMotionEvent, ProximityState, TabletToolButtonEvent, TabletToolEvent,
+        TabletToolProximityEvent, TabletToolTipEvent, TabletToolTipState, TouchEvent,
     },
     input::{
-        keyboard::{keysyms, FilterResult},
+        keyboard::{keysyms, FilterResult, ModifiersState},
         pointer::{AxisFrame, ButtonEvent, Focus, GrabStartData, MotionEvent, RelativeMotionEvent},
     },
-    reexports::wayland_server::protocol::wl_surface::WlSurface,
-    utils::{Logical, Point, SERIAL_COUNTER},
+    utils::{Logical, Point, Serial, Size, SERIAL_COUNTER},
+    wayland::tablet_manager::{TabletDescriptor, TabletSeatTrait},
 };
 
 use crate::{
     backend::UdevData,
     grabs::{resize_grab::ResizeEdge, MoveSurfaceGrab, ResizeSurfaceGrab},
-    handlers::keybindings::{self, KeyAction},
+    handlers::{
+        gestures::GestureKind,
+        keybindings::{self, KeyAction, ScreenshotTarget},
+        keyboard::serialize_modifiers,
+    },
     state::Corrosion,
-    CorrosionConfig,
 };
 
 impl Corrosion<UdevData> {
     pub fn process_input_event<I: InputBackend>(&mut self, event: InputEvent<I>) {
+        self.notify_activity();
+        let pointer_location = self.pointer_location;
+        let tablet_tool_location = self.tablet_tool_location;
+
         match event {
             InputEvent::Keyboard { event, .. } => {
-                let serial = SERIAL_COUNTER.next_serial();
-                let time = Event::time_msec(&event);
-                let press_state = event.state();
-                let action = self.seat.get_keyboard().unwrap().input::<KeyAction, _>(
-                    self,
+                // keyboards can have keymaps of their own
+                self.use_keyboard(&event.device().name());
+                self.keyboard_key(
                     event.key_code(),
-                    press_state,
-                    serial,
-                    time,
-                    |_, modifier, handle| {
-                        let action: KeyAction;
-                        if keybindings::get_mod_key_and_compare(modifier)
-                            && press_state == KeyState::Pressed
-                        {
-                            // our shitty keybindings
-                            // TODO: get rid of this shit
-                            let corrosion_config = CorrosionConfig::new();
-                            let defaults = corrosion_config.get_defaults();
-                            if handle.modified_sym() == keysyms::KEY_h | keysyms::KEY_H {
-                                tracing::info!("running wofi");
-                                let launcher = &defaults.launcher;
-                                action = KeyAction::_Launcher(launcher.to_string());
-                            } else if handle.modified_sym() == keysyms::KEY_q | keysyms::KEY_Q {
-                                tracing::info!("Quitting");
-                                action = KeyAction::Quit;
-                            } else if handle.modified_sym() == keysyms::KEY_Return {
-                                tracing::info!("spawn terminal");
-                                let terminal = &defaults.terminal;
-                                action = KeyAction::Spawn(terminal.to_string());
-                            } else if handle.modified_sym() == keysyms::KEY_x | keysyms::KEY_X {
-                                // TODO: make it so you can close windows
-                                action = KeyAction::_CloseWindow;
-                            } else if (keysyms::KEY_XF86Switch_VT_1..=keysyms::KEY_XF86Switch_VT_12)
-                                .contains(&handle.modified_sym())
-                            {
-                                action = KeyAction::VTSwitch(
-                                    (handle.modified_sym() - keysyms::KEY_XF86Switch_VT_1 + 1)
-                                        as i32,
-                                )
-                            } else {
-                                return FilterResult::Forward;
-                            }
-                        } else {
-                            return FilterResult::Forward;
-                        }
-                        FilterResult::Intercept(action)
-                    },
+                    event.state(),
+                    Event::time_msec(&event),
+                    true,
                 );
-                if let Some(action) = action {
-                    self.parse_keybindings(action);
-                }
             }
             InputEvent::PointerMotion { event } => {
-                let serial = SERIAL_COUNTER.next_serial();
-
-                self.pointer_location += event.delta();
-                self.pointer_location = self.clamp_coords(self.pointer_location);
-                let surface_under = self.surface_under_pointer(&self.seat.get_pointer().unwrap());
-                if let Some(pointer) = self.seat.get_pointer() {
-                    pointer.motion(
-                        self,
-                        surface_under.clone(),
-                        &MotionEvent {
-                            location: self.pointer_location,
-                            serial,
-                            time: event.time_msec(),
-                        },
-                    );
-                    pointer.relative_motion(
-                        self,
-                        surface_under.clone(),
-                        &RelativeMotionEvent {
-                            delta: event.delta(),
-                            delta_unaccel: event.delta_unaccel(),
-                            utime: event.time(),
-                        },
-                    )
-                }
+                self.pointer_motion(event.delta(), event.delta_unaccel(), event.time())
             }
             InputEvent::PointerMotionAbsolute { event, .. } => {
-                let serial = SERIAL_COUNTER.next_serial();
-
                 let max_x = self.space.outputs().fold(0, |acc, o| {
                     acc + self.space.output_geometry(o).unwrap().size.w
                 });
@@ -117,108 +58,11 @@ impl Corrosion<UdevData> {
 
                 let max_y = self.space.output_geometry(max_h_output).unwrap().size.h;
 
-                self.pointer_location.x = event.x_transformed(max_x);
-                self.pointer_location.y = event.y_transformed(max_y);
-
-                // clamp to screen limits
-                self.pointer_location = self.clamp_coords(self.pointer_location);
-
-                let under = self.surface_under_pointer(&self.seat.get_pointer().unwrap());
-                if let Some(ptr) = self.seat.get_pointer() {
-                    ptr.motion(
-                        self,
-                        under,
-                        &MotionEvent {
-                            location: self.pointer_location,
-                            serial,
-                            time: event.time_msec(),
-                        },
-                    );
-                }
+                let location = (event.x_transformed(max_x), event.y_transformed(max_y)).into();
+                self.pointer_motion_absolute(location, event.time_msec());
             }
             InputEvent::PointerButton { event, .. } => {
-                let pointer = self.seat.get_pointer().unwrap();
-                let keyboard = self.seat.get_keyboard().unwrap();
-
-                let serial = SERIAL_COUNTER.next_serial();
-
-                let button = event.button_code();
-
-                let button_state = event.state();
-
-                if ButtonState::Pressed == button_state && !pointer.is_grabbed() {
-                    if let Some((window, _loc)) = self
-                        .space
-                        .element_under(pointer.current_location())
-                        .map(|(w, l)| (w.clone(), l))
-                    {
-                        self.space.raise_element(&window, true);
-                        keyboard.set_focus(
-                            self,
-                            Some(window.toplevel().wl_surface().clone()),
-                            serial,
-                        );
-                        self.space.elements().for_each(|window| {
-                            window.toplevel().send_configure();
-                        });
-
-                        // Check for compositor initiated move grab
-                        if self.seat.get_keyboard().unwrap().modifier_state().logo {
-                            let start_data = GrabStartData {
-                                focus: None,
-                                button,
-                                location: pointer.current_location(),
-                            };
-
-                            let initial_window_location =
-                                self.space.element_location(&window).unwrap();
-
-                            let edges = ResizeEdge::all();
-
-                            let initial_rect = &window.geometry();
-
-                            match button {
-                                0x110 => {
-                                    let move_grab = MoveSurfaceGrab {
-                                        start_data,
-                                        window,
-                                        initial_window_location,
-                                    };
-
-                                    pointer.set_grab(self, move_grab, serial, Focus::Clear);
-                                }
-                                0x111 => {
-                                    let resize_grab = ResizeSurfaceGrab::start(
-                                        start_data,
-                                        window,
-                                        edges,
-                                        *initial_rect,
-                                    );
-                                    pointer.set_grab(self, resize_grab, serial, Focus::Clear);
-                                }
-                                _ => (),
-                            }
-                        };
-                    } else if let Some((window, _loc)) = self.surface_under_pointer(&pointer) {
-                        keyboard.set_focus(self, Some(window), serial);
-                    } else {
-                        self.space.elements().for_each(|window| {
-                            window.set_activated(false);
-                            window.toplevel().send_configure();
-                        });
-                        keyboard.set_focus(self, Option::<WlSurface>::None, serial);
-                    }
-                };
-
-                pointer.button(
-                    self,
-                    &ButtonEvent {
-                        button,
-                        state: button_state,
-                        serial,
-                        time: event.time_msec(),
-                    },
-                );
+                self.pointer_button(event.button_code(), event.state(), event.time_msec())
             }
             InputEvent::PointerAxis { event, .. } => {
                 let source = event.source();
@@ -250,11 +94,544 @@ impl Corrosion<UdevData> {
                     frame = frame.stop(Axis::Vertical);
                 }
 
-                self.seat.get_pointer().unwrap().axis(self, frame);
+                if let Some(pointer) = self.seat.get_pointer() {
+                    pointer.axis(self, frame);
+                }
+            }
+            InputEvent::TouchDown { event } => {
+                if let Some(location) = self.absolute_location(&event.device().name(), |size| {
+                    event.position_transformed(size)
+                }) {
+                    let serial = SERIAL_COUNTER.next_serial();
+                    self.last_input_serial = Some(serial);
+                    self.focus_under(location);
+
+                    let under = self.surface_under(location);
+                    if let (Some(touch), Some((surface, surface_location))) =
+                        (self.seat.get_touch(), under)
+                    {
+                        touch.down(
+                            serial,
+                            event.time_msec(),
+                            &surface,
+                            surface_location,
+                            event.slot(),
+                            location,
+                        );
+                    }
+                }
+            }
+            InputEvent::TouchMotion { event } => {
+                // the point stays on the surface it went down on
+                let location = self.absolute_location(&event.device().name(), |size| {
+                    event.position_transformed(size)
+                });
+                if let (Some(touch), Some(location)) = (self.seat.get_touch(), location) {
+                    touch.motion(event.time_msec(), event.slot(), location);
+                }
+            }
+            InputEvent::TouchUp { event } => {
+                if let Some(touch) = self.seat.get_touch() {
+                    let serial = SERIAL_COUNTER.next_serial();
+                    touch.up(serial, event.time_msec(), event.slot());
+                }
+            }
+            InputEvent::TouchCancel { .. } => {
+                if let Some(touch) = self.seat.get_touch() {
+                    touch.cancel();
+                }
+            }
+            InputEvent::TouchFrame { .. } => {
+                if let Some(touch) = self.seat.get_touch() {
+                    touch.frame();
+                }
+            }
+            InputEvent::DeviceAdded { device } => {
+                if device.has_capability(DeviceCapability::TabletTool) {
+                    self.seat
+                        .tablet_seat()
+                        .add_tablet::<Self>(&self.display_handle, &TabletDescriptor::from(&device));
+                }
+            }
+            InputEvent::DeviceRemoved { device } => {
+                if device.has_capability(DeviceCapability::TabletTool) {
+                    let tablet_seat = self.seat.tablet_seat();
+                    tablet_seat.remove_tablet(&TabletDescriptor::from(&device));
+                    // tools can go from one tablet to another, so they stay until the last is gone
+                    if tablet_seat.count_tablets() == 0 {
+                        tablet_seat.clear_tools();
+                        self.tablet_tool_location = None;
+                    }
+                }
+            }
+            InputEvent::TabletToolProximity { event } => {
+                let tablet_seat = self.seat.tablet_seat();
+                tablet_seat.add_tool::<Self>(&self.display_handle, &event.tool());
+                let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&event.device()));
+                let tool = tablet_seat.get_tool(&event.tool());
+                let location = self.absolute_location(&event.device().name(), |size| {
+                    event.position_transformed(size)
+                });
+
+                if let (Some(tablet), Some(tool), Some(location)) = (tablet, tool, location) {
+                    match event.state() {
+                        ProximityState::In => {
+                            self.tablet_tool_location = Some(location);
+                            if let Some(under) = self.surface_under(location) {
+                                tool.proximity_in(
+                                    location,
+                                    under,
+                                    &tablet,
+                                    SERIAL_COUNTER.next_serial(),
+                                    event.time_msec(),
+                                );
+                            }
+                        }
+                        ProximityState::Out => {
+                            self.tablet_tool_location = None;
+                            tool.proximity_out(event.time_msec());
+                        }
+                    }
+                }
+            }
+            InputEvent::TabletToolAxis { event } => {
+                let tablet_seat = self.seat.tablet_seat();
+                let tablet = tablet_seat.get_tablet(&TabletDescriptor::from(&event.device()));
+                let tool = tablet_seat.get_tool(&event.tool());
+                let location = self.absolute_location(&event.device().name(), |size| {
+                    event.position_transformed(size)
+                });
+
+                if let (Some(tablet), Some(tool), Some(location)) = (tablet, tool, location) {
+                    self.tablet_tool_location = Some(location);
+                    if event.pressure_has_changed() {
+                        tool.pressure(event.pressure());
+                    }
+                    if event.distance_has_changed() {
+                        tool.distance(event.distance());
+                    }
+                    if event.tilt_has_changed() {
+                        tool.tilt(event.tilt());
+                    }
+                    if event.slider_has_changed() {
+                        tool.slider_position(event.slider_position());
+                    }
+                    if event.rotation_has_changed() {
+                        tool.rotation(event.rotation());
+                    }
+                    if event.wheel_has_changed() {
+                        tool.wheel(event.wheel_delta(), event.wheel_delta_discrete());
+                    }
+                    tool.motion(
+                        location,
+                        self.surface_under(location),
+                        &tablet,
+                        SERIAL_COUNTER.next_serial(),
+                        event.time_msec(),
+                    );
+                }
+            }
+            InputEvent::TabletToolTip { event } => {
+                if let Some(tool) = self.seat.tablet_seat().get_tool(&event.tool()) {
+                    match event.tip_state() {
+                        TabletToolTipState::Down => {
+                            let serial = SERIAL_COUNTER.next_serial();
+                            self.last_input_serial = Some(serial);
+                            tool.tip_down(serial, event.time_msec());
+                            // touching a window with the pen focuses it, like a click
+                            if let Some(location) = self.tablet_tool_location {
+                                self.focus_under(location);
+                            }
+                        }
+                        TabletToolTipState::Up => tool.tip_up(event.time_msec()),
+                    }
+                }
+            }
+            InputEvent::TabletToolButton { event } => {
+                if let Some(tool) = self.seat.tablet_seat().get_tool(&event.tool()) {
+                    tool.button(
+                        event.button(),
+                        event.button_state(),
+                        SERIAL_COUNTER.next_serial(),
+                        event.time_msec(),
+                    );
+                }
+            }
+            InputEvent::GestureSwipeBegin { event } => {
+                self.gesture_begin(GestureKind::Swipe, event.fingers(), event.time_msec())
+            }
+            InputEvent::GestureSwipeUpdate { event } => self.gesture_update(
+                GestureKind::Swipe,
+                event.time_msec(),
+                (event.delta_x(), event.delta_y()).into(),
+                1.0,
+                0.0,
+            ),
+            InputEvent::GestureSwipeEnd { event } => {
+                self.gesture_end(GestureKind::Swipe, event.time_msec(), event.cancelled())
+            }
+            InputEvent::GesturePinchBegin { event } => {
+                self.gesture_begin(GestureKind::Pinch, event.fingers(), event.time_msec())
+            }
+            InputEvent::GesturePinchUpdate { event } => self.gesture_update(
+                GestureKind::Pinch,
+                event.time_msec(),
+                (event.delta_x(), event.delta_y()).into(),
+                event.scale(),
+                event.rotation(),
+            ),
+            InputEvent::GesturePinchEnd { event } => {
+                self.gesture_end(GestureKind::Pinch, event.time_msec(), event.cancelled())
+            }
+            InputEvent::GestureHoldBegin { event } => {
+                self.gesture_begin(GestureKind::Hold, event.fingers(), event.time_msec())
+            }
+            InputEvent::GestureHoldEnd { event } => {
+                self.gesture_end(GestureKind::Hold, event.time_msec(), event.cancelled())
             }
             _ => {}
         }
+
+        // Only the cursors are drawn by us, everything else repaints when its client commits
+        if self.pointer_location != pointer_location {
+            self.schedule_render_at(pointer_location);
+            self.schedule_render_at(self.pointer_location);
+        }
+        if self.tablet_tool_location != tablet_tool_location {
+            for location in [tablet_tool_location, self.tablet_tool_location]
+                .into_iter()
+                .flatten()
+            {
+                self.schedule_render_at(location);
+            }
+        }
+    }
+
+    // Virtual keyboards type through here as well, so keybindings work from both
+    // `to_input_method` is false for keys the input method typed itself, they'd go round in circles
+    pub fn keyboard_key(
+        &mut self,
+        keycode: u32,
+        press_state: KeyState,
+        time: u32,
+        to_input_method: bool,
+    ) {
+        let serial = SERIAL_COUNTER.next_serial();
+        if press_state == KeyState::Pressed {
+            self.last_input_serial = Some(serial);
+        }
+        let keyboard = match self.seat.get_keyboard() {
+            Some(keyboard) => keyboard,
+            None => return,
+        };
+        let modifiers = serialize_modifiers(&keyboard.modifier_state());
+        let action = keyboard.input::<Option<KeyAction>, _>(
+            self,
+            keycode,
+            press_state,
+            serial,
+            time,
+            |state, modifier, handle| {
+                let action: KeyAction;
+                if keybindings::get_mod_key_and_compare(modifier)
+                    && press_state == KeyState::Pressed
+                {
+                    // our shitty keybindings
+                    // TODO: get rid of this shit
+                    let defaults = state.config.get_defaults();
+                    if handle.modified_sym() == keysyms::KEY_h | keysyms::KEY_H {
+                        tracing::info!("running wofi");
+                        let launcher = &defaults.launcher;
+                        action = KeyAction::_Launcher(launcher.to_string());
+                    } else if handle.modified_sym() == keysyms::KEY_q | keysyms::KEY_Q {
+                        tracing::info!("Quitting");
+                        action = KeyAction::Quit;
+                    } else if handle.modified_sym() == keysyms::KEY_Return {
+                        tracing::info!("spawn terminal");
+                        let terminal = &defaults.terminal;
+                        action = KeyAction::Spawn(terminal.to_string());
+                    } else if handle.modified_sym() == keysyms::KEY_x | keysyms::KEY_X {
+                        // TODO: make it so you can close windows
+                        action = KeyAction::_CloseWindow;
+                    } else if handle.modified_sym() == keysyms::KEY_space {
+                        action = KeyAction::NextLayout;
+                    } else if handle.modified_sym() == keysyms::KEY_p {
+                        action = KeyAction::Screenshot {
+                            target: ScreenshotTarget::Output,
+                        };
+                    } else if handle.modified_sym() == keysyms::KEY_P {
+                        // shift takes a screenshot of the focused window instead
+                        action = KeyAction::Screenshot {
+                            target: ScreenshotTarget::Window,
+                        };
+                    } else if (keysyms::KEY_XF86Switch_VT_1..=keysyms::KEY_XF86Switch_VT_12)
+                        .contains(&handle.modified_sym())
+                    {
+                        action = KeyAction::VTSwitch(
+                            (handle.modified_sym() - keysyms::KEY_XF86Switch_VT_1 + 1) as i32,
+                        )
+                    } else {
+                        return state.forward_key(
+                            modifier,
+                            serial,
+                            time,
+                            keycode,
+                            press_state,
+                            to_input_method,
+                        );
+                    }
+                } else {
+                    return state.forward_key(
+                        modifier,
+                        serial,
+                        time,
+                        keycode,
+                        press_state,
+                        to_input_method,
+                    );
+                }
+                // the lock surface gets every key, only switching VTs still works
+                if state.is_locked() && !matches!(action, KeyAction::VTSwitch(_)) {
+                    return FilterResult::Forward;
+                }
+                FilterResult::Intercept(Some(action))
+            },
+        );
+        // smithay sent the new modifiers without the locks
+        if serialize_modifiers(&keyboard.modifier_state()) != modifiers {
+            self.resend_keyboard_locks(to_input_method);
+        }
+        if let Some(Some(action)) = action {
+            self.parse_keybindings(action);
+        }
+        self.update_keyboard_leds();
+    }
+
+    // Keys that aren't bound go to the input method while it grabs the keyboard, it types the
+    // result into the focused client through text-input. The lock surface still gets them first
+    fn forward_key(
+        &mut self,
+        modifiers: &ModifiersState,
+        serial: Serial,
+        time: u32,
+        keycode: u32,
+        press_state: KeyState,
+        to_input_method: bool,
+    ) -> FilterResult<Option<KeyAction>> {
+        if !to_input_method || !self.input_method_state.has_grab() || self.is_locked() {
+            return FilterResult::Forward;
+        }
+        let modifiers = self.with_keyboard_locks(*modifiers);
+        self.input_method_state
+            .send_modifiers(serial, serialize_modifiers(&modifiers));
+        self.input_method_state
+            .send_key(serial, time, keycode, press_state == KeyState::Pressed);
+        FilterResult::Intercept(None)
+    }
+
+    // `utime` is in microseconds
+    pub fn pointer_motion(
+        &mut self,
+        delta: Point<f64, Logical>,
+        delta_unaccel: Point<f64, Logical>,
+        utime: u64,
+    ) {
+        let serial = SERIAL_COUNTER.next_serial();
+        // the last pointer may have gone away with events still queued
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        // a locked or confined pointer doesn't go everywhere, relative motion is still sent
+        let previous_under = self.surface_under_pointer(&pointer);
+        let target = self.clamp_coords(self.pointer_location + delta);
+        let location = self.constrain_pointer_motion(previous_under.as_ref(), target);
+        let moved = location != self.pointer_location;
+        self.pointer_location = location;
+        let surface_under = self.surface_under_pointer(&pointer);
+        if moved {
+            pointer.motion(
+                self,
+                surface_under.clone(),
+                &MotionEvent {
+                    location: self.pointer_location,
+                    serial,
+                    time: (utime / 1000) as u32,
+                },
+            );
+        }
+        pointer.relative_motion(
+            self,
+            surface_under,
+            &RelativeMotionEvent {
+                delta,
+                delta_unaccel,
+                utime,
+            },
+        );
+        self.update_pointer_constraint();
     }
+
+    pub fn pointer_motion_absolute(&mut self, location: Point<f64, Logical>, time: u32) {
+        let serial = SERIAL_COUNTER.next_serial();
+
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        // clamp to screen limits
+        self.pointer_location = self.clamp_coords(location);
+
+        let under = self.surface_under_pointer(&pointer);
+        pointer.motion(
+            self,
+            under,
+            &MotionEvent {
+                location: self.pointer_location,
+                serial,
+                time,
+            },
+        );
+        self.update_pointer_constraint();
+    }
+
+    pub fn pointer_button(&mut self, button: u32, button_state: ButtonState, time: u32) {
+        let pointer = match self.seat.get_pointer() {
+            Some(pointer) => pointer,
+            None => return,
+        };
+
+        let serial = SERIAL_COUNTER.next_serial();
+
+        if button_state == ButtonState::Pressed {
+            self.last_input_serial = Some(serial);
+        }
+
+        if ButtonState::Pressed == button_state && self.is_locked() {
+            // clicking a lock surface focuses it, windows stay where they are
+            if let Some((surface, _loc)) = self.surface_under_pointer(&pointer) {
+                self.set_keyboard_focus(Some(surface));
+            }
+        } else if ButtonState::Pressed == button_state && !pointer.is_grabbed() {
+            if let Some((window, _loc)) = self
+                .space
+                .element_under(pointer.current_location())
+                .map(|(w, l)| (w.clone(), l))
+            {
+                self.space.raise_element(&window, true);
+                // an active window doesn't commit when it gets raised
+                self.schedule_render_for_surface(window.toplevel().wl_surface());
+                self.set_keyboard_focus(Some(window.toplevel().wl_surface().clone()));
+                self.space.elements().for_each(|window| {
+                    window.toplevel().send_configure();
+                });
+
+                // Check for compositor initiated move grab
+                let logo = self
+                    .seat
+                    .get_keyboard()
+                    .map_or(false, |keyboard| keyboard.modifier_state().logo);
+                if logo {
+                    let start_data = GrabStartData {
+                        focus: None,
+                        button,
+                        location: pointer.current_location(),
+                    };
+
+                    let initial_window_location = self.space.element_location(&window).unwrap();
+
+                    let edges = ResizeEdge::all();
+
+                    let initial_rect = &window.geometry();
+
+                    match button {
+                        0x110 => {
+                            let move_grab = MoveSurfaceGrab {
+                                start_data,
+                                window,
+                                initial_window_location,
+                            };
+
+                            pointer.set_grab(self, move_grab, serial, Focus::Clear);
+                        }
+                        0x111 => {
+                            let resize_grab =
+                                ResizeSurfaceGrab::start(start_data, window, edges, *initial_rect);
+                            pointer.set_grab(self, resize_grab, serial, Focus::Clear);
+                        }
+                        _ => (),
+                    }
+                };
+            } else if let Some((window, _loc)) = self.surface_under_pointer(&pointer) {
+                self.set_keyboard_focus(Some(window));
+            } else {
+                self.space.elements().for_each(|window| {
+                    window.set_activated(false);
+                    window.toplevel().send_configure();
+                });
+                self.set_keyboard_focus(None);
+            }
+        };
+
+        pointer.button(
+            self,
+            &ButtonEvent {
+                button,
+                state: button_state,
+                serial,
+                time,
+            },
+        );
+    }
+
+    // Touchscreens and tablets cover a single output, the one configured for the device or else
+    // the first. `position` maps the device coordinates into a size
+    fn absolute_location(
+        &self,
+        device: &str,
+        position: impl FnOnce(Size<i32, Logical>) -> Point<f64, Logical>,
+    ) -> Option<Point<f64, Logical>> {
+        let output = self
+            .config
+            .input_config(device)
+            .and_then(|config| config.map_to_output.as_ref())
+            .and_then(|name| self.space.outputs().find(|output| &output.name() == name))
+            .or_else(|| self.space.outputs().next())?;
+        let geometry = self.space.output_geometry(output)?;
+
+        // the panel doesn't rotate with the output, so touches are turned along with it
+        let transform = output.current_transform();
+        let size = transform.invert().transform_size(geometry.size);
+        let location = transform.transform_point_in(position(size), &size.to_f64());
+        Some(geometry.loc.to_f64() + location)
+    }
+
+    // A tap focuses and raises whatever is under it, the same as a click
+    fn focus_under(&mut self, location: Point<f64, Logical>) {
+        let window = self
+            .space
+            .element_under(location)
+            .map(|(window, _)| window.clone());
+
+        if self.is_locked() {
+            if let Some((surface, _loc)) = self.surface_under(location) {
+                self.set_keyboard_focus(Some(surface));
+            }
+        } else if let Some(window) = window {
+            self.focus_window(&window);
+        } else if let Some((surface, _loc)) = self.surface_under(location) {
+            self.set_keyboard_focus(Some(surface));
+        } else {
+            self.space.elements().for_each(|window| {
+                window.set_activated(false);
+                window.toplevel().send_configure();
+            });
+            self.set_keyboard_focus(None);
+        }
+    }
+
     fn clamp_coords(&self, pos: Point<f64, Logical>) -> Point<f64, Logical> {
         if self.space.outputs().next().is_none() {
             return pos;
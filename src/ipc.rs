@@ -0,0 +1,367 @@
+// A line based control socket. Every line sent to it is one command, which gets answered with
+// a line that is either "ok" or "error: <reason>". Queries send their lines before the "ok".
+// Commands:
+//   dpms on|off [output]
+//   screenshot output|window|region <x>,<y> <width>x<height>
+//   windows, one "<app id>\t<title>\t<states>" line per window, with the states comma
+//     separated out of focused, urgent, minimized, maximized and fullscreen
+// The socket path is exported to children as $CORROSIONWM_SOCK, `corrosionwm --msg "dpms off"`
+// sends a single command to it
+
+use std::{
+    env,
+    ffi::OsStr,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use smithay::{
+    reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction},
+    utils::{Logical, Rectangle},
+};
+
+use crate::{handlers::keybindings::ScreenshotTarget, state::Backend, CalloopData, Corrosion};
+
+pub const SOCKET_ENV: &str = "CORROSIONWM_SOCK";
+
+// longer lines than this can't be a command
+const MAX_LINE_LENGTH: usize = 4096;
+
+#[derive(Debug, PartialEq)]
+pub enum IpcCommand {
+    // turns every output, or only the named one, on or off
+    Dpms { on: bool, output: Option<String> },
+    // the region is in slurp's format, so `screenshot region $(slurp)` works
+    Screenshot(ScreenshotTarget),
+    Windows,
+}
+
+impl FromStr for IpcCommand {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("dpms") => {
+                let on = match words.next() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    _ => return Err(String::from("usage: dpms on|off [output]")),
+                };
+                Ok(IpcCommand::Dpms {
+                    on,
+                    output: words.next().map(String::from),
+                })
+            }
+            Some("screenshot") => {
+                let target = match words.next() {
+                    Some("output") => ScreenshotTarget::Output,
+                    Some("window") => ScreenshotTarget::Window,
+                    Some("region") => {
+                        let region = words.collect::<Vec<_>>().join(" ");
+                        ScreenshotTarget::Region(parse_region(&region).ok_or_else(|| {
+                            String::from("usage: screenshot region <x>,<y> <width>x<height>")
+                        })?)
+                    }
+                    _ => {
+                        return Err(String::from(
+                            "usage: screenshot output|window|region <x>,<y> <width>x<height>",
+                        ))
+                    }
+                };
+                Ok(IpcCommand::Screenshot(target))
+            }
+            Some("windows") => Ok(IpcCommand::Windows),
+            Some(command) => Err(format!("unknown command '{}'", command)),
+            None => Err(String::from("empty command")),
+        }
+    }
+}
+
+// "<x>,<y> <width>x<height>"
+fn parse_region(region: &str) -> Option<Rectangle<i32, Logical>> {
+    let (position, size) = region.trim().split_once(' ')?;
+    let (x, y) = position.split_once(',')?;
+    let (width, height) = size.trim().split_once('x')?;
+    Some(Rectangle::from_loc_and_size(
+        (x.parse().ok()?, y.parse().ok()?),
+        (width.parse().ok()?, height.parse().ok()?),
+    ))
+}
+
+// The socket file, removed again when the compositor exits
+pub struct IpcSocket {
+    path: PathBuf,
+}
+
+impl Drop for IpcSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// $XDG_RUNTIME_DIR/corrosionwm.<wayland socket>.sock, so nested sessions don't clash
+fn socket_path(socket_name: &OsStr) -> Option<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")?;
+    Some(PathBuf::from(runtime_dir).join(format!(
+        "corrosionwm.{}.sock",
+        socket_name.to_string_lossy()
+    )))
+}
+
+impl<BackendData: Backend + 'static> Corrosion<BackendData> {
+    // Starts listening on the ipc socket
+    pub fn init_ipc(
+        event_loop: &LoopHandle<'static, CalloopData<BackendData>>,
+        socket_name: &OsStr,
+    ) -> Option<IpcSocket> {
+        let path = match socket_path(socket_name) {
+            Some(path) => path,
+            None => {
+                tracing::warn!("XDG_RUNTIME_DIR is not set, not creating the ipc socket");
+                return None;
+            }
+        };
+
+        // a previous instance may have left its socket behind
+        let _ = std::fs::remove_file(&path);
+        let listener = match UnixListener::bind(&path).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            Ok(listener)
+        }) {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("Unable to create ipc socket {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        env::set_var(SOCKET_ENV, &path);
+        tracing::info!("Listening for ipc commands on {}", path.display());
+
+        event_loop
+            .insert_source(
+                Generic::new(listener, Interest::READ, Mode::Level),
+                |_, listener, data| {
+                    loop {
+                        match listener.accept() {
+                            Ok((stream, _)) => data.state.add_ipc_client(stream),
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                            Err(err) => {
+                                tracing::error!("Unable to accept ipc client: {}", err);
+                                break;
+                            }
+                        }
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .expect("Failed to init the ipc event source.");
+        Some(IpcSocket { path })
+    }
+
+    fn add_ipc_client(&mut self, stream: UnixStream) {
+        if let Err(err) = stream.set_nonblocking(true) {
+            tracing::error!("Unable to set up ipc client: {}", err);
+            return;
+        }
+
+        let mut buffer = Vec::new();
+        let mut replies = Vec::new();
+        let result = self.handle.insert_source(
+            // edge triggered, so replies that didn't fit into the socket get sent once it's
+            // writable again without waking up all the time before that
+            Generic::new(stream, Interest::BOTH, Mode::Edge),
+            move |_, stream, data| {
+                let mut chunk = [0u8; 1024];
+                let mut too_long = false;
+                let closed = loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => break true,
+                        Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break false,
+                        Err(_) => break true,
+                    }
+
+                    while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..=end).collect();
+                        let reply = String::from_utf8_lossy(&line)
+                            .parse::<IpcCommand>()
+                            .and_then(|command| data.state.handle_ipc_command(command));
+                        let reply = match reply {
+                            Ok(lines) => lines + "ok\n",
+                            Err(err) => format!("error: {}\n", err),
+                        };
+                        replies.extend_from_slice(reply.as_bytes());
+                    }
+
+                    // whatever is left is an unfinished line, a client that never sends a
+                    // newline doesn't get to grow it forever
+                    if buffer.len() > MAX_LINE_LENGTH {
+                        replies.extend_from_slice(b"error: line too long\n");
+                        buffer.clear();
+                        too_long = true;
+                        break true;
+                    }
+                };
+
+                while !replies.is_empty() {
+                    match stream.write(&replies) {
+                        Ok(0) => return Ok(PostAction::Remove),
+                        Ok(written) => {
+                            replies.drain(..written);
+                        }
+                        Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                        Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                        Err(_) => return Ok(PostAction::Remove),
+                    }
+                }
+
+                // a client that is done sending still gets the rest of its replies, one that
+                // sent too long a line only gets what fit into the socket
+                Ok(if too_long || (closed && replies.is_empty()) {
+                    PostAction::Remove
+                } else {
+                    PostAction::Continue
+                })
+            },
+        );
+        if let Err(err) = result {
+            tracing::error!("Unable to insert ipc client into the event loop: {}", err);
+        }
+    }
+
+    // Returns the lines a query answers with, every one ending in a newline
+    fn handle_ipc_command(&mut self, command: IpcCommand) -> Result<String, String> {
+        tracing::debug!("ipc command: {:?}", command);
+        match command {
+            IpcCommand::Dpms { on, output } => {
+                BackendData::set_output_power(self, output.as_deref(), on).map(|()| String::new())
+            }
+            IpcCommand::Screenshot(target) => {
+                BackendData::screenshot(self, target).map(|()| String::new())
+            }
+            IpcCommand::Windows => Ok(self.window_list()),
+        }
+    }
+
+    fn window_list(&self) -> String {
+        let mut list = String::new();
+        for info in self.toplevel_infos() {
+            let urgent = self
+                .window_for_surface(&info.surface)
+                .map_or(false, |window| self.is_urgent(&window));
+            let states = [
+                (info.activated, "focused"),
+                (urgent, "urgent"),
+                (info.minimized, "minimized"),
+                (info.maximized, "maximized"),
+                (info.fullscreen, "fullscreen"),
+            ]
+            .into_iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, name)| name)
+            .collect::<Vec<_>>()
+            .join(",");
+            // tabs and newlines would break the format
+            let clean = |text: &str| text.replace(['\t', '\n'], " ");
+            list += &format!(
+                "{}\t{}\t{}\n",
+                clean(&info.app_id),
+                clean(&info.title),
+                states
+            );
+        }
+        list
+    }
+}
+
+// Sends a single command to the running compositor and returns its reply
+pub fn send_command(command: &str) -> io::Result<String> {
+    let path = env::var_os(SOCKET_ENV).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} is not set, is corrosionwm running?", SOCKET_ENV),
+        )
+    })?;
+    let mut stream = UnixStream::connect(path)?;
+    writeln!(stream, "{}", command.trim())?;
+
+    // everything up to and including the "ok" or "error" line
+    let mut reply = String::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let last = line == "ok" || line.starts_with("error: ");
+        reply += &line;
+        if last {
+            break;
+        }
+        reply.push('\n');
+    }
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed() {
+        assert_eq!(
+            "dpms off".parse(),
+            Ok(IpcCommand::Dpms {
+                on: false,
+                output: None
+            })
+        );
+        assert_eq!(
+            "  dpms on DP-1\n".parse(),
+            Ok(IpcCommand::Dpms {
+                on: true,
+                output: Some(String::from("DP-1"))
+            })
+        );
+        assert_eq!(
+            "screenshot window".parse(),
+            Ok(IpcCommand::Screenshot(ScreenshotTarget::Window))
+        );
+        assert_eq!(
+            "screenshot region 10,20   300x200".parse(),
+            Ok(IpcCommand::Screenshot(ScreenshotTarget::Region(
+                Rectangle::from_loc_and_size((10, 20), (300, 200))
+            )))
+        );
+        assert_eq!("windows".parse(), Ok(IpcCommand::Windows));
+    }
+
+    #[test]
+    fn bad_commands_are_errors() {
+        assert_eq!("".parse::<IpcCommand>(), Err(String::from("empty command")));
+        assert_eq!(
+            "reboot now".parse::<IpcCommand>(),
+            Err(String::from("unknown command 'reboot'"))
+        );
+        assert!("dpms".parse::<IpcCommand>().is_err());
+        assert!("dpms maybe".parse::<IpcCommand>().is_err());
+        assert!("screenshot".parse::<IpcCommand>().is_err());
+        assert!("screenshot everything".parse::<IpcCommand>().is_err());
+        assert!("screenshot region 10,20".parse::<IpcCommand>().is_err());
+    }
+
+    #[test]
+    fn regions_are_parsed_the_way_slurp_prints_them() {
+        assert_eq!(
+            parse_region("0,0 1920x1080\n"),
+            Some(Rectangle::from_loc_and_size((0, 0), (1920, 1080)))
+        );
+        assert_eq!(
+            parse_region("-10,5 20x30"),
+            Some(Rectangle::from_loc_and_size((-10, 5), (20, 30)))
+        );
+        assert_eq!(parse_region("10,20"), None);
+        assert_eq!(parse_region("10 20 30x40"), None);
+        assert_eq!(parse_region("10,20 30,40"), None);
+        assert_eq!(parse_region("a,b cxd"), None);
+    }
+}
@@ -9,6 +9,8 @@ mod cursor;
 mod drawing;
 mod grabs;
 mod input;
+mod ipc;
+mod protocols;
 mod state;
 mod winit;
 
@@ -36,6 +38,20 @@ fn find_term(defaults: &Defaults) -> Option<&String> {
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1); // skip the first argument, which is the binary name
+    let flag = args.next(); // get the first argument
+    let arg = args.next(); // get the second argument
+
+    // talk to the running instance instead of starting another one, before anything gets logged
+    // or the config gets loaded
+    if let (Some("-m") | Some("--msg"), Some(command)) = (flag.as_deref(), arg.as_deref()) {
+        match ipc::send_command(command) {
+            Ok(reply) => println!("{}", reply),
+            Err(err) => eprintln!("Unable to send ipc command: {}", err),
+        }
+        return Ok(());
+    }
+
     // initialize logging
     if let Ok(env_filter) = tracing_subscriber::EnvFilter::try_from_env("CORROSIONWM_LOG") {
         // change this by changing the RUST_LOG environment variable
@@ -65,10 +81,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(_) => String::from("udev"),
     };
 
-    let mut args = std::env::args().skip(1); // skip the first argument, which is the binary name
-    let flag = args.next(); // get the first argument
-    let arg = args.next(); // get the second argument
-
     // handle the arguments
     // TODO: we should also make it process the arguments first so it doesnt log a bunch of stuff
     match (flag.as_deref(), arg) {
@@ -76,6 +88,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("Usage: corrosionwm [OPTION]...");
             println!("A Wayland compositor written in Rust");
             println!("--command <command> or -c <command> to run a command on startup");
+            println!("--msg <command> or -m <command> to send an ipc command, e.g. \"dpms off\"");
         }
         (Some("-c") | Some("--command"), Some(command)) => {
             std::process::Command::new(command).spawn().ok();
@@ -95,18 +108,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     match backend.as_ref() {
         "winit" => {
             // initialize the winit backend
-            winit_corrosion::init_winit::<WinitData>()
+            winit_corrosion::init_winit::<WinitData>(corrosion_config)
                 .expect("Unable to initialize winit backend :(");
         }
         "udev" => {
             // initialize the udev backend
-            backend::initialize_backend();
+            backend::initialize_backend(corrosion_config);
         }
         _ => {
             // default to udev
             tracing::error!("Backend setting not known, defaulting to udev");
             tracing::error!("Backend setting was: {}", backend);
-            backend::initialize_backend();
+            backend::initialize_backend(corrosion_config);
         }
     };
 
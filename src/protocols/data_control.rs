@@ -0,0 +1,311 @@
+// wlr-data-control-unstable-v1, lets clipboard managers like cliphist and wl-clipboard see and
+// set the clipboard and primary selection without having the keyboard focus
+// this only keeps track of devices, sources and offers, the handler decides where the data of a
+// selection comes from
+
+use std::{
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use smithay::reexports::{
+    wayland_protocols_wlr::data_control::v1::server::{
+        zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+        zwlr_data_control_manager_v1::{self, ZwlrDataControlManagerV1},
+        zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+        zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+    },
+    wayland_server::{
+        backend::{ClientId, ObjectId},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+
+const VERSION: u32 = 2;
+
+pub trait DataControlHandler {
+    fn data_control_state(&mut self) -> &mut DataControlState;
+    // a clipboard manager set a selection, or cleared it with None
+    fn set_selection(&mut self, source: Option<DataControlSource>, primary: bool);
+    // a clipboard manager wants the data of the current selection written to `fd`
+    fn receive_selection(&mut self, mime_type: String, fd: OwnedFd, primary: bool);
+}
+
+// A selection offered by a clipboard manager
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataControlSource {
+    resource: ZwlrDataControlSourceV1,
+}
+
+impl DataControlSource {
+    pub fn mime_types(&self) -> Vec<String> {
+        self.resource
+            .data::<DataControlSourceData>()
+            .map(|data| data.mime_types.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    // Asks the clipboard manager to write the data for `mime_type` to `fd`
+    pub fn send(&self, mime_type: String, fd: OwnedFd) {
+        self.resource.send(mime_type, fd.as_raw_fd());
+    }
+
+    // The source is no longer the selection
+    pub fn cancel(&self) {
+        self.resource.cancelled();
+    }
+
+    pub fn alive(&self) -> bool {
+        self.resource.is_alive()
+    }
+}
+
+#[derive(Default)]
+pub struct DataControlSourceData {
+    mime_types: Mutex<Vec<String>>,
+    // a source can only be set as a selection once, and can't get more mime types after that
+    used: AtomicBool,
+}
+
+pub struct DataControlState {
+    devices: Vec<ZwlrDataControlDeviceV1>,
+    // the mime types of the current selections, so new devices can be told about them
+    selection: Option<Vec<String>>,
+    primary_selection: Option<Vec<String>>,
+}
+
+impl DataControlState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrDataControlManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwlrDataControlManagerV1, _>(VERSION, ());
+        Self {
+            devices: Vec::new(),
+            selection: None,
+            primary_selection: None,
+        }
+    }
+
+    // Tells every clipboard manager about a new selection, None if it was cleared
+    pub fn set_selection<D>(
+        &mut self,
+        dh: &DisplayHandle,
+        mime_types: Option<Vec<String>>,
+        primary: bool,
+    ) where
+        D: Dispatch<ZwlrDataControlOfferV1, bool> + 'static,
+    {
+        let current = if primary {
+            &mut self.primary_selection
+        } else {
+            &mut self.selection
+        };
+        *current = mime_types;
+        for device in &self.devices {
+            send_selection::<D>(dh, device, current.as_deref(), primary);
+        }
+    }
+}
+
+// Sends a fresh offer for a selection to `device`
+fn send_selection<D>(
+    dh: &DisplayHandle,
+    device: &ZwlrDataControlDeviceV1,
+    mime_types: Option<&[String]>,
+    primary: bool,
+) where
+    D: Dispatch<ZwlrDataControlOfferV1, bool> + 'static,
+{
+    // the primary selection only came with version 2
+    if primary && device.version() < 2 {
+        return;
+    }
+
+    let offer = mime_types.and_then(|mime_types| {
+        let client = dh.get_client(device.id()).ok()?;
+        let offer = client
+            .create_resource::<ZwlrDataControlOfferV1, _, D>(dh, device.version(), primary)
+            .ok()?;
+        device.data_offer(&offer);
+        for mime_type in mime_types {
+            offer.offer(mime_type.clone());
+        }
+        Some(offer)
+    });
+    if primary {
+        device.primary_selection(offer.as_ref());
+    } else {
+        device.selection(offer.as_ref());
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrDataControlManagerV1, (), D> for DataControlState
+where
+    D: GlobalDispatch<ZwlrDataControlManagerV1, ()>
+        + Dispatch<ZwlrDataControlManagerV1, ()>
+        + DataControlHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrDataControlManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwlrDataControlManagerV1, (), D> for DataControlState
+where
+    D: Dispatch<ZwlrDataControlManagerV1, ()>
+        + Dispatch<ZwlrDataControlDeviceV1, ()>
+        + Dispatch<ZwlrDataControlSourceV1, DataControlSourceData>
+        + Dispatch<ZwlrDataControlOfferV1, bool>
+        + DataControlHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ZwlrDataControlManagerV1,
+        request: zwlr_data_control_manager_v1::Request,
+        _data: &(),
+        dh: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_data_control_manager_v1::Request::CreateDataSource { id } => {
+                data_init.init(id, DataControlSourceData::default());
+            }
+            // there is only one seat
+            zwlr_data_control_manager_v1::Request::GetDataDevice { id, .. } => {
+                let device = data_init.init(id, ());
+                let state = state.data_control_state();
+                send_selection::<D>(dh, &device, state.selection.as_deref(), false);
+                send_selection::<D>(dh, &device, state.primary_selection.as_deref(), true);
+                state.devices.push(device);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrDataControlDeviceV1, (), D> for DataControlState
+where
+    D: Dispatch<ZwlrDataControlDeviceV1, ()> + DataControlHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        device: &ZwlrDataControlDeviceV1,
+        request: zwlr_data_control_device_v1::Request,
+        _data: &(),
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let (source, primary) = match request {
+            zwlr_data_control_device_v1::Request::SetSelection { source } => (source, false),
+            zwlr_data_control_device_v1::Request::SetPrimarySelection { source } => (source, true),
+            _ => return,
+        };
+
+        if let Some(source) = &source {
+            let used = source
+                .data::<DataControlSourceData>()
+                .map_or(true, |data| data.used.swap(true, Ordering::SeqCst));
+            if used {
+                device.post_error(
+                    zwlr_data_control_device_v1::Error::UsedSource,
+                    "the source was already used",
+                );
+                return;
+            }
+        }
+        state.set_selection(
+            source.map(|resource| DataControlSource { resource }),
+            primary,
+        );
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .data_control_state()
+            .devices
+            .retain(|device| device.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwlrDataControlSourceV1, DataControlSourceData, D> for DataControlState
+where
+    D: Dispatch<ZwlrDataControlSourceV1, DataControlSourceData> + DataControlHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        source: &ZwlrDataControlSourceV1,
+        request: zwlr_data_control_source_v1::Request,
+        data: &DataControlSourceData,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwlr_data_control_source_v1::Request::Offer { mime_type } = request {
+            if data.used.load(Ordering::SeqCst) {
+                source.post_error(
+                    zwlr_data_control_source_v1::Error::InvalidOffer,
+                    "the source was already used",
+                );
+                return;
+            }
+            data.mime_types.lock().unwrap().push(mime_type);
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrDataControlOfferV1, bool, D> for DataControlState
+where
+    D: Dispatch<ZwlrDataControlOfferV1, bool> + DataControlHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _offer: &ZwlrDataControlOfferV1,
+        request: zwlr_data_control_offer_v1::Request,
+        primary: &bool,
+        _dh: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwlr_data_control_offer_v1::Request::Receive { mime_type, fd } = request {
+            // the fd is ours now, it gets closed when the handler is done with it
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            state.receive_selection(mime_type, fd, *primary);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_data_control {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::data_control::v1::server::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1: ()
+        ] => $crate::protocols::data_control::DataControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::data_control::v1::server::zwlr_data_control_manager_v1::ZwlrDataControlManagerV1: ()
+        ] => $crate::protocols::data_control::DataControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::data_control::v1::server::zwlr_data_control_device_v1::ZwlrDataControlDeviceV1: ()
+        ] => $crate::protocols::data_control::DataControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::data_control::v1::server::zwlr_data_control_source_v1::ZwlrDataControlSourceV1: $crate::protocols::data_control::DataControlSourceData
+        ] => $crate::protocols::data_control::DataControlState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::data_control::v1::server::zwlr_data_control_offer_v1::ZwlrDataControlOfferV1: bool
+        ] => $crate::protocols::data_control::DataControlState);
+    };
+}
@@ -0,0 +1,18 @@
+// Bindings for ext-foreign-toplevel-list-v1, generated from the xml the same way
+// wayland-protocols does it since our version of it doesn't have the protocol yet
+// TODO: use wayland-protocols once smithay moves to a version that has it
+#![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+#![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+#![allow(clippy::all)]
+
+use smithay::reexports::wayland_server::{self, protocol::*};
+
+pub mod __interfaces {
+    use smithay::reexports::wayland_server::{
+        backend as wayland_backend, protocol::__interfaces::*,
+    };
+    wayland_scanner::generate_interfaces!("protocols/ext-foreign-toplevel-list-v1.xml");
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_server_code!("protocols/ext-foreign-toplevel-list-v1.xml");
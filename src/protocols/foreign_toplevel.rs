@@ -0,0 +1,467 @@
+// wlr-foreign-toplevel-management-unstable-v1 and ext-foreign-toplevel-list-v1, the window list
+// for taskbars and alt-tab tools. The ext list only has titles and app ids, and no requests
+// the handler hands us a snapshot of every window with refresh(), we diff it against what
+// clients were told last time and only send the changes
+
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::foreign_toplevel::v1::server::{
+            zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+            zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+};
+
+use super::ext_foreign_toplevel_list::{
+    ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+    ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+};
+
+const VERSION: u32 = 3;
+const EXT_VERSION: u32 = 1;
+
+pub trait ForeignToplevelHandler {
+    fn foreign_toplevel_state(&mut self) -> &mut ForeignToplevelState;
+    fn activate(&mut self, surface: WlSurface);
+    fn close(&mut self, surface: WlSurface);
+    fn set_maximized(&mut self, surface: WlSurface, maximized: bool);
+    fn set_minimized(&mut self, surface: WlSurface, minimized: bool);
+    fn set_fullscreen(&mut self, surface: WlSurface, output: Option<Output>, fullscreen: bool);
+}
+
+// What clients get to know about a window
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToplevelInfo {
+    pub surface: WlSurface,
+    pub title: String,
+    pub app_id: String,
+    pub maximized: bool,
+    pub minimized: bool,
+    pub activated: bool,
+    pub fullscreen: bool,
+    pub outputs: Vec<Output>,
+}
+
+impl ToplevelInfo {
+    fn states(&self) -> Vec<u8> {
+        use zwlr_foreign_toplevel_handle_v1::State;
+        [
+            (self.maximized, State::Maximized),
+            (self.minimized, State::Minimized),
+            (self.activated, State::Activated),
+            (self.fullscreen, State::Fullscreen),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .flat_map(|(_, state)| (state as u32).to_ne_bytes())
+        .collect()
+    }
+}
+
+struct Toplevel {
+    info: ToplevelInfo,
+    // never reused, so ext clients can tell a new window from an old one
+    identifier: String,
+    handles: Vec<ZwlrForeignToplevelHandleV1>,
+    ext_handles: Vec<ExtForeignToplevelHandleV1>,
+}
+
+pub struct ForeignToplevelState {
+    managers: Vec<ZwlrForeignToplevelManagerV1>,
+    ext_lists: Vec<ExtForeignToplevelListV1>,
+    toplevels: Vec<Toplevel>,
+    next_identifier: u64,
+}
+
+impl ForeignToplevelState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrForeignToplevelManagerV1, ()>
+            + GlobalDispatch<ExtForeignToplevelListV1, ()>
+            + 'static,
+    {
+        display.create_global::<D, ZwlrForeignToplevelManagerV1, _>(VERSION, ());
+        display.create_global::<D, ExtForeignToplevelListV1, _>(EXT_VERSION, ());
+        Self {
+            managers: Vec::new(),
+            ext_lists: Vec::new(),
+            toplevels: Vec::new(),
+            next_identifier: 0,
+        }
+    }
+
+    // Updates clients to `toplevels`, which has to contain every window there is
+    pub fn refresh<D>(&mut self, dh: &DisplayHandle, toplevels: Vec<ToplevelInfo>)
+    where
+        D: Dispatch<ZwlrForeignToplevelHandleV1, WlSurface>
+            + Dispatch<ExtForeignToplevelHandleV1, ()>
+            + 'static,
+    {
+        self.toplevels.retain(|toplevel| {
+            let alive = toplevels
+                .iter()
+                .any(|info| info.surface == toplevel.info.surface);
+            if !alive {
+                for handle in &toplevel.handles {
+                    handle.closed();
+                }
+                for handle in &toplevel.ext_handles {
+                    handle.closed();
+                }
+            }
+            alive
+        });
+
+        for info in toplevels {
+            match self
+                .toplevels
+                .iter_mut()
+                .find(|toplevel| toplevel.info.surface == info.surface)
+            {
+                Some(toplevel) => {
+                    if toplevel.info != info {
+                        for handle in &toplevel.handles {
+                            send_changes(dh, handle, Some(&toplevel.info), &info);
+                        }
+                        if toplevel.info.title != info.title || toplevel.info.app_id != info.app_id
+                        {
+                            for handle in &toplevel.ext_handles {
+                                send_ext_changes(handle, Some(&toplevel.info), &info);
+                            }
+                        }
+                        toplevel.info = info;
+                    }
+                }
+                None => {
+                    let identifier = format!("{:016x}", self.next_identifier);
+                    self.next_identifier += 1;
+                    let handles = self
+                        .managers
+                        .iter()
+                        .filter_map(|manager| announce::<D>(dh, manager, &info))
+                        .collect();
+                    let ext_handles = self
+                        .ext_lists
+                        .iter()
+                        .filter_map(|list| announce_ext::<D>(dh, list, &identifier, &info))
+                        .collect();
+                    self.toplevels.push(Toplevel {
+                        info,
+                        identifier,
+                        handles,
+                        ext_handles,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// Creates a handle for `info` on the client of `manager` and sends everything about it
+fn announce<D>(
+    dh: &DisplayHandle,
+    manager: &ZwlrForeignToplevelManagerV1,
+    info: &ToplevelInfo,
+) -> Option<ZwlrForeignToplevelHandleV1>
+where
+    D: Dispatch<ZwlrForeignToplevelHandleV1, WlSurface> + 'static,
+{
+    let client = dh.get_client(manager.id()).ok()?;
+    let handle = client
+        .create_resource::<ZwlrForeignToplevelHandleV1, _, D>(
+            dh,
+            manager.version(),
+            info.surface.clone(),
+        )
+        .ok()?;
+    manager.toplevel(&handle);
+    send_changes(dh, &handle, None, info);
+    Some(handle)
+}
+
+fn send_changes(
+    dh: &DisplayHandle,
+    handle: &ZwlrForeignToplevelHandleV1,
+    old: Option<&ToplevelInfo>,
+    new: &ToplevelInfo,
+) {
+    if old.map_or(true, |old| old.title != new.title) {
+        handle.title(new.title.clone());
+    }
+    if old.map_or(true, |old| old.app_id != new.app_id) {
+        handle.app_id(new.app_id.clone());
+    }
+
+    if let Ok(client) = dh.get_client(handle.id()) {
+        let old_outputs = old.map_or(&[][..], |old| &old.outputs[..]);
+        for output in old_outputs.iter().filter(|o| !new.outputs.contains(o)) {
+            for wl_output in output.client_outputs(&client) {
+                handle.output_leave(&wl_output);
+            }
+        }
+        for output in new.outputs.iter().filter(|o| !old_outputs.contains(o)) {
+            for wl_output in output.client_outputs(&client) {
+                handle.output_enter(&wl_output);
+            }
+        }
+    }
+
+    let states = new.states();
+    if old.map_or(true, |old| old.states() != states) {
+        handle.state(states);
+    }
+    handle.done();
+}
+
+// The same for an ext list, which also gets the identifier
+fn announce_ext<D>(
+    dh: &DisplayHandle,
+    list: &ExtForeignToplevelListV1,
+    identifier: &str,
+    info: &ToplevelInfo,
+) -> Option<ExtForeignToplevelHandleV1>
+where
+    D: Dispatch<ExtForeignToplevelHandleV1, ()> + 'static,
+{
+    let client = dh.get_client(list.id()).ok()?;
+    let handle = client
+        .create_resource::<ExtForeignToplevelHandleV1, _, D>(dh, list.version(), ())
+        .ok()?;
+    list.toplevel(&handle);
+    handle.identifier(identifier.to_owned());
+    send_ext_changes(&handle, None, info);
+    Some(handle)
+}
+
+fn send_ext_changes(
+    handle: &ExtForeignToplevelHandleV1,
+    old: Option<&ToplevelInfo>,
+    new: &ToplevelInfo,
+) {
+    if old.map_or(true, |old| old.title != new.title) {
+        handle.title(new.title.clone());
+    }
+    if old.map_or(true, |old| old.app_id != new.app_id) {
+        handle.app_id(new.app_id.clone());
+    }
+    handle.done();
+}
+
+impl<D> GlobalDispatch<ZwlrForeignToplevelManagerV1, (), D> for ForeignToplevelState
+where
+    D: GlobalDispatch<ZwlrForeignToplevelManagerV1, ()>
+        + Dispatch<ZwlrForeignToplevelManagerV1, ()>
+        + Dispatch<ZwlrForeignToplevelHandleV1, WlSurface>
+        + ForeignToplevelHandler
+        + 'static,
+{
+    fn bind(
+        state: &mut D,
+        dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrForeignToplevelManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let manager = data_init.init(resource, ());
+        let state = state.foreign_toplevel_state();
+        for toplevel in &mut state.toplevels {
+            if let Some(handle) = announce::<D>(dh, &manager, &toplevel.info) {
+                toplevel.handles.push(handle);
+            }
+        }
+        state.managers.push(manager);
+    }
+}
+
+impl<D> Dispatch<ZwlrForeignToplevelManagerV1, (), D> for ForeignToplevelState
+where
+    D: Dispatch<ZwlrForeignToplevelManagerV1, ()> + ForeignToplevelHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        manager: &ZwlrForeignToplevelManagerV1,
+        request: zwlr_foreign_toplevel_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Request::Stop = request {
+            state
+                .foreign_toplevel_state()
+                .managers
+                .retain(|other| other != manager);
+            manager.finished();
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .foreign_toplevel_state()
+            .managers
+            .retain(|manager| manager.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwlrForeignToplevelHandleV1, WlSurface, D> for ForeignToplevelState
+where
+    D: Dispatch<ZwlrForeignToplevelHandleV1, WlSurface> + ForeignToplevelHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _handle: &ZwlrForeignToplevelHandleV1,
+        request: zwlr_foreign_toplevel_handle_v1::Request,
+        surface: &WlSurface,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let surface = surface.clone();
+        match request {
+            zwlr_foreign_toplevel_handle_v1::Request::Activate { .. } => state.activate(surface),
+            zwlr_foreign_toplevel_handle_v1::Request::Close => state.close(surface),
+            zwlr_foreign_toplevel_handle_v1::Request::SetMaximized => {
+                state.set_maximized(surface, true)
+            }
+            zwlr_foreign_toplevel_handle_v1::Request::UnsetMaximized => {
+                state.set_maximized(surface, false)
+            }
+            zwlr_foreign_toplevel_handle_v1::Request::SetMinimized => {
+                state.set_minimized(surface, true)
+            }
+            zwlr_foreign_toplevel_handle_v1::Request::UnsetMinimized => {
+                state.set_minimized(surface, false)
+            }
+            zwlr_foreign_toplevel_handle_v1::Request::SetFullscreen { output } => {
+                let output = output.as_ref().and_then(Output::from_resource);
+                state.set_fullscreen(surface, output, true)
+            }
+            zwlr_foreign_toplevel_handle_v1::Request::UnsetFullscreen => {
+                state.set_fullscreen(surface, None, false)
+            }
+            // minimize animations are not a thing here
+            zwlr_foreign_toplevel_handle_v1::Request::SetRectangle { .. } => {}
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &WlSurface) {
+        for toplevel in &mut state.foreign_toplevel_state().toplevels {
+            toplevel.handles.retain(|handle| handle.id() != resource);
+        }
+    }
+}
+
+impl<D> GlobalDispatch<ExtForeignToplevelListV1, (), D> for ForeignToplevelState
+where
+    D: GlobalDispatch<ExtForeignToplevelListV1, ()>
+        + Dispatch<ExtForeignToplevelListV1, ()>
+        + Dispatch<ExtForeignToplevelHandleV1, ()>
+        + ForeignToplevelHandler
+        + 'static,
+{
+    fn bind(
+        state: &mut D,
+        dh: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtForeignToplevelListV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let list = data_init.init(resource, ());
+        let state = state.foreign_toplevel_state();
+        for toplevel in &mut state.toplevels {
+            if let Some(handle) = announce_ext::<D>(dh, &list, &toplevel.identifier, &toplevel.info)
+            {
+                toplevel.ext_handles.push(handle);
+            }
+        }
+        state.ext_lists.push(list);
+    }
+}
+
+impl<D> Dispatch<ExtForeignToplevelListV1, (), D> for ForeignToplevelState
+where
+    D: Dispatch<ExtForeignToplevelListV1, ()> + ForeignToplevelHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        list: &ExtForeignToplevelListV1,
+        request: ext_foreign_toplevel_list_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Request::Stop = request {
+            state
+                .foreign_toplevel_state()
+                .ext_lists
+                .retain(|other| other != list);
+            list.finished();
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .foreign_toplevel_state()
+            .ext_lists
+            .retain(|list| list.id() != resource);
+    }
+}
+
+// the handles only have a destroy request
+impl<D> Dispatch<ExtForeignToplevelHandleV1, (), D> for ForeignToplevelState
+where
+    D: Dispatch<ExtForeignToplevelHandleV1, ()> + ForeignToplevelHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _handle: &ExtForeignToplevelHandleV1,
+        _request: ext_foreign_toplevel_handle_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        for toplevel in &mut state.foreign_toplevel_state().toplevels {
+            toplevel
+                .ext_handles
+                .retain(|handle| handle.id() != resource);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_foreign_toplevel {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1: ()
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1: ()
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::foreign_toplevel::v1::server::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1: smithay::reexports::wayland_server::protocol::wl_surface::WlSurface
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::protocols::ext_foreign_toplevel_list::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1: ()
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::protocols::ext_foreign_toplevel_list::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1: ()
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            $crate::protocols::ext_foreign_toplevel_list::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1: ()
+        ] => $crate::protocols::foreign_toplevel::ForeignToplevelState);
+    };
+}
@@ -0,0 +1,132 @@
+// idle-inhibit-unstable-v1, lets video players keep the screen on
+// only the inhibiting surfaces are tracked here, whether they count is up to the handler
+
+use smithay::reexports::{
+    wayland_protocols::wp::idle_inhibit::zv1::server::{
+        zwp_idle_inhibit_manager_v1::{self, ZwpIdleInhibitManagerV1},
+        zwp_idle_inhibitor_v1::{self, ZwpIdleInhibitorV1},
+    },
+    wayland_server::{
+        backend::{ClientId, ObjectId},
+        protocol::wl_surface::WlSurface,
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+
+const VERSION: u32 = 1;
+
+pub trait IdleInhibitHandler {
+    fn idle_inhibit_state(&mut self) -> &mut IdleInhibitState;
+    // an inhibitor was created or destroyed
+    fn inhibitors_changed(&mut self);
+}
+
+pub struct IdleInhibitState {
+    inhibitors: Vec<(ZwpIdleInhibitorV1, WlSurface)>,
+}
+
+impl IdleInhibitState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpIdleInhibitManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwpIdleInhibitManagerV1, _>(VERSION, ());
+        Self {
+            inhibitors: Vec::new(),
+        }
+    }
+
+    // Every surface with an inhibitor, visible or not
+    pub fn surfaces(&self) -> impl Iterator<Item = &WlSurface> {
+        self.inhibitors.iter().map(|(_, surface)| surface)
+    }
+}
+
+impl<D> GlobalDispatch<ZwpIdleInhibitManagerV1, (), D> for IdleInhibitState
+where
+    D: GlobalDispatch<ZwpIdleInhibitManagerV1, ()>
+        + Dispatch<ZwpIdleInhibitManagerV1, ()>
+        + IdleInhibitHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpIdleInhibitManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpIdleInhibitManagerV1, (), D> for IdleInhibitState
+where
+    D: Dispatch<ZwpIdleInhibitManagerV1, ()>
+        + Dispatch<ZwpIdleInhibitorV1, ()>
+        + IdleInhibitHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ZwpIdleInhibitManagerV1,
+        request: zwp_idle_inhibit_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_idle_inhibit_manager_v1::Request::CreateInhibitor { id, surface } => {
+                let inhibitor = data_init.init(id, ());
+                state
+                    .idle_inhibit_state()
+                    .inhibitors
+                    .push((inhibitor, surface));
+                state.inhibitors_changed();
+            }
+            zwp_idle_inhibit_manager_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpIdleInhibitorV1, (), D> for IdleInhibitState
+where
+    D: Dispatch<ZwpIdleInhibitorV1, ()> + IdleInhibitHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _inhibitor: &ZwpIdleInhibitorV1,
+        _request: zwp_idle_inhibitor_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .idle_inhibit_state()
+            .inhibitors
+            .retain(|(inhibitor, _)| inhibitor.id() != resource);
+        state.inhibitors_changed();
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_idle_inhibit {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1: ()
+        ] => $crate::protocols::idle_inhibit::IdleInhibitState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1: ()
+        ] => $crate::protocols::idle_inhibit::IdleInhibitState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::idle_inhibit::zv1::server::zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1: ()
+        ] => $crate::protocols::idle_inhibit::IdleInhibitState);
+    };
+}
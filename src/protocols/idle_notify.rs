@@ -0,0 +1,166 @@
+// ext-idle-notify-v1, tells clients like swayidle when the user went idle and when they came back
+// this has no timers of its own, the handler calls poll() whenever the next notification is due
+
+use std::time::{Duration, Instant};
+
+use smithay::reexports::{
+    wayland_protocols::ext::idle_notify::v1::server::{
+        ext_idle_notification_v1::{self, ExtIdleNotificationV1},
+        ext_idle_notifier_v1::{self, ExtIdleNotifierV1},
+    },
+    wayland_server::{
+        backend::{ClientId, ObjectId},
+        Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+    },
+};
+
+const VERSION: u32 = 1;
+
+pub trait IdleNotifierHandler {
+    fn idle_notifier_state(&mut self) -> &mut IdleNotifierState;
+    // a notification was created, it may be due before everything polled so far
+    fn notification_created(&mut self);
+}
+
+struct Notification {
+    resource: ExtIdleNotificationV1,
+    timeout: Duration,
+    idle: bool,
+}
+
+pub struct IdleNotifierState {
+    notifications: Vec<Notification>,
+    last_activity: Instant,
+}
+
+impl IdleNotifierState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ExtIdleNotifierV1, ()> + 'static,
+    {
+        display.create_global::<D, ExtIdleNotifierV1, _>(VERSION, ());
+        Self {
+            notifications: Vec::new(),
+            last_activity: Instant::now(),
+        }
+    }
+
+    // The user did something, idle notifications get resumed
+    pub fn notify_activity(&mut self) {
+        self.last_activity = Instant::now();
+        for notification in self.notifications.iter_mut().filter(|n| n.idle) {
+            notification.resource.resumed();
+            notification.idle = false;
+        }
+    }
+
+    // Sends idled to every notification whose timeout passed, returns how long it takes until
+    // the next one is due
+    pub fn poll(&mut self) -> Option<Duration> {
+        let idle_for = self.last_activity.elapsed();
+        let mut next = None;
+        for notification in self.notifications.iter_mut().filter(|n| !n.idle) {
+            if idle_for >= notification.timeout {
+                notification.resource.idled();
+                notification.idle = true;
+            } else {
+                let remaining = notification.timeout - idle_for;
+                next = Some(next.map_or(remaining, |next: Duration| next.min(remaining)));
+            }
+        }
+        next
+    }
+}
+
+impl<D> GlobalDispatch<ExtIdleNotifierV1, (), D> for IdleNotifierState
+where
+    D: GlobalDispatch<ExtIdleNotifierV1, ()>
+        + Dispatch<ExtIdleNotifierV1, ()>
+        + IdleNotifierHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtIdleNotifierV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ExtIdleNotifierV1, (), D> for IdleNotifierState
+where
+    D: Dispatch<ExtIdleNotifierV1, ()>
+        + Dispatch<ExtIdleNotificationV1, ()>
+        + IdleNotifierHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _notifier: &ExtIdleNotifierV1,
+        request: ext_idle_notifier_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            // there is only one seat, so the seat argument doesn't matter
+            ext_idle_notifier_v1::Request::GetIdleNotification { id, timeout, .. } => {
+                let resource = data_init.init(id, ());
+                state
+                    .idle_notifier_state()
+                    .notifications
+                    .push(Notification {
+                        resource,
+                        timeout: Duration::from_millis(timeout as u64),
+                        idle: false,
+                    });
+                state.notification_created();
+            }
+            ext_idle_notifier_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ExtIdleNotificationV1, (), D> for IdleNotifierState
+where
+    D: Dispatch<ExtIdleNotificationV1, ()> + IdleNotifierHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _notification: &ExtIdleNotificationV1,
+        _request: ext_idle_notification_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .idle_notifier_state()
+            .notifications
+            .retain(|notification| notification.resource.id() != resource);
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_idle_notify {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::idle_notify::v1::server::ext_idle_notifier_v1::ExtIdleNotifierV1: ()
+        ] => $crate::protocols::idle_notify::IdleNotifierState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::idle_notify::v1::server::ext_idle_notifier_v1::ExtIdleNotifierV1: ()
+        ] => $crate::protocols::idle_notify::IdleNotifierState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::idle_notify::v1::server::ext_idle_notification_v1::ExtIdleNotificationV1: ()
+        ] => $crate::protocols::idle_notify::IdleNotifierState);
+    };
+}
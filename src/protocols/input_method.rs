@@ -0,0 +1,446 @@
+// input-method-unstable-v2, lets fcitx5 and ibus compose text for the active text input
+// there is one input method at a time, it can grab the keyboard to see keys before the focused
+// client does, and show popups like candidate lists next to the text cursor
+
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use smithay::{
+    reexports::{
+        wayland_protocols_misc::zwp_input_method_v2::server::{
+            zwp_input_method_keyboard_grab_v2::{self, ZwpInputMethodKeyboardGrabV2},
+            zwp_input_method_manager_v2::{self, ZwpInputMethodManagerV2},
+            zwp_input_method_v2::{self, ZwpInputMethodV2},
+            zwp_input_popup_surface_v2::{self, ZwpInputPopupSurfaceV2},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::{
+                wl_keyboard::{KeyState, KeymapFormat},
+                wl_surface::WlSurface,
+            },
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Rectangle, Serial},
+    wayland::compositor,
+};
+
+use super::text_input::{TextChange, TextInputProperties};
+
+const VERSION: u32 = 1;
+const POPUP_SURFACE_ROLE: &str = "zwp_input_popup_surface_v2";
+
+pub trait InputMethodHandler {
+    fn input_method_state(&mut self) -> &mut InputMethodState;
+    // the input method came or went
+    fn input_method_changed(&mut self);
+    // text from the input method for the active text input
+    fn input_method_commit(&mut self, change: TextChange);
+    // the input method grabbed the keyboard, it needs the keymap before the first key
+    fn keyboard_grabbed(&mut self);
+    // a popup was created or destroyed
+    fn popups_changed(&mut self);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputPopup {
+    resource: ZwpInputPopupSurfaceV2,
+    surface: WlSurface,
+}
+
+impl InputPopup {
+    pub fn wl_surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    // Where the text cursor is, relative to the popup
+    pub fn set_text_input_rectangle(&self, rectangle: Rectangle<i32, Logical>) {
+        self.resource.text_input_rectangle(
+            rectangle.loc.x,
+            rectangle.loc.y,
+            rectangle.size.w,
+            rectangle.size.h,
+        );
+    }
+}
+
+// What the input method sent since its last commit
+#[derive(Default)]
+pub struct InputMethodData {
+    pending: Mutex<TextChange>,
+}
+
+pub struct InputMethodState {
+    input_method: Option<ZwpInputMethodV2>,
+    // whether the input method was told to activate
+    active: bool,
+    // commits have to carry the number of done events, older ones are for another text input
+    dones: u32,
+    grab: Option<ZwpInputMethodKeyboardGrabV2>,
+    // the last modifiers sent to the grab, as depressed, latched, locked and group
+    grab_modifiers: Option<(u32, u32, u32, u32)>,
+    // the keymap sent to the grab stays open, it is unlinked right away
+    keymap_file: Option<File>,
+    popups: Vec<InputPopup>,
+}
+
+impl InputMethodState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpInputMethodManagerV2, ()> + 'static,
+    {
+        display.create_global::<D, ZwpInputMethodManagerV2, _>(VERSION, ());
+        Self {
+            input_method: None,
+            active: false,
+            dones: 0,
+            grab: None,
+            grab_modifiers: None,
+            keymap_file: None,
+            popups: Vec::new(),
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Whether `object` belongs to the client running the input method, e.g. the virtual
+    // keyboard it types unused keys with
+    pub fn is_input_method_client(&self, object: &ObjectId) -> bool {
+        self.input_method.as_ref().map_or(false, |input_method| {
+            input_method.id().same_client_as(object)
+        })
+    }
+
+    pub fn popups(&self) -> &[InputPopup] {
+        &self.popups
+    }
+
+    // Tells the input method about the active text input, None deactivates it
+    pub fn set_text_input(&mut self, properties: Option<&TextInputProperties>) {
+        let input_method = match &self.input_method {
+            Some(input_method) => input_method,
+            None => return,
+        };
+        match properties {
+            Some(properties) => {
+                if !self.active {
+                    input_method.activate();
+                    self.active = true;
+                }
+                if let Some((text, cursor, anchor)) = &properties.surrounding_text {
+                    input_method.surrounding_text(text.clone(), *cursor as u32, *anchor as u32);
+                }
+                input_method.text_change_cause(properties.change_cause);
+                input_method.content_type(properties.content_hint, properties.content_purpose);
+            }
+            None if self.active => {
+                input_method.deactivate();
+                self.active = false;
+            }
+            None => return,
+        }
+        input_method.done();
+        self.dones = self.dones.wrapping_add(1);
+    }
+
+    // Keys only go to a grab while there is a text input to type into
+    pub fn has_grab(&self) -> bool {
+        self.active && self.grab.is_some()
+    }
+
+    pub fn send_key(&self, serial: Serial, time: u32, keycode: u32, pressed: bool) {
+        if let Some(grab) = &self.grab {
+            let state = if pressed {
+                KeyState::Pressed
+            } else {
+                KeyState::Released
+            };
+            grab.key(serial.into(), time, keycode, state);
+        }
+    }
+
+    pub fn send_modifiers(&mut self, serial: Serial, modifiers: (u32, u32, u32, u32)) {
+        let grab = match &self.grab {
+            Some(grab) => grab,
+            None => return,
+        };
+        if self.grab_modifiers != Some(modifiers) {
+            let (depressed, latched, locked, group) = modifiers;
+            grab.modifiers(serial.into(), depressed, latched, locked, group);
+            self.grab_modifiers = Some(modifiers);
+        }
+    }
+
+    // Sends the keymap and repeat info of the seat keyboard to the grab
+    pub fn send_keymap(&mut self, keymap: &str, repeat_rate: i32, repeat_delay: i32) {
+        let grab = match &self.grab {
+            Some(grab) => grab,
+            None => return,
+        };
+        let file = match keymap_file(keymap) {
+            Ok(file) => file,
+            Err(err) => {
+                tracing::warn!("Unable to share the keymap with the input method: {}", err);
+                return;
+            }
+        };
+        grab.keymap(
+            KeymapFormat::XkbV1,
+            file.as_raw_fd(),
+            keymap.len() as u32 + 1,
+        );
+        grab.repeat_info(repeat_rate, repeat_delay);
+        self.keymap_file = Some(file);
+        // the modifiers mean something else with another keymap
+        self.grab_modifiers = None;
+    }
+}
+
+// An unlinked file with the keymap and a terminating nul, like wl_keyboard sends it
+fn keymap_file(keymap: &str) -> std::io::Result<File> {
+    let directory = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let path = directory.join(format!("corrosionwm-keymap-{}", std::process::id()));
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+    file.write_all(keymap.as_bytes())?;
+    file.write_all(&[0])?;
+    Ok(file)
+}
+
+impl<D> GlobalDispatch<ZwpInputMethodManagerV2, (), D> for InputMethodState
+where
+    D: GlobalDispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodManagerV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpInputMethodManagerV2>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodManagerV2, (), D> for InputMethodState
+where
+    D: Dispatch<ZwpInputMethodManagerV2, ()>
+        + Dispatch<ZwpInputMethodV2, InputMethodData>
+        + InputMethodHandler
+        + 'static,
+{
+    // there is only one seat, so the one asked for doesn't matter
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ZwpInputMethodManagerV2,
+        request: zwp_input_method_manager_v2::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwp_input_method_manager_v2::Request::GetInputMethod { input_method, .. } = request {
+            let input_method = data_init.init(input_method, InputMethodData::default());
+            let input_method_state = state.input_method_state();
+            // a second input method never gets to do anything
+            if input_method_state.input_method.is_some() {
+                input_method.unavailable();
+                return;
+            }
+            input_method_state.input_method = Some(input_method);
+            input_method_state.active = false;
+            input_method_state.dones = 0;
+            state.input_method_changed();
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodV2, InputMethodData, D> for InputMethodState
+where
+    D: Dispatch<ZwpInputMethodV2, InputMethodData>
+        + Dispatch<ZwpInputPopupSurfaceV2, ()>
+        + Dispatch<ZwpInputMethodKeyboardGrabV2, ()>
+        + InputMethodHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        input_method: &ZwpInputMethodV2,
+        request: zwp_input_method_v2::Request,
+        data: &InputMethodData,
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let current = state.input_method_state().input_method.as_ref() == Some(input_method);
+        match request {
+            zwp_input_method_v2::Request::CommitString { text } => {
+                data.pending.lock().unwrap().commit = Some(text);
+            }
+            zwp_input_method_v2::Request::SetPreeditString {
+                text,
+                cursor_begin,
+                cursor_end,
+            } => {
+                data.pending.lock().unwrap().preedit = Some((text, cursor_begin, cursor_end));
+            }
+            zwp_input_method_v2::Request::DeleteSurroundingText {
+                before_length,
+                after_length,
+            } => {
+                data.pending.lock().unwrap().delete = Some((before_length, after_length));
+            }
+            zwp_input_method_v2::Request::Commit { serial } => {
+                let change = std::mem::take(&mut *data.pending.lock().unwrap());
+                // text composed for a text input that went away in the meantime is dropped
+                if current && serial == state.input_method_state().dones {
+                    state.input_method_commit(change);
+                }
+            }
+            zwp_input_method_v2::Request::GetInputPopupSurface { id, surface } => {
+                let resource = data_init.init(id, ());
+                if compositor::give_role(&surface, POPUP_SURFACE_ROLE).is_err() {
+                    input_method.post_error(
+                        zwp_input_method_v2::Error::Role,
+                        "the surface already has a role",
+                    );
+                    return;
+                }
+                if !current {
+                    return;
+                }
+                state
+                    .input_method_state()
+                    .popups
+                    .push(InputPopup { resource, surface });
+                state.popups_changed();
+            }
+            zwp_input_method_v2::Request::GrabKeyboard { keyboard } => {
+                let grab = data_init.init(keyboard, ());
+                if !current {
+                    return;
+                }
+                let input_method_state = state.input_method_state();
+                input_method_state.grab = Some(grab);
+                input_method_state.grab_modifiers = None;
+                state.keyboard_grabbed();
+            }
+            zwp_input_method_v2::Request::Destroy => {}
+            _ => {}
+        }
+    }
+
+    // The grab and popups go with the input method
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &InputMethodData) {
+        let input_method_state = state.input_method_state();
+        if input_method_state
+            .input_method
+            .as_ref()
+            .map_or(true, |input_method| input_method.id() != resource)
+        {
+            return;
+        }
+        input_method_state.input_method = None;
+        input_method_state.active = false;
+        input_method_state.grab = None;
+        input_method_state.keymap_file = None;
+        input_method_state.popups.clear();
+        state.input_method_changed();
+        state.popups_changed();
+    }
+}
+
+impl<D> Dispatch<ZwpInputPopupSurfaceV2, (), D> for InputMethodState
+where
+    D: Dispatch<ZwpInputPopupSurfaceV2, ()> + InputMethodHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _popup: &ZwpInputPopupSurfaceV2,
+        _request: zwp_input_popup_surface_v2::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let popups = &mut state.input_method_state().popups;
+        let count = popups.len();
+        popups.retain(|popup| popup.resource.id() != resource);
+        if popups.len() != count {
+            state.popups_changed();
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpInputMethodKeyboardGrabV2, (), D> for InputMethodState
+where
+    D: Dispatch<ZwpInputMethodKeyboardGrabV2, ()> + InputMethodHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _grab: &ZwpInputMethodKeyboardGrabV2,
+        _request: zwp_input_method_keyboard_grab_v2::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    // released or destroyed, keys go straight to the focused client again
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let input_method_state = state.input_method_state();
+        if input_method_state
+            .grab
+            .as_ref()
+            .map_or(false, |grab| grab.id() == resource)
+        {
+            input_method_state.grab = None;
+            input_method_state.keymap_file = None;
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_input_method {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_input_method_v2::server::zwp_input_method_manager_v2::ZwpInputMethodManagerV2: ()
+        ] => $crate::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_input_method_v2::server::zwp_input_method_manager_v2::ZwpInputMethodManagerV2: ()
+        ] => $crate::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_input_method_v2::server::zwp_input_method_v2::ZwpInputMethodV2: $crate::protocols::input_method::InputMethodData
+        ] => $crate::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_input_method_v2::server::zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2: ()
+        ] => $crate::protocols::input_method::InputMethodState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_input_method_v2::server::zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2: ()
+        ] => $crate::protocols::input_method::InputMethodState);
+    };
+}
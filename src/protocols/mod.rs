@@ -0,0 +1,18 @@
+// mod.rs
+// wayland protocols that smithay doesn't implement for us
+
+pub mod data_control;
+pub mod ext_foreign_toplevel_list;
+pub mod foreign_toplevel;
+pub mod idle_inhibit;
+pub mod idle_notify;
+pub mod input_method;
+pub mod output_management;
+pub mod output_power_management;
+pub mod pointer_constraints;
+pub mod pointer_gestures;
+pub mod screencopy;
+pub mod session_lock;
+pub mod text_input;
+pub mod virtual_keyboard;
+pub mod virtual_pointer;
@@ -0,0 +1,635 @@
+// wlr-output-management-unstable-v1, used by tools like wlr-randr and kanshi
+// smithay does not implement this one, so the protocol objects are handled here and the
+// actual reconfiguration is left to the OutputManagementHandler
+
+use std::sync::Mutex;
+
+use smithay::{
+    reexports::{
+        wayland_protocols_wlr::output_management::v1::server::{
+            zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
+            zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
+            zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
+            zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
+            zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Physical, Point, Size, Transform},
+};
+
+const VERSION: u32 = 2;
+
+// A mode of a head, the refresh rate is in mHz
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeadMode {
+    pub size: Size<i32, Physical>,
+    pub refresh: i32,
+    pub preferred: bool,
+}
+
+// Everything clients get told about a single connector
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadState {
+    pub name: String,
+    pub description: String,
+    pub make: String,
+    pub model: String,
+    pub physical_size: (i32, i32), // in millimeters
+    pub modes: Vec<HeadMode>,
+    pub current_mode: Option<usize>,
+    pub enabled: bool,
+    pub position: Point<i32, Logical>,
+    pub transform: Transform,
+    pub scale: f64,
+}
+
+// A mode a client asked for, either one we advertised or a custom one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestedMode {
+    Advertised(HeadMode),
+    Custom {
+        size: Size<i32, Physical>,
+        refresh: i32,
+    },
+}
+
+// The configuration a client requested for a single head
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadConfiguration {
+    pub name: String,
+    pub enabled: bool,
+    pub mode: Option<RequestedMode>,
+    pub position: Option<Point<i32, Logical>>,
+    pub transform: Option<Transform>,
+    pub scale: Option<f64>,
+}
+
+impl HeadConfiguration {
+    fn new(name: String, enabled: bool) -> Self {
+        Self {
+            name,
+            enabled,
+            mode: None,
+            position: None,
+            transform: None,
+            scale: None,
+        }
+    }
+}
+
+pub trait OutputManagementHandler {
+    fn output_management_state(&mut self) -> &mut OutputManagementState;
+    // applies (or only tests) a configuration covering every head, returns whether it worked
+    fn apply_output_configuration(
+        &mut self,
+        configuration: Vec<HeadConfiguration>,
+        test_only: bool,
+    ) -> bool;
+}
+
+struct HeadResources {
+    name: String,
+    head: ZwlrOutputHeadV1,
+    modes: Vec<ZwlrOutputModeV1>,
+}
+
+struct ManagerInstance {
+    manager: ZwlrOutputManagerV1,
+    heads: Vec<HeadResources>,
+}
+
+pub struct OutputManagementState {
+    display: DisplayHandle,
+    serial: u32,
+    heads: Vec<HeadState>,
+    managers: Vec<ManagerInstance>,
+}
+
+// user data of a zwlr_output_configuration_v1
+pub struct PendingConfiguration {
+    serial: u32,
+    used: bool,
+    enabled: Vec<ZwlrOutputConfigurationHeadV1>,
+    disabled: Vec<String>,
+}
+
+impl PendingConfiguration {
+    fn configures(&self, name: &str) -> bool {
+        self.disabled.iter().any(|disabled| disabled == name)
+            || self.enabled.iter().any(|head| {
+                head.data::<Mutex<HeadConfiguration>>()
+                    .map(|config| config.lock().unwrap().name == name)
+                    .unwrap_or(false)
+            })
+    }
+}
+
+impl OutputManagementState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrOutputManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwlrOutputManagerV1, _>(VERSION, ());
+        Self {
+            display: display.clone(),
+            serial: 0,
+            heads: Vec::new(),
+            managers: Vec::new(),
+        }
+    }
+
+    // Sends every change between the last and the new set of heads to all clients
+    pub fn update<D>(&mut self, heads: Vec<HeadState>)
+    where
+        D: Dispatch<ZwlrOutputHeadV1, String> + Dispatch<ZwlrOutputModeV1, HeadMode> + 'static,
+    {
+        if heads == self.heads {
+            return;
+        }
+        self.serial = self.serial.wrapping_add(1);
+
+        for instance in &mut self.managers {
+            let client = match self.display.get_client(instance.manager.id()) {
+                Ok(client) => client,
+                Err(_) => continue,
+            };
+
+            // heads whose connector went away, or whose modes changed, are re-sent as new heads
+            instance.heads.retain(|resources| {
+                let old = self.heads.iter().find(|head| head.name == resources.name);
+                let new = heads.iter().find(|head| head.name == resources.name);
+                match (old, new) {
+                    (Some(old), Some(new)) if old.modes == new.modes => true,
+                    _ => {
+                        finish_head(resources);
+                        false
+                    }
+                }
+            });
+
+            for new in &heads {
+                let old = self.heads.iter().find(|head| head.name == new.name);
+                match instance
+                    .heads
+                    .iter()
+                    .find(|resources| resources.name == new.name)
+                {
+                    Some(resources) => {
+                        if let Some(old) = old {
+                            send_head_changes(resources, old, new);
+                        }
+                    }
+                    None => {
+                        if let Some(resources) =
+                            send_new_head::<D>(&self.display, &client, &instance.manager, new)
+                        {
+                            instance.heads.push(resources);
+                        }
+                    }
+                }
+            }
+
+            instance.manager.done(self.serial);
+        }
+
+        self.heads = heads;
+    }
+
+    // whether `mode` was sent to a client as one of the modes of the head called `name`
+    fn head_has_mode(&self, name: &str, mode: &ZwlrOutputModeV1) -> bool {
+        self.managers.iter().any(|instance| {
+            instance
+                .heads
+                .iter()
+                .any(|resources| resources.name == name && resources.modes.contains(mode))
+        })
+    }
+}
+
+fn finish_head(resources: &HeadResources) {
+    for mode in &resources.modes {
+        mode.finished();
+    }
+    resources.head.finished();
+}
+
+fn send_new_head<D>(
+    display: &DisplayHandle,
+    client: &Client,
+    manager: &ZwlrOutputManagerV1,
+    state: &HeadState,
+) -> Option<HeadResources>
+where
+    D: Dispatch<ZwlrOutputHeadV1, String> + Dispatch<ZwlrOutputModeV1, HeadMode> + 'static,
+{
+    let head = client
+        .create_resource::<ZwlrOutputHeadV1, _, D>(display, manager.version(), state.name.clone())
+        .ok()?;
+    manager.head(&head);
+
+    head.name(state.name.clone());
+    head.description(state.description.clone());
+    if state.physical_size != (0, 0) {
+        head.physical_size(state.physical_size.0, state.physical_size.1);
+    }
+
+    let mut modes = Vec::with_capacity(state.modes.len());
+    for mode in &state.modes {
+        let resource = match client.create_resource::<ZwlrOutputModeV1, _, D>(
+            display,
+            manager.version(),
+            *mode,
+        ) {
+            Ok(resource) => resource,
+            Err(_) => continue,
+        };
+        head.mode(&resource);
+        resource.size(mode.size.w, mode.size.h);
+        if mode.refresh > 0 {
+            resource.refresh(mode.refresh);
+        }
+        if mode.preferred {
+            resource.preferred();
+        }
+        modes.push(resource);
+    }
+
+    if head.version() >= zwlr_output_head_v1::EVT_MAKE_SINCE {
+        head.make(state.make.clone());
+        head.model(state.model.clone());
+    }
+
+    let resources = HeadResources {
+        name: state.name.clone(),
+        head,
+        modes,
+    };
+    send_enabled_state(&resources, state);
+    Some(resources)
+}
+
+fn send_head_changes(resources: &HeadResources, old: &HeadState, new: &HeadState) {
+    if old.enabled != new.enabled
+        || old.current_mode != new.current_mode
+        || old.position != new.position
+        || old.transform != new.transform
+        || old.scale != new.scale
+    {
+        send_enabled_state(resources, new);
+    }
+}
+
+fn send_enabled_state(resources: &HeadResources, state: &HeadState) {
+    let head = &resources.head;
+    head.enabled(state.enabled as i32);
+    if !state.enabled {
+        return;
+    }
+
+    if let Some(mode) = state
+        .current_mode
+        .and_then(|index| resources.modes.get(index))
+    {
+        head.current_mode(mode);
+    }
+    head.position(state.position.x, state.position.y);
+    head.transform(state.transform.into());
+    head.scale(state.scale);
+}
+
+impl<D> GlobalDispatch<ZwlrOutputManagerV1, (), D> for OutputManagementState
+where
+    D: GlobalDispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputHeadV1, String>
+        + Dispatch<ZwlrOutputModeV1, HeadMode>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn bind(
+        state: &mut D,
+        display: &DisplayHandle,
+        client: &Client,
+        resource: New<ZwlrOutputManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let manager = data_init.init(resource, ());
+        let management_state = state.output_management_state();
+
+        let heads = management_state
+            .heads
+            .iter()
+            .filter_map(|head| send_new_head::<D>(display, client, &manager, head))
+            .collect();
+        manager.done(management_state.serial);
+
+        management_state
+            .managers
+            .push(ManagerInstance { manager, heads });
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputManagerV1, (), D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputManagerV1, ()>
+        + Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        manager: &ZwlrOutputManagerV1,
+        request: zwlr_output_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_manager_v1::Request::CreateConfiguration { id, serial } => {
+                data_init.init(
+                    id,
+                    Mutex::new(PendingConfiguration {
+                        serial,
+                        used: false,
+                        enabled: Vec::new(),
+                        disabled: Vec::new(),
+                    }),
+                );
+            }
+            zwlr_output_manager_v1::Request::Stop => {
+                let management_state = state.output_management_state();
+                if let Some(index) = management_state
+                    .managers
+                    .iter()
+                    .position(|instance| &instance.manager == manager)
+                {
+                    let instance = management_state.managers.remove(index);
+                    for resources in &instance.heads {
+                        finish_head(resources);
+                    }
+                }
+                manager.finished();
+            }
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .output_management_state()
+            .managers
+            .retain(|instance| instance.manager.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputHeadV1, String, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputHeadV1, String> + OutputManagementHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &ZwlrOutputHeadV1,
+        _request: zwlr_output_head_v1::Request,
+        _data: &String,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputModeV1, HeadMode, D> for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputModeV1, HeadMode> + OutputManagementHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _resource: &ZwlrOutputModeV1,
+        _request: zwlr_output_mode_v1::Request,
+        _data: &HeadMode,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>, D>
+    for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputConfigurationV1, Mutex<PendingConfiguration>>
+        + Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<HeadConfiguration>>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        configuration: &ZwlrOutputConfigurationV1,
+        request: zwlr_output_configuration_v1::Request,
+        data: &Mutex<PendingConfiguration>,
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_configuration_v1::Request::EnableHead { id, head } => {
+                let mut pending = data.lock().unwrap();
+                let name = head.data::<String>().cloned().unwrap_or_default();
+                if pending.configures(&name) {
+                    configuration.post_error(
+                        zwlr_output_configuration_v1::Error::AlreadyConfiguredHead,
+                        format!("head {} has already been configured", name),
+                    );
+                    return;
+                }
+                let head = data_init.init(id, Mutex::new(HeadConfiguration::new(name, true)));
+                pending.enabled.push(head);
+            }
+            zwlr_output_configuration_v1::Request::DisableHead { head } => {
+                let mut pending = data.lock().unwrap();
+                let name = head.data::<String>().cloned().unwrap_or_default();
+                if pending.configures(&name) {
+                    configuration.post_error(
+                        zwlr_output_configuration_v1::Error::AlreadyConfiguredHead,
+                        format!("head {} has already been configured", name),
+                    );
+                    return;
+                }
+                pending.disabled.push(name);
+            }
+            zwlr_output_configuration_v1::Request::Apply => {
+                apply_configuration(state, configuration, data, false);
+            }
+            zwlr_output_configuration_v1::Request::Test => {
+                apply_configuration(state, configuration, data, true);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn apply_configuration<D: OutputManagementHandler>(
+    state: &mut D,
+    configuration: &ZwlrOutputConfigurationV1,
+    data: &Mutex<PendingConfiguration>,
+    test_only: bool,
+) {
+    let mut pending = data.lock().unwrap();
+    if pending.used {
+        configuration.post_error(
+            zwlr_output_configuration_v1::Error::AlreadyUsed,
+            "configuration has already been applied or tested",
+        );
+        return;
+    }
+    pending.used = true;
+
+    let management_state = state.output_management_state();
+    if pending.serial != management_state.serial {
+        configuration.cancelled();
+        return;
+    }
+
+    if let Some(head) = management_state
+        .heads
+        .iter()
+        .find(|head| !pending.configures(&head.name))
+    {
+        configuration.post_error(
+            zwlr_output_configuration_v1::Error::UnconfiguredHead,
+            format!("head {} has not been configured", head.name),
+        );
+        return;
+    }
+
+    let heads = pending
+        .enabled
+        .iter()
+        .filter_map(|head| head.data::<Mutex<HeadConfiguration>>())
+        .map(|head| head.lock().unwrap().clone())
+        .chain(
+            pending
+                .disabled
+                .iter()
+                .map(|name| HeadConfiguration::new(name.clone(), false)),
+        )
+        .collect();
+    drop(pending);
+
+    if state.apply_output_configuration(heads, test_only) {
+        configuration.succeeded();
+    } else {
+        configuration.failed();
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<HeadConfiguration>, D>
+    for OutputManagementState
+where
+    D: Dispatch<ZwlrOutputConfigurationHeadV1, Mutex<HeadConfiguration>>
+        + OutputManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        resource: &ZwlrOutputConfigurationHeadV1,
+        request: zwlr_output_configuration_head_v1::Request,
+        data: &Mutex<HeadConfiguration>,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let mut head = data.lock().unwrap();
+        match request {
+            zwlr_output_configuration_head_v1::Request::SetMode { mode } => {
+                // only the modes advertised for this very head can be set on it
+                let belongs_to_head = state
+                    .output_management_state()
+                    .head_has_mode(&head.name, &mode);
+                let mode = match mode.data::<HeadMode>().filter(|_| belongs_to_head) {
+                    Some(mode) => *mode,
+                    None => {
+                        resource.post_error(
+                            zwlr_output_configuration_head_v1::Error::InvalidMode,
+                            "mode does not belong to this head",
+                        );
+                        return;
+                    }
+                };
+                head.mode = Some(RequestedMode::Advertised(mode));
+            }
+            zwlr_output_configuration_head_v1::Request::SetCustomMode {
+                width,
+                height,
+                refresh,
+            } => {
+                if width <= 0 || height <= 0 || refresh < 0 {
+                    resource.post_error(
+                        zwlr_output_configuration_head_v1::Error::InvalidCustomMode,
+                        "invalid custom mode",
+                    );
+                    return;
+                }
+                head.mode = Some(RequestedMode::Custom {
+                    size: (width, height).into(),
+                    refresh,
+                });
+            }
+            zwlr_output_configuration_head_v1::Request::SetPosition { x, y } => {
+                head.position = Some((x, y).into());
+            }
+            zwlr_output_configuration_head_v1::Request::SetTransform { transform } => {
+                match transform.into_result() {
+                    Ok(transform) => head.transform = Some(transform.into()),
+                    Err(_) => {
+                        resource.post_error(
+                            zwlr_output_configuration_head_v1::Error::InvalidTransform,
+                            "invalid transform",
+                        );
+                    }
+                }
+            }
+            zwlr_output_configuration_head_v1::Request::SetScale { scale } => {
+                if scale <= 0.0 {
+                    resource.post_error(
+                        zwlr_output_configuration_head_v1::Error::InvalidScale,
+                        "scale has to be positive",
+                    );
+                    return;
+                }
+                head.scale = Some(scale);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_output_management {
+    ($ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1: ()
+        ] => $crate::protocols::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_manager_v1::ZwlrOutputManagerV1: ()
+        ] => $crate::protocols::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_head_v1::ZwlrOutputHeadV1: String
+        ] => $crate::protocols::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_mode_v1::ZwlrOutputModeV1: $crate::protocols::output_management::HeadMode
+        ] => $crate::protocols::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_configuration_v1::ZwlrOutputConfigurationV1: std::sync::Mutex<$crate::protocols::output_management::PendingConfiguration>
+        ] => $crate::protocols::output_management::OutputManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_management::v1::server::zwlr_output_configuration_head_v1::ZwlrOutputConfigurationHeadV1: std::sync::Mutex<$crate::protocols::output_management::HeadConfiguration>
+        ] => $crate::protocols::output_management::OutputManagementState);
+    };
+}
@@ -0,0 +1,202 @@
+// wlr-output-power-management-unstable-v1, lets tools like wlopm or swayidle turn outputs off
+// the handler decides what turning an output off means, this only keeps track of the objects
+
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::output_power_management::v1::server::{
+            zwlr_output_power_manager_v1::{self, ZwlrOutputPowerManagerV1},
+            zwlr_output_power_v1::{self, ZwlrOutputPowerV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+};
+
+const VERSION: u32 = 1;
+
+pub trait OutputPowerManagementHandler {
+    fn output_power_management_state(&mut self) -> &mut OutputPowerManagementState;
+    // whether the output is powered on, None if its power can't be controlled
+    fn output_power(&mut self, output: &Output) -> Option<bool>;
+    // turns the output on or off, returns whether it worked
+    fn set_output_power(&mut self, output: &Output, on: bool) -> bool;
+}
+
+pub struct OutputPowerManagementState {
+    powers: Vec<(ZwlrOutputPowerV1, Output)>,
+}
+
+impl OutputPowerManagementState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrOutputPowerManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwlrOutputPowerManagerV1, _>(VERSION, ());
+        Self { powers: Vec::new() }
+    }
+
+    // Tells clients the output was turned on or off, whoever did it
+    pub fn power_changed(&self, output: &Output, on: bool) {
+        for (power, _) in self.powers.iter().filter(|(_, o)| o == output) {
+            power.mode(power_mode(on));
+        }
+    }
+
+    // The output went away, its power objects can't be used anymore
+    pub fn output_removed(&mut self, output: &Output) {
+        self.powers.retain(|(power, o)| {
+            if o == output {
+                power.failed();
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+fn power_mode(on: bool) -> zwlr_output_power_v1::Mode {
+    if on {
+        zwlr_output_power_v1::Mode::On
+    } else {
+        zwlr_output_power_v1::Mode::Off
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrOutputPowerManagerV1, (), D> for OutputPowerManagementState
+where
+    D: GlobalDispatch<ZwlrOutputPowerManagerV1, ()>
+        + Dispatch<ZwlrOutputPowerManagerV1, ()>
+        + OutputPowerManagementHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrOutputPowerManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputPowerManagerV1, (), D> for OutputPowerManagementState
+where
+    D: Dispatch<ZwlrOutputPowerManagerV1, ()>
+        + Dispatch<ZwlrOutputPowerV1, ()>
+        + OutputPowerManagementHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ZwlrOutputPowerManagerV1,
+        request: zwlr_output_power_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_power_manager_v1::Request::GetOutputPower { id, output } => {
+                let power = data_init.init(id, ());
+                let output = match Output::from_resource(&output) {
+                    Some(output) => output,
+                    None => {
+                        power.failed();
+                        return;
+                    }
+                };
+                match state.output_power(&output) {
+                    Some(on) => {
+                        power.mode(power_mode(on));
+                        state
+                            .output_power_management_state()
+                            .powers
+                            .push((power, output));
+                    }
+                    None => power.failed(),
+                }
+            }
+            zwlr_output_power_manager_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ZwlrOutputPowerV1, (), D> for OutputPowerManagementState
+where
+    D: Dispatch<ZwlrOutputPowerV1, ()> + OutputPowerManagementHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        power: &ZwlrOutputPowerV1,
+        request: zwlr_output_power_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwlr_output_power_v1::Request::SetMode { mode } => {
+                let output = match state
+                    .output_power_management_state()
+                    .powers
+                    .iter()
+                    .find(|(p, _)| p == power)
+                {
+                    Some((_, output)) => output.clone(),
+                    // already failed, requests are ignored from now on
+                    None => return,
+                };
+                let on = match mode {
+                    WEnum::Value(zwlr_output_power_v1::Mode::On) => true,
+                    WEnum::Value(zwlr_output_power_v1::Mode::Off) => false,
+                    _ => {
+                        power.post_error(
+                            zwlr_output_power_v1::Error::InvalidMode,
+                            "unknown power mode",
+                        );
+                        return;
+                    }
+                };
+                // the mode event is sent through power_changed once the handler is done
+                if !state.set_output_power(&output, on) {
+                    power.failed();
+                    state
+                        .output_power_management_state()
+                        .powers
+                        .retain(|(p, _)| p != power);
+                }
+            }
+            zwlr_output_power_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .output_power_management_state()
+            .powers
+            .retain(|(power, _)| power.id() != resource);
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_output_power_management {
+    ($ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_power_management::v1::server::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1: ()
+        ] => $crate::protocols::output_power_management::OutputPowerManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_power_management::v1::server::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1: ()
+        ] => $crate::protocols::output_power_management::OutputPowerManagementState);
+        smithay::reexports::wayland_server::delegate_dispatch!($ty: [
+            smithay::reexports::wayland_protocols_wlr::output_power_management::v1::server::zwlr_output_power_v1::ZwlrOutputPowerV1: ()
+        ] => $crate::protocols::output_power_management::OutputPowerManagementState);
+    };
+}
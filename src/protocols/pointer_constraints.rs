@@ -0,0 +1,350 @@
+// pointer-constraints-unstable-v1, lets games and 3D tools lock the pointer in place or confine it
+// to a region of their surface
+// this only keeps track of the constraints and tells clients when they start and stop applying,
+// actually holding the pointer back is up to the input code
+// regions and cursor position hints are double buffered, commit() has to be called for every
+// surface commit
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::pointer_constraints::zv1::server::{
+            zwp_confined_pointer_v1::{self, ZwpConfinedPointerV1},
+            zwp_locked_pointer_v1::{self, ZwpLockedPointerV1},
+            zwp_pointer_constraints_v1::{self, Lifetime, ZwpPointerConstraintsV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::{wl_region::WlRegion, wl_surface::WlSurface},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::{Logical, Point},
+    wayland::compositor::{get_region_attributes, RegionAttributes},
+};
+
+const VERSION: u32 = 1;
+
+pub trait PointerConstraintsHandler {
+    fn pointer_constraints_state(&mut self) -> &mut PointerConstraintsState;
+    // a constraint was created, it may apply right away if the pointer is already on its surface
+    fn new_constraint(&mut self, surface: &WlSurface);
+    // an active lock went away, the client would like the pointer to be at `location` now,
+    // relative to the surface
+    fn cursor_position_hint(&mut self, surface: &WlSurface, location: Point<f64, Logical>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstraintKind {
+    Locked,
+    Confined,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ConstraintResource {
+    Locked(ZwpLockedPointerV1),
+    Confined(ZwpConfinedPointerV1),
+}
+
+impl ConstraintResource {
+    fn id(&self) -> ObjectId {
+        match self {
+            ConstraintResource::Locked(resource) => resource.id(),
+            ConstraintResource::Confined(resource) => resource.id(),
+        }
+    }
+}
+
+pub struct PointerConstraint {
+    resource: ConstraintResource,
+    surface: WlSurface,
+    oneshot: bool,
+    active: bool,
+    // None is the whole surface
+    region: Option<RegionAttributes>,
+    pending_region: Option<Option<RegionAttributes>>,
+    cursor_hint: Option<Point<f64, Logical>>,
+    pending_cursor_hint: Option<Point<f64, Logical>>,
+}
+
+impl PointerConstraint {
+    pub fn kind(&self) -> ConstraintKind {
+        match self.resource {
+            ConstraintResource::Locked(_) => ConstraintKind::Locked,
+            ConstraintResource::Confined(_) => ConstraintKind::Confined,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Whether `location`, relative to the surface, is in the region of the constraint
+    pub fn region_contains(&self, location: Point<f64, Logical>) -> bool {
+        self.region
+            .as_ref()
+            .map_or(true, |region| region.contains(location.to_i32_floor()))
+    }
+}
+
+#[derive(Default)]
+pub struct PointerConstraintsState {
+    constraints: Vec<PointerConstraint>,
+}
+
+impl PointerConstraintsState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpPointerConstraintsV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwpPointerConstraintsV1, _>(VERSION, ());
+        Self::default()
+    }
+
+    // There is only one seat, so a surface has at most one constraint
+    pub fn constraint(&self, surface: &WlSurface) -> Option<&PointerConstraint> {
+        self.constraints
+            .iter()
+            .find(|constraint| &constraint.surface == surface)
+    }
+
+    // Starts applying the constraint on `surface`
+    pub fn activate(&mut self, surface: &WlSurface) {
+        let constraint = match self
+            .constraints
+            .iter_mut()
+            .find(|constraint| &constraint.surface == surface)
+        {
+            Some(constraint) if !constraint.active => constraint,
+            _ => return,
+        };
+        constraint.active = true;
+        match &constraint.resource {
+            ConstraintResource::Locked(resource) => resource.locked(),
+            ConstraintResource::Confined(resource) => resource.confined(),
+        }
+    }
+
+    // Stops applying every constraint except the one on `focus`, oneshot ones are gone after this
+    pub fn deactivate_except(&mut self, focus: Option<&WlSurface>) {
+        self.constraints.retain_mut(|constraint| {
+            if !constraint.active || Some(&constraint.surface) == focus {
+                return true;
+            }
+            constraint.active = false;
+            match &constraint.resource {
+                ConstraintResource::Locked(resource) => resource.unlocked(),
+                ConstraintResource::Confined(resource) => resource.unconfined(),
+            }
+            !constraint.oneshot
+        });
+    }
+
+    // Applies the pending region and cursor position hint of the constraint on `surface`
+    pub fn commit(&mut self, surface: &WlSurface) {
+        if let Some(constraint) = self
+            .constraints
+            .iter_mut()
+            .find(|constraint| &constraint.surface == surface)
+        {
+            if let Some(region) = constraint.pending_region.take() {
+                constraint.region = region;
+            }
+            if let Some(hint) = constraint.pending_cursor_hint.take() {
+                constraint.cursor_hint = Some(hint);
+            }
+        }
+    }
+
+    fn find_mut(&mut self, resource: ObjectId) -> Option<&mut PointerConstraint> {
+        self.constraints
+            .iter_mut()
+            .find(|constraint| constraint.resource.id() == resource)
+    }
+
+    // Forgets a destroyed constraint, returns it if it was active
+    fn remove(&mut self, resource: ObjectId) -> Option<PointerConstraint> {
+        let index = self
+            .constraints
+            .iter()
+            .position(|constraint| constraint.resource.id() == resource)?;
+        let constraint = self.constraints.remove(index);
+        constraint.active.then_some(constraint)
+    }
+}
+
+impl<D> GlobalDispatch<ZwpPointerConstraintsV1, (), D> for PointerConstraintsState
+where
+    D: GlobalDispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpPointerConstraintsV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpPointerConstraintsV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpPointerConstraintsV1, (), D> for PointerConstraintsState
+where
+    D: Dispatch<ZwpPointerConstraintsV1, ()>
+        + Dispatch<ZwpLockedPointerV1, ()>
+        + Dispatch<ZwpConfinedPointerV1, ()>
+        + PointerConstraintsHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        constraints: &ZwpPointerConstraintsV1,
+        request: zwp_pointer_constraints_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let (surface, region, lifetime, resource) = match request {
+            zwp_pointer_constraints_v1::Request::LockPointer {
+                id,
+                surface,
+                region,
+                lifetime,
+                ..
+            } => {
+                let resource = ConstraintResource::Locked(data_init.init(id, ()));
+                (surface, region, lifetime, resource)
+            }
+            zwp_pointer_constraints_v1::Request::ConfinePointer {
+                id,
+                surface,
+                region,
+                lifetime,
+                ..
+            } => {
+                let resource = ConstraintResource::Confined(data_init.init(id, ()));
+                (surface, region, lifetime, resource)
+            }
+            _ => return,
+        };
+
+        let constraints_state = state.pointer_constraints_state();
+        if constraints_state.constraint(&surface).is_some() {
+            constraints.post_error(
+                zwp_pointer_constraints_v1::Error::AlreadyConstrained,
+                "the surface already has a pointer constraint",
+            );
+            return;
+        }
+        constraints_state.constraints.push(PointerConstraint {
+            resource,
+            oneshot: lifetime != WEnum::Value(Lifetime::Persistent),
+            active: false,
+            region: region.as_ref().map(get_region_attributes),
+            pending_region: None,
+            cursor_hint: None,
+            pending_cursor_hint: None,
+            surface: surface.clone(),
+        });
+        state.new_constraint(&surface);
+    }
+}
+
+// Applies on the next commit of the surface
+fn set_region<D: PointerConstraintsHandler>(
+    state: &mut D,
+    resource: ObjectId,
+    region: Option<WlRegion>,
+) {
+    if let Some(constraint) = state.pointer_constraints_state().find_mut(resource) {
+        constraint.pending_region = Some(region.as_ref().map(get_region_attributes));
+    }
+}
+
+impl<D> Dispatch<ZwpLockedPointerV1, (), D> for PointerConstraintsState
+where
+    D: Dispatch<ZwpLockedPointerV1, ()> + PointerConstraintsHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        locked_pointer: &ZwpLockedPointerV1,
+        request: zwp_locked_pointer_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            zwp_locked_pointer_v1::Request::SetCursorPositionHint {
+                surface_x,
+                surface_y,
+            } => {
+                if let Some(constraint) = state
+                    .pointer_constraints_state()
+                    .find_mut(locked_pointer.id())
+                {
+                    constraint.pending_cursor_hint = Some((surface_x, surface_y).into());
+                }
+            }
+            zwp_locked_pointer_v1::Request::SetRegion { region } => {
+                set_region(state, locked_pointer.id(), region)
+            }
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let constraint = match state.pointer_constraints_state().remove(resource) {
+            Some(constraint) => constraint,
+            None => return,
+        };
+        if let Some(hint) = constraint.cursor_hint {
+            state.cursor_position_hint(&constraint.surface, hint);
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpConfinedPointerV1, (), D> for PointerConstraintsState
+where
+    D: Dispatch<ZwpConfinedPointerV1, ()> + PointerConstraintsHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        confined_pointer: &ZwpConfinedPointerV1,
+        request: zwp_confined_pointer_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwp_confined_pointer_v1::Request::SetRegion { region } = request {
+            set_region(state, confined_pointer.id(), region);
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state.pointer_constraints_state().remove(resource);
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_pointer_constraints {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1: ()
+        ] => $crate::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1: ()
+        ] => $crate::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_locked_pointer_v1::ZwpLockedPointerV1: ()
+        ] => $crate::protocols::pointer_constraints::PointerConstraintsState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_constraints::zv1::server::zwp_confined_pointer_v1::ZwpConfinedPointerV1: ()
+        ] => $crate::protocols::pointer_constraints::PointerConstraintsState);
+    };
+}
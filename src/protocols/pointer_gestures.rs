@@ -0,0 +1,256 @@
+// pointer-gestures-unstable-v1, forwards touchpad swipes, pinches and holds to clients so image
+// viewers and browsers can zoom and navigate
+// the compositor decides which gestures it keeps for itself, only the rest is sent here
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::pointer_gestures::zv1::server::{
+            zwp_pointer_gesture_hold_v1::{self, ZwpPointerGestureHoldV1},
+            zwp_pointer_gesture_pinch_v1::{self, ZwpPointerGesturePinchV1},
+            zwp_pointer_gesture_swipe_v1::{self, ZwpPointerGestureSwipeV1},
+            zwp_pointer_gestures_v1::{self, ZwpPointerGesturesV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Point, Serial},
+};
+
+const VERSION: u32 = 3;
+
+pub trait PointerGesturesHandler {
+    fn pointer_gestures_state(&mut self) -> &mut PointerGesturesState;
+}
+
+#[derive(Default)]
+pub struct PointerGesturesState {
+    swipes: Vec<ZwpPointerGestureSwipeV1>,
+    pinches: Vec<ZwpPointerGesturePinchV1>,
+    holds: Vec<ZwpPointerGestureHoldV1>,
+    // the surface the running gesture was sent to, libinput only runs one at a time
+    focus: Option<WlSurface>,
+}
+
+// The gesture objects of the client owning `surface`
+fn for_client<'a, R: Resource>(
+    resources: &'a [R],
+    surface: Option<&'a WlSurface>,
+) -> impl Iterator<Item = &'a R> {
+    resources.iter().filter(move |resource| {
+        surface.map_or(false, |surface| {
+            surface.is_alive() && resource.id().same_client_as(&surface.id())
+        })
+    })
+}
+
+impl PointerGesturesState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpPointerGesturesV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwpPointerGesturesV1, _>(VERSION, ());
+        Self::default()
+    }
+
+    pub fn swipe_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        self.focus = Some(surface.clone());
+        for swipe in for_client(&self.swipes, Some(surface)) {
+            swipe.begin(serial.into(), time, surface, fingers);
+        }
+    }
+
+    pub fn swipe_update(&self, time: u32, delta: Point<f64, Logical>) {
+        for swipe in for_client(&self.swipes, self.focus.as_ref()) {
+            swipe.update(time, delta.x, delta.y);
+        }
+    }
+
+    pub fn swipe_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        for swipe in for_client(&self.swipes, self.focus.as_ref()) {
+            swipe.end(serial.into(), time, cancelled as i32);
+        }
+        self.focus = None;
+    }
+
+    pub fn pinch_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        self.focus = Some(surface.clone());
+        for pinch in for_client(&self.pinches, Some(surface)) {
+            pinch.begin(serial.into(), time, surface, fingers);
+        }
+    }
+
+    // `scale` is relative to the start of the pinch, `rotation` to the last update, in degrees
+    pub fn pinch_update(&self, time: u32, delta: Point<f64, Logical>, scale: f64, rotation: f64) {
+        for pinch in for_client(&self.pinches, self.focus.as_ref()) {
+            pinch.update(time, delta.x, delta.y, scale, rotation);
+        }
+    }
+
+    pub fn pinch_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        for pinch in for_client(&self.pinches, self.focus.as_ref()) {
+            pinch.end(serial.into(), time, cancelled as i32);
+        }
+        self.focus = None;
+    }
+
+    pub fn hold_begin(&mut self, surface: &WlSurface, serial: Serial, time: u32, fingers: u32) {
+        self.focus = Some(surface.clone());
+        for hold in for_client(&self.holds, Some(surface)) {
+            hold.begin(serial.into(), time, surface, fingers);
+        }
+    }
+
+    pub fn hold_end(&mut self, serial: Serial, time: u32, cancelled: bool) {
+        for hold in for_client(&self.holds, self.focus.as_ref()) {
+            hold.end(serial.into(), time, cancelled as i32);
+        }
+        self.focus = None;
+    }
+}
+
+impl<D> GlobalDispatch<ZwpPointerGesturesV1, (), D> for PointerGesturesState
+where
+    D: GlobalDispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGesturesV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpPointerGesturesV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGesturesV1, (), D> for PointerGesturesState
+where
+    D: Dispatch<ZwpPointerGesturesV1, ()>
+        + Dispatch<ZwpPointerGestureSwipeV1, ()>
+        + Dispatch<ZwpPointerGesturePinchV1, ()>
+        + Dispatch<ZwpPointerGestureHoldV1, ()>
+        + PointerGesturesHandler
+        + 'static,
+{
+    // there is only one seat, so the pointer the gesture is for doesn't matter
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _gestures: &ZwpPointerGesturesV1,
+        request: zwp_pointer_gestures_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let gestures_state = state.pointer_gestures_state();
+        match request {
+            zwp_pointer_gestures_v1::Request::GetSwipeGesture { id, .. } => {
+                gestures_state.swipes.push(data_init.init(id, ()));
+            }
+            zwp_pointer_gestures_v1::Request::GetPinchGesture { id, .. } => {
+                gestures_state.pinches.push(data_init.init(id, ()));
+            }
+            zwp_pointer_gestures_v1::Request::GetHoldGesture { id, .. } => {
+                gestures_state.holds.push(data_init.init(id, ()));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGestureSwipeV1, (), D> for PointerGesturesState
+where
+    D: Dispatch<ZwpPointerGestureSwipeV1, ()> + PointerGesturesHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _swipe: &ZwpPointerGestureSwipeV1,
+        _request: zwp_pointer_gesture_swipe_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .swipes
+            .retain(|swipe| swipe.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGesturePinchV1, (), D> for PointerGesturesState
+where
+    D: Dispatch<ZwpPointerGesturePinchV1, ()> + PointerGesturesHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _pinch: &ZwpPointerGesturePinchV1,
+        _request: zwp_pointer_gesture_pinch_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .pinches
+            .retain(|pinch| pinch.id() != resource);
+    }
+}
+
+impl<D> Dispatch<ZwpPointerGestureHoldV1, (), D> for PointerGesturesState
+where
+    D: Dispatch<ZwpPointerGestureHoldV1, ()> + PointerGesturesHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _hold: &ZwpPointerGestureHoldV1,
+        _request: zwp_pointer_gesture_hold_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        state
+            .pointer_gestures_state()
+            .holds
+            .retain(|hold| hold.id() != resource);
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_pointer_gestures {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gestures_v1::ZwpPointerGesturesV1: ()
+        ] => $crate::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gestures_v1::ZwpPointerGesturesV1: ()
+        ] => $crate::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_swipe_v1::ZwpPointerGestureSwipeV1: ()
+        ] => $crate::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_pinch_v1::ZwpPointerGesturePinchV1: ()
+        ] => $crate::protocols::pointer_gestures::PointerGesturesState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::pointer_gestures::zv1::server::zwp_pointer_gesture_hold_v1::ZwpPointerGestureHoldV1: ()
+        ] => $crate::protocols::pointer_gestures::PointerGesturesState);
+    };
+}
@@ -0,0 +1,353 @@
+// wlr-screencopy-unstable-v1, used by grim and wf-recorder to capture outputs
+// this only validates the buffers, the actual copy is done by the handler when it renders the
+// output next
+// TODO: ext-image-copy-capture-v1, our wayland-protocols version doesn't have it yet
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use smithay::{
+    backend::allocator::Fourcc,
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::screencopy::v1::server::{
+            zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+            zwlr_screencopy_manager_v1::{self, ZwlrScreencopyManagerV1},
+        },
+        wayland_server::{
+            protocol::{wl_buffer::WlBuffer, wl_output::WlOutput, wl_shm},
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Buffer, Logical, Physical, Rectangle, Size},
+    wayland::{dmabuf, shm},
+};
+
+const VERSION: u32 = 3;
+// the renderer reads back RGBA, which is xbgr in wl_shm terms
+const SHM_FORMAT: wl_shm::Format = wl_shm::Format::Xbgr8888;
+const DMABUF_FORMAT: Fourcc = Fourcc::Xrgb8888;
+
+pub trait ScreencopyHandler {
+    fn screencopy_state(&mut self) -> &mut ScreencopyState;
+    // a client wants `frame` copied, the frame fails if it gets dropped without being submitted
+    fn frame(&mut self, frame: Screencopy);
+}
+
+pub struct ScreencopyState;
+
+impl ScreencopyState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrScreencopyManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwlrScreencopyManagerV1, _>(VERSION, ());
+        Self
+    }
+}
+
+pub struct ScreencopyFrameData {
+    // None if the output is already gone
+    output: Option<Output>,
+    // the captured part of the output, in the coordinates of its buffer
+    region: Rectangle<i32, Buffer>,
+    overlay_cursor: bool,
+    used: AtomicBool,
+}
+
+// A frame waiting for its buffer to be filled
+pub struct Screencopy {
+    frame: ZwlrScreencopyFrameV1,
+    buffer: WlBuffer,
+    output: Output,
+    region: Rectangle<i32, Buffer>,
+    overlay_cursor: bool,
+    with_damage: bool,
+    submitted: bool,
+}
+
+impl Screencopy {
+    pub fn buffer(&self) -> &WlBuffer {
+        &self.buffer
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    pub fn region(&self) -> Rectangle<i32, Buffer> {
+        self.region
+    }
+
+    pub fn overlay_cursor(&self) -> bool {
+        self.overlay_cursor
+    }
+
+    // copy_with_damage frames wait until the output changes
+    pub fn with_damage(&self) -> bool {
+        self.with_damage
+    }
+
+    // The buffer got filled, `time` is when the captured frame was rendered and `damage` is what
+    // it changed on the output
+    pub fn submit(mut self, damage: &[Rectangle<i32, Physical>], time: Duration) {
+        if self.with_damage {
+            // the damage is relative to the captured region
+            for rect in damage {
+                let rect = match physical_to_buffer(&self.output, *rect).intersection(self.region) {
+                    Some(rect) => rect,
+                    None => continue,
+                };
+                self.frame.damage(
+                    (rect.loc.x - self.region.loc.x) as u32,
+                    (rect.loc.y - self.region.loc.y) as u32,
+                    rect.size.w as u32,
+                    rect.size.h as u32,
+                );
+            }
+        }
+        self.frame.flags(zwlr_screencopy_frame_v1::Flags::empty());
+        let secs = time.as_secs();
+        self.frame
+            .ready((secs >> 32) as u32, secs as u32, time.subsec_nanos());
+        self.submitted = true;
+    }
+}
+
+impl Drop for Screencopy {
+    fn drop(&mut self) {
+        if !self.submitted {
+            self.frame.failed();
+        }
+    }
+}
+
+// Turns a region in output local logical coordinates into one in the output's buffer
+fn buffer_region(output: &Output, region: Rectangle<i32, Logical>) -> Rectangle<i32, Buffer> {
+    let scale = output.current_scale().fractional_scale();
+    physical_to_buffer(output, region.to_f64().to_physical(scale).to_i32_round())
+}
+
+// Turns a region of the transformed output, like the damage of a repaint, into one in its buffer
+fn physical_to_buffer(output: &Output, region: Rectangle<i32, Physical>) -> Rectangle<i32, Buffer> {
+    let mode_size = match output.current_mode() {
+        Some(mode) => mode.size,
+        None => return Rectangle::default(),
+    };
+    let transform = output.current_transform();
+
+    let output_size = transform.transform_size(mode_size);
+    let region = transform.invert().transform_rect_in(region, &output_size);
+    let region = Rectangle::<i32, Buffer>::from_loc_and_size(
+        (region.loc.x, region.loc.y),
+        (region.size.w, region.size.h),
+    );
+    let full = Rectangle::from_loc_and_size((0, 0), (mode_size.w, mode_size.h));
+    region.intersection(full).unwrap_or_default()
+}
+
+impl<D> GlobalDispatch<ZwlrScreencopyManagerV1, (), D> for ScreencopyState
+where
+    D: GlobalDispatch<ZwlrScreencopyManagerV1, ()>
+        + Dispatch<ZwlrScreencopyManagerV1, ()>
+        + ScreencopyHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrScreencopyManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwlrScreencopyManagerV1, (), D> for ScreencopyState
+where
+    D: Dispatch<ZwlrScreencopyManagerV1, ()>
+        + Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData>
+        + ScreencopyHandler
+        + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _manager: &ZwlrScreencopyManagerV1,
+        request: zwlr_screencopy_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let (frame, overlay_cursor, output, region) = match request {
+            zwlr_screencopy_manager_v1::Request::CaptureOutput {
+                frame,
+                overlay_cursor,
+                output,
+            } => (frame, overlay_cursor, output, None),
+            zwlr_screencopy_manager_v1::Request::CaptureOutputRegion {
+                frame,
+                overlay_cursor,
+                output,
+                x,
+                y,
+                width,
+                height,
+            } => (
+                frame,
+                overlay_cursor,
+                output,
+                Some(Rectangle::from_loc_and_size((x, y), (width, height))),
+            ),
+            _ => return,
+        };
+        init_frame(data_init, frame, overlay_cursor != 0, &output, region);
+    }
+}
+
+fn init_frame<D>(
+    data_init: &mut DataInit<'_, D>,
+    frame: New<ZwlrScreencopyFrameV1>,
+    overlay_cursor: bool,
+    output: &WlOutput,
+    region: Option<Rectangle<i32, Logical>>,
+) where
+    D: Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData> + 'static,
+{
+    let output = match Output::from_resource(output) {
+        Some(output) => output,
+        None => {
+            let frame = data_init.init(
+                frame,
+                ScreencopyFrameData {
+                    output: None,
+                    region: Rectangle::default(),
+                    overlay_cursor,
+                    used: AtomicBool::new(true),
+                },
+            );
+            frame.failed();
+            return;
+        }
+    };
+
+    let full = output
+        .current_mode()
+        .map(|mode| mode.size)
+        .unwrap_or_default();
+    let region = match region {
+        Some(region) => buffer_region(&output, region),
+        None => Rectangle::from_loc_and_size((0, 0), (full.w, full.h)),
+    };
+    let whole_output = region.loc == (0, 0).into() && region.size == Size::from((full.w, full.h));
+
+    let frame = data_init.init(
+        frame,
+        ScreencopyFrameData {
+            output: Some(output),
+            region,
+            overlay_cursor,
+            used: AtomicBool::new(false),
+        },
+    );
+    if region.size.w <= 0 || region.size.h <= 0 {
+        frame.failed();
+        return;
+    }
+
+    let (width, height) = (region.size.w as u32, region.size.h as u32);
+    frame.buffer(SHM_FORMAT, width, height, width * 4);
+    if frame.version() >= 3 {
+        // dmabufs are rendered to directly, which only works for the whole output
+        if whole_output {
+            frame.linux_dmabuf(DMABUF_FORMAT as u32, width, height);
+        }
+        frame.buffer_done();
+    }
+}
+
+impl<D> Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData, D> for ScreencopyState
+where
+    D: Dispatch<ZwlrScreencopyFrameV1, ScreencopyFrameData> + ScreencopyHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        frame: &ZwlrScreencopyFrameV1,
+        request: zwlr_screencopy_frame_v1::Request,
+        data: &ScreencopyFrameData,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let (buffer, with_damage) = match request {
+            zwlr_screencopy_frame_v1::Request::Copy { buffer } => (buffer, false),
+            zwlr_screencopy_frame_v1::Request::CopyWithDamage { buffer } => (buffer, true),
+            _ => return,
+        };
+
+        if data.used.swap(true, Ordering::SeqCst) {
+            // frames of outputs that are gone start out used, but they already failed
+            if data.output.is_none() {
+                return;
+            }
+            frame.post_error(
+                zwlr_screencopy_frame_v1::Error::AlreadyUsed,
+                "the frame was already copied",
+            );
+            return;
+        }
+        if let Err(reason) = check_buffer(&buffer, data.region.size) {
+            frame.post_error(zwlr_screencopy_frame_v1::Error::InvalidBuffer, reason);
+            return;
+        }
+
+        state.frame(Screencopy {
+            frame: frame.clone(),
+            buffer,
+            output: data.output.clone().unwrap(),
+            region: data.region,
+            overlay_cursor: data.overlay_cursor,
+            with_damage,
+            submitted: false,
+        });
+    }
+}
+
+// Makes sure the buffer is one of the ones we offered
+fn check_buffer(buffer: &WlBuffer, size: Size<i32, Buffer>) -> Result<(), &'static str> {
+    if let Ok(dmabuf) = dmabuf::get_dmabuf(buffer) {
+        if dmabuf.size() != size {
+            return Err("the dmabuf has the wrong size");
+        }
+        return Ok(());
+    }
+
+    let data = shm::with_buffer_contents(buffer, |_, data| data)
+        .map_err(|_| "the buffer is neither shm nor a dmabuf")?;
+    if data.format != SHM_FORMAT {
+        return Err("the shm buffer has the wrong format");
+    }
+    if data.width != size.w || data.height != size.h || data.stride < size.w * 4 {
+        return Err("the shm buffer has the wrong size");
+    }
+    Ok(())
+}
+
+#[macro_export]
+macro_rules! delegate_screencopy {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1: ()
+        ] => $crate::protocols::screencopy::ScreencopyState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1: ()
+        ] => $crate::protocols::screencopy::ScreencopyState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::screencopy::v1::server::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1: $crate::protocols::screencopy::ScreencopyFrameData
+        ] => $crate::protocols::screencopy::ScreencopyState);
+    };
+}
@@ -0,0 +1,286 @@
+// ext-session-lock-v1, lets lockers like swaylock lock the session
+// the session stays locked until the locker unlocks it, a locker that dies leaves it locked and
+// a new locker can take over
+
+use smithay::{
+    output::Output,
+    reexports::{
+        wayland_protocols::ext::session_lock::v1::server::{
+            ext_session_lock_manager_v1::{self, ExtSessionLockManagerV1},
+            ext_session_lock_surface_v1::{self, ExtSessionLockSurfaceV1},
+            ext_session_lock_v1::{self, ExtSessionLockV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+    utils::{Logical, Size, SERIAL_COUNTER},
+    wayland::compositor,
+};
+
+const VERSION: u32 = 1;
+const LOCK_SURFACE_ROLE: &str = "ext_session_lock_surface_v1";
+
+pub trait SessionLockHandler {
+    fn session_lock_state(&mut self) -> &mut SessionLockState;
+    // the session got locked, nothing but lock surfaces may be shown from now on
+    fn lock(&mut self);
+    fn unlock(&mut self);
+    fn new_lock_surface(&mut self, surface: LockSurface);
+    fn lock_surface_destroyed(&mut self, surface: LockSurface);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockSurface {
+    resource: ExtSessionLockSurfaceV1,
+    surface: WlSurface,
+    output: Output,
+}
+
+impl LockSurface {
+    pub fn wl_surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    pub fn output(&self) -> &Output {
+        &self.output
+    }
+
+    // Lock surfaces always cover their whole output
+    pub fn configure(&self, size: Size<i32, Logical>) {
+        let serial = SERIAL_COUNTER.next_serial();
+        self.resource
+            .configure(serial.into(), size.w as u32, size.h as u32);
+    }
+}
+
+pub struct SessionLockState {
+    locked: bool,
+    locker: Option<ExtSessionLockV1>,
+    surfaces: Vec<LockSurface>,
+}
+
+impl SessionLockState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ExtSessionLockManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ExtSessionLockManagerV1, _>(VERSION, ());
+        Self {
+            locked: false,
+            locker: None,
+            surfaces: Vec::new(),
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn lock_surface(&self, output: &Output) -> Option<&LockSurface> {
+        self.surfaces
+            .iter()
+            .find(|surface| &surface.output == output)
+    }
+
+    fn is_locker(&self, lock: &ExtSessionLockV1) -> bool {
+        self.locker.as_ref() == Some(lock)
+    }
+}
+
+impl<D> GlobalDispatch<ExtSessionLockManagerV1, (), D> for SessionLockState
+where
+    D: GlobalDispatch<ExtSessionLockManagerV1, ()>
+        + Dispatch<ExtSessionLockManagerV1, ()>
+        + SessionLockHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ExtSessionLockManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ExtSessionLockManagerV1, (), D> for SessionLockState
+where
+    D: Dispatch<ExtSessionLockManagerV1, ()>
+        + Dispatch<ExtSessionLockV1, ()>
+        + SessionLockHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ExtSessionLockManagerV1,
+        request: ext_session_lock_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            ext_session_lock_manager_v1::Request::Lock { id } => {
+                let lock = data_init.init(id, ());
+                let lock_state = state.session_lock_state();
+                // only one locker at a time, unless the previous one died
+                if lock_state.locker.is_some() {
+                    lock.finished();
+                    return;
+                }
+                lock_state.locker = Some(lock.clone());
+                lock_state.locked = true;
+                // every frame rendered from here on only shows lock surfaces, so there is
+                // nothing left to wait for
+                state.lock();
+                lock.locked();
+            }
+            ext_session_lock_manager_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+}
+
+impl<D> Dispatch<ExtSessionLockV1, (), D> for SessionLockState
+where
+    D: Dispatch<ExtSessionLockV1, ()>
+        + Dispatch<ExtSessionLockSurfaceV1, ()>
+        + SessionLockHandler
+        + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        lock: &ExtSessionLockV1,
+        request: ext_session_lock_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        match request {
+            ext_session_lock_v1::Request::GetLockSurface {
+                id,
+                surface,
+                output,
+            } => {
+                let resource = data_init.init(id, ());
+                // surfaces of a lock that got finished are never shown
+                if !state.session_lock_state().is_locker(lock) {
+                    return;
+                }
+                let output = match Output::from_resource(&output) {
+                    Some(output) => output,
+                    None => return,
+                };
+                if state.session_lock_state().lock_surface(&output).is_some() {
+                    lock.post_error(
+                        ext_session_lock_v1::Error::DuplicateOutput,
+                        "the output already has a lock surface",
+                    );
+                    return;
+                }
+                if compositor::give_role(&surface, LOCK_SURFACE_ROLE).is_err() {
+                    lock.post_error(
+                        ext_session_lock_v1::Error::Role,
+                        "the surface already has a role",
+                    );
+                    return;
+                }
+
+                let lock_surface = LockSurface {
+                    resource,
+                    surface,
+                    output,
+                };
+                state
+                    .session_lock_state()
+                    .surfaces
+                    .push(lock_surface.clone());
+                state.new_lock_surface(lock_surface);
+            }
+            ext_session_lock_v1::Request::UnlockAndDestroy => {
+                let lock_state = state.session_lock_state();
+                if !lock_state.is_locker(lock) {
+                    return;
+                }
+                lock_state.locker = None;
+                lock_state.locked = false;
+                lock_state.surfaces.clear();
+                state.unlock();
+            }
+            ext_session_lock_v1::Request::Destroy => {
+                if state.session_lock_state().is_locker(lock) {
+                    lock.post_error(
+                        ext_session_lock_v1::Error::InvalidDestroy,
+                        "the session is locked, use unlock_and_destroy",
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The session stays locked when the locker goes away without unlocking
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let lock_state = state.session_lock_state();
+        if lock_state
+            .locker
+            .as_ref()
+            .map_or(false, |locker| locker.id() == resource)
+        {
+            tracing::warn!("The locker went away without unlocking, the session stays locked");
+            lock_state.locker = None;
+        }
+    }
+}
+
+impl<D> Dispatch<ExtSessionLockSurfaceV1, (), D> for SessionLockState
+where
+    D: Dispatch<ExtSessionLockSurfaceV1, ()> + SessionLockHandler + 'static,
+{
+    fn request(
+        _state: &mut D,
+        _client: &Client,
+        _surface: &ExtSessionLockSurfaceV1,
+        _request: ext_session_lock_surface_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let surfaces = &mut state.session_lock_state().surfaces;
+        if let Some(index) = surfaces
+            .iter()
+            .position(|surface| surface.resource.id() == resource)
+        {
+            let surface = surfaces.remove(index);
+            state.lock_surface_destroyed(surface);
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_session_lock {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::session_lock::v1::server::ext_session_lock_manager_v1::ExtSessionLockManagerV1: ()
+        ] => $crate::protocols::session_lock::SessionLockState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::session_lock::v1::server::ext_session_lock_manager_v1::ExtSessionLockManagerV1: ()
+        ] => $crate::protocols::session_lock::SessionLockState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::session_lock::v1::server::ext_session_lock_v1::ExtSessionLockV1: ()
+        ] => $crate::protocols::session_lock::SessionLockState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::ext::session_lock::v1::server::ext_session_lock_surface_v1::ExtSessionLockSurfaceV1: ()
+        ] => $crate::protocols::session_lock::SessionLockState);
+    };
+}
@@ -0,0 +1,310 @@
+// text-input-unstable-v3, lets clients take composed text from an input method instead of keys
+// only the enabled text input of the client with the keyboard focus is active, the handler
+// passes its state on to the input method and the text back
+
+use std::sync::Mutex;
+
+use smithay::{
+    reexports::{
+        wayland_protocols::wp::text_input::zv3::server::{
+            zwp_text_input_manager_v3::{self, ZwpTextInputManagerV3},
+            zwp_text_input_v3::{self, ChangeCause, ContentHint, ContentPurpose, ZwpTextInputV3},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_surface::WlSurface,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::{Logical, Rectangle},
+};
+
+const VERSION: u32 = 1;
+
+pub trait TextInputHandler {
+    fn text_input_state(&mut self) -> &mut TextInputState;
+    // the active text input committed new state, or another one became active
+    fn text_input_updated(&mut self);
+}
+
+// What a text input told about the text around the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextInputProperties {
+    pub enabled: bool,
+    // the text around the cursor, with the cursor and anchor as byte offsets
+    pub surrounding_text: Option<(String, i32, i32)>,
+    pub change_cause: ChangeCause,
+    pub content_hint: ContentHint,
+    pub content_purpose: ContentPurpose,
+    // in the coordinates of the focused surface
+    pub cursor_rectangle: Option<Rectangle<i32, Logical>>,
+}
+
+impl Default for TextInputProperties {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            surrounding_text: None,
+            change_cause: ChangeCause::InputMethod,
+            content_hint: ContentHint::None,
+            content_purpose: ContentPurpose::Normal,
+            cursor_rectangle: None,
+        }
+    }
+}
+
+// Text for the active text input, sent together in one done
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextChange {
+    // the text being composed, with the cursor as byte offsets into it
+    pub preedit: Option<(String, i32, i32)>,
+    pub commit: Option<String>,
+    // bytes to remove before and after the cursor
+    pub delete: Option<(u32, u32)>,
+}
+
+#[derive(Default)]
+pub struct TextInputData {
+    inner: Mutex<TextInputInner>,
+}
+
+#[derive(Default)]
+struct TextInputInner {
+    pending: TextInputProperties,
+    current: TextInputProperties,
+    // done events carry the number of commits so far
+    commits: u32,
+}
+
+pub struct TextInputState {
+    text_inputs: Vec<ZwpTextInputV3>,
+    focus: Option<WlSurface>,
+}
+
+fn data(text_input: &ZwpTextInputV3) -> &TextInputData {
+    text_input.data::<TextInputData>().unwrap()
+}
+
+impl TextInputState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpTextInputManagerV3, ()> + 'static,
+    {
+        display.create_global::<D, ZwpTextInputManagerV3, _>(VERSION, ());
+        Self {
+            text_inputs: Vec::new(),
+            focus: None,
+        }
+    }
+
+    pub fn focus(&self) -> Option<&WlSurface> {
+        self.focus.as_ref()
+    }
+
+    fn for_client<'a>(
+        &'a self,
+        surface: &'a WlSurface,
+    ) -> impl Iterator<Item = &'a ZwpTextInputV3> + 'a {
+        self.text_inputs
+            .iter()
+            .filter(move |text_input| text_input.id().same_client_as(&surface.id()))
+    }
+
+    // Follows the keyboard focus, leaving a surface disables its text inputs
+    pub fn set_focus(&mut self, surface: Option<&WlSurface>) {
+        if self.focus.as_ref() == surface {
+            return;
+        }
+        if let Some(old) = self.focus.take() {
+            for text_input in self.for_client(&old) {
+                let mut inner = data(text_input).inner.lock().unwrap();
+                inner.pending.enabled = false;
+                inner.current.enabled = false;
+                if old.is_alive() {
+                    text_input.leave(&old);
+                }
+            }
+        }
+        self.focus = surface.cloned();
+        if let Some(new) = &self.focus {
+            for text_input in self.for_client(new) {
+                text_input.enter(new);
+            }
+        }
+    }
+
+    fn active(&self) -> Option<&ZwpTextInputV3> {
+        let focus = self.focus.as_ref()?;
+        self.for_client(focus)
+            .find(|text_input| data(text_input).inner.lock().unwrap().current.enabled)
+    }
+
+    // The state of the active text input, None if there is none
+    pub fn active_properties(&self) -> Option<TextInputProperties> {
+        self.active()
+            .map(|text_input| data(text_input).inner.lock().unwrap().current.clone())
+    }
+
+    // Sends text to the active text input
+    pub fn send_change(&self, change: &TextChange) {
+        let text_input = match self.active() {
+            Some(text_input) => text_input,
+            None => return,
+        };
+        match &change.preedit {
+            Some((text, begin, end)) => text_input.preedit_string(Some(text.clone()), *begin, *end),
+            None => text_input.preedit_string(None, 0, 0),
+        }
+        if let Some(text) = &change.commit {
+            text_input.commit_string(Some(text.clone()));
+        }
+        if let Some((before, after)) = change.delete {
+            text_input.delete_surrounding_text(before, after);
+        }
+        text_input.done(data(text_input).inner.lock().unwrap().commits);
+    }
+}
+
+impl<D> GlobalDispatch<ZwpTextInputManagerV3, (), D> for TextInputState
+where
+    D: GlobalDispatch<ZwpTextInputManagerV3, ()>
+        + Dispatch<ZwpTextInputManagerV3, ()>
+        + TextInputHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpTextInputManagerV3>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpTextInputManagerV3, (), D> for TextInputState
+where
+    D: Dispatch<ZwpTextInputManagerV3, ()>
+        + Dispatch<ZwpTextInputV3, TextInputData>
+        + TextInputHandler
+        + 'static,
+{
+    // there is only one seat, so the one asked for doesn't matter
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        _manager: &ZwpTextInputManagerV3,
+        request: zwp_text_input_manager_v3::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwp_text_input_manager_v3::Request::GetTextInput { id, .. } = request {
+            let text_input = data_init.init(id, TextInputData::default());
+            let text_input_state = state.text_input_state();
+            // the client may already have the focus
+            if let Some(focus) = &text_input_state.focus {
+                if text_input.id().same_client_as(&focus.id()) {
+                    text_input.enter(focus);
+                }
+            }
+            text_input_state.text_inputs.push(text_input);
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpTextInputV3, TextInputData, D> for TextInputState
+where
+    D: Dispatch<ZwpTextInputV3, TextInputData> + TextInputHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        text_input: &ZwpTextInputV3,
+        request: zwp_text_input_v3::Request,
+        data: &TextInputData,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        let mut inner = data.inner.lock().unwrap();
+        match request {
+            // enabling starts over with a clean state
+            zwp_text_input_v3::Request::Enable => {
+                inner.pending = TextInputProperties {
+                    enabled: true,
+                    ..Default::default()
+                };
+            }
+            zwp_text_input_v3::Request::Disable => inner.pending.enabled = false,
+            zwp_text_input_v3::Request::SetSurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => inner.pending.surrounding_text = Some((text, cursor, anchor)),
+            zwp_text_input_v3::Request::SetTextChangeCause { cause } => {
+                if let WEnum::Value(cause) = cause {
+                    inner.pending.change_cause = cause;
+                }
+            }
+            zwp_text_input_v3::Request::SetContentType { hint, purpose } => {
+                if let WEnum::Value(hint) = hint {
+                    inner.pending.content_hint = hint;
+                }
+                if let WEnum::Value(purpose) = purpose {
+                    inner.pending.content_purpose = purpose;
+                }
+            }
+            zwp_text_input_v3::Request::SetCursorRectangle {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                inner.pending.cursor_rectangle =
+                    Some(Rectangle::from_loc_and_size((x, y), (width, height)))
+            }
+            zwp_text_input_v3::Request::Commit => {
+                inner.current = inner.pending.clone();
+                inner.commits = inner.commits.wrapping_add(1);
+                drop(inner);
+                // text inputs without the focus can commit all they want
+                let focused = state
+                    .text_input_state()
+                    .focus
+                    .as_ref()
+                    .map_or(false, |focus| text_input.id().same_client_as(&focus.id()));
+                if focused {
+                    state.text_input_updated();
+                }
+            }
+            zwp_text_input_v3::Request::Destroy => {}
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &TextInputData) {
+        state
+            .text_input_state()
+            .text_inputs
+            .retain(|text_input| text_input.id() != resource);
+        // it may have been the active one
+        state.text_input_updated();
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_text_input {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3: ()
+        ] => $crate::protocols::text_input::TextInputState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_manager_v3::ZwpTextInputManagerV3: ()
+        ] => $crate::protocols::text_input::TextInputState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols::wp::text_input::zv3::server::zwp_text_input_v3::ZwpTextInputV3: $crate::protocols::text_input::TextInputData
+        ] => $crate::protocols::text_input::TextInputState);
+    };
+}
@@ -0,0 +1,334 @@
+// virtual-keyboard-unstable-v1, lets clients type like a keyboard, for wtype and remote desktops
+// every virtual keyboard brings its own keymap, the handler decides what its keys do
+
+use std::{
+    fs::File,
+    os::unix::{fs::FileExt, io::FromRawFd},
+};
+
+use smithay::{
+    backend::input::KeyState,
+    input::keyboard::{xkb, ModifiersState},
+    reexports::{
+        wayland_protocols_misc::zwp_virtual_keyboard_v1::server::{
+            zwp_virtual_keyboard_manager_v1::{self, ZwpVirtualKeyboardManagerV1},
+            zwp_virtual_keyboard_v1::{self, ZwpVirtualKeyboardV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_keyboard::KeymapFormat,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource,
+        },
+    },
+};
+
+const VERSION: u32 = 1;
+// real keymaps are around 100 KiB, anything much bigger is someone trying to waste our memory
+const MAX_KEYMAP_SIZE: u32 = 1 << 20;
+
+pub trait VirtualKeyboardHandler {
+    fn virtual_keyboard_state(&mut self) -> &mut VirtualKeyboardState;
+    // whether `client` may create virtual keyboards at all
+    fn virtual_keyboard_allowed(&mut self, client: &Client) -> bool;
+    // `keycode` is an evdev code, to be read with `keymap`. `modifiers` are the ones of this
+    // keyboard before the key
+    fn virtual_key(
+        &mut self,
+        keyboard: &ZwpVirtualKeyboardV1,
+        keymap: &str,
+        modifiers: ModifiersState,
+        time: u32,
+        keycode: u32,
+        state: KeyState,
+    );
+    // the modifiers or layout of a virtual keyboard changed, by a key or because the client set
+    // them
+    fn virtual_modifiers(
+        &mut self,
+        keyboard: &ZwpVirtualKeyboardV1,
+        keymap: &str,
+        modifiers: ModifiersState,
+    );
+    // a virtual keyboard was created or destroyed
+    fn virtual_keyboards_changed(&mut self);
+}
+
+pub struct VirtualKeyboardState {
+    keyboards: Vec<VirtualKeyboard>,
+}
+
+// Every virtual keyboard types into an xkb state of its own, so its modifiers don't get mixed up
+// with the ones of the seat. It can't type before it uploaded a keymap
+struct VirtualKeyboard {
+    keyboard: ZwpVirtualKeyboardV1,
+    keymap: Option<VirtualKeymap>,
+}
+
+struct VirtualKeymap {
+    text: String,
+    state: xkb::State,
+}
+
+impl VirtualKeymap {
+    fn new(text: String) -> Option<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let keymap = xkb::Keymap::new_from_string(
+            &context,
+            text.clone(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )?;
+        Some(Self {
+            text,
+            state: xkb::State::new(&keymap),
+        })
+    }
+
+    fn modifiers(&self) -> ModifiersState {
+        let state = &self.state;
+        let active = |name| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+        let mut modifiers = ModifiersState::default();
+        modifiers.ctrl = active(xkb::MOD_NAME_CTRL);
+        modifiers.alt = active(xkb::MOD_NAME_ALT);
+        modifiers.shift = active(xkb::MOD_NAME_SHIFT);
+        modifiers.caps_lock = active(xkb::MOD_NAME_CAPS);
+        modifiers.logo = active(xkb::MOD_NAME_LOGO);
+        modifiers.num_lock = active(xkb::MOD_NAME_NUM);
+        modifiers.serialized.depressed = state.serialize_mods(xkb::STATE_MODS_DEPRESSED);
+        modifiers.serialized.latched = state.serialize_mods(xkb::STATE_MODS_LATCHED);
+        modifiers.serialized.locked = state.serialize_mods(xkb::STATE_MODS_LOCKED);
+        modifiers.serialized.layout_locked = state.serialize_layout(xkb::STATE_LAYOUT_LOCKED);
+        modifiers
+    }
+}
+
+impl VirtualKeyboardState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwpVirtualKeyboardManagerV1, _>(VERSION, ());
+        Self {
+            keyboards: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyboards.is_empty()
+    }
+
+    fn keymap(&mut self, keyboard: &ZwpVirtualKeyboardV1) -> Option<&mut Option<VirtualKeymap>> {
+        self.keyboards
+            .iter_mut()
+            .find(|virtual_keyboard| virtual_keyboard.keyboard == *keyboard)
+            .map(|virtual_keyboard| &mut virtual_keyboard.keymap)
+    }
+}
+
+// Keymaps are shared like the ones of wl_keyboard, `size` includes the terminating nul
+fn read_keymap(file: &File, size: u32) -> std::io::Result<String> {
+    // the client can claim any size, the file can't lie about its own
+    let size = u64::from(size).min(file.metadata()?.len());
+    let mut keymap = vec![0; size as usize];
+    // the offset of the fd is shared with the client, so it can't be trusted
+    file.read_exact_at(&mut keymap, 0)?;
+    let end = keymap
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(keymap.len());
+    keymap.truncate(end);
+    String::from_utf8(keymap)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+impl<D> GlobalDispatch<ZwpVirtualKeyboardManagerV1, (), D> for VirtualKeyboardState
+where
+    D: GlobalDispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + VirtualKeyboardHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwpVirtualKeyboardManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwpVirtualKeyboardManagerV1, (), D> for VirtualKeyboardState
+where
+    D: Dispatch<ZwpVirtualKeyboardManagerV1, ()>
+        + Dispatch<ZwpVirtualKeyboardV1, ()>
+        + VirtualKeyboardHandler
+        + 'static,
+{
+    // there is only one seat, so the one asked for doesn't matter
+    fn request(
+        state: &mut D,
+        client: &Client,
+        manager: &ZwpVirtualKeyboardManagerV1,
+        request: zwp_virtual_keyboard_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        if let zwp_virtual_keyboard_manager_v1::Request::CreateVirtualKeyboard { id, .. } = request
+        {
+            let keyboard = data_init.init(id, ());
+            if !state.virtual_keyboard_allowed(client) {
+                manager.post_error(
+                    zwp_virtual_keyboard_manager_v1::Error::Unauthorized,
+                    "virtual keyboards are not allowed for this client",
+                );
+                return;
+            }
+            state
+                .virtual_keyboard_state()
+                .keyboards
+                .push(VirtualKeyboard {
+                    keyboard,
+                    keymap: None,
+                });
+            state.virtual_keyboards_changed();
+        }
+    }
+}
+
+impl<D> Dispatch<ZwpVirtualKeyboardV1, (), D> for VirtualKeyboardState
+where
+    D: Dispatch<ZwpVirtualKeyboardV1, ()> + VirtualKeyboardHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        keyboard: &ZwpVirtualKeyboardV1,
+        request: zwp_virtual_keyboard_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        // keyboards of clients that weren't allowed were never added
+        let keymap = match state.virtual_keyboard_state().keymap(keyboard) {
+            Some(keymap) => keymap,
+            None => return,
+        };
+        match request {
+            zwp_virtual_keyboard_v1::Request::Keymap { format, fd, size } => {
+                // the fd is ours now, closed when the file goes away
+                let file = unsafe { File::from_raw_fd(fd) };
+                if format != KeymapFormat::XkbV1 as u32 {
+                    tracing::warn!(
+                        "Virtual keyboard uploaded a keymap of unknown format {}",
+                        format
+                    );
+                    return;
+                }
+                if size > MAX_KEYMAP_SIZE {
+                    keyboard.post_error(
+                        zwp_virtual_keyboard_v1::Error::NoKeymap,
+                        format!("keymaps can't be larger than {} bytes", MAX_KEYMAP_SIZE),
+                    );
+                    return;
+                }
+                let text = match read_keymap(&file, size) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        tracing::warn!("Unable to read virtual keyboard keymap: {}", err);
+                        return;
+                    }
+                };
+                match VirtualKeymap::new(text) {
+                    Some(virtual_keymap) => *keymap = Some(virtual_keymap),
+                    None => tracing::warn!("Virtual keyboard uploaded a keymap xkb can't read"),
+                }
+            }
+            zwp_virtual_keyboard_v1::Request::Key {
+                time,
+                key,
+                state: key_state,
+            } => {
+                let keymap = match keymap {
+                    Some(keymap) => keymap,
+                    None => {
+                        keyboard.post_error(
+                            zwp_virtual_keyboard_v1::Error::NoKeymap,
+                            "a keymap has to be set before typing",
+                        );
+                        return;
+                    }
+                };
+                // 1 is pressed in wl_keyboard.key_state
+                let (key_state, direction) = if key_state == 1 {
+                    (KeyState::Pressed, xkb::KeyDirection::Down)
+                } else {
+                    (KeyState::Released, xkb::KeyDirection::Up)
+                };
+                let before = keymap.modifiers();
+                // xkb keycodes are evdev ones plus 8
+                let changed = keymap.state.update_key(key.saturating_add(8), direction);
+                let after = keymap.modifiers();
+                let text = keymap.text.clone();
+
+                state.virtual_key(keyboard, &text, before, time, key, key_state);
+                if changed != 0 {
+                    state.virtual_modifiers(keyboard, &text, after);
+                }
+            }
+            zwp_virtual_keyboard_v1::Request::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+            } => {
+                let keymap = match keymap {
+                    Some(keymap) => keymap,
+                    None => {
+                        keyboard.post_error(
+                            zwp_virtual_keyboard_v1::Error::NoKeymap,
+                            "a keymap has to be set before typing",
+                        );
+                        return;
+                    }
+                };
+                keymap
+                    .state
+                    .update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
+                let modifiers = keymap.modifiers();
+                let text = keymap.text.clone();
+                state.virtual_modifiers(keyboard, &text, modifiers);
+            }
+            zwp_virtual_keyboard_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &()) {
+        let keyboards = &mut state.virtual_keyboard_state().keyboards;
+        let count = keyboards.len();
+        keyboards.retain(|virtual_keyboard| virtual_keyboard.keyboard.id() != resource);
+        // keyboards of clients that weren't allowed were never added
+        if keyboards.len() != count {
+            state.virtual_keyboards_changed();
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_virtual_keyboard {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_virtual_keyboard_v1::server::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1: ()
+        ] => $crate::protocols::virtual_keyboard::VirtualKeyboardState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_virtual_keyboard_v1::server::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1: ()
+        ] => $crate::protocols::virtual_keyboard::VirtualKeyboardState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_misc::zwp_virtual_keyboard_v1::server::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1: ()
+        ] => $crate::protocols::virtual_keyboard::VirtualKeyboardState);
+    };
+}
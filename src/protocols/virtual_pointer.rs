@@ -0,0 +1,299 @@
+// wlr-virtual-pointer-unstable-v1, lets clients move the pointer and click, for ydotool-style
+// automation and remote desktops
+// scrolling is collected into a frame like libinput sends it, the handler gets it whole
+
+use std::sync::Mutex;
+
+use smithay::{
+    backend::input::{Axis, AxisSource, ButtonState},
+    input::pointer::AxisFrame,
+    output::Output,
+    reexports::{
+        wayland_protocols_wlr::virtual_pointer::v1::server::{
+            zwlr_virtual_pointer_manager_v1::{self, ZwlrVirtualPointerManagerV1},
+            zwlr_virtual_pointer_v1::{self, ZwlrVirtualPointerV1},
+        },
+        wayland_server::{
+            backend::{ClientId, ObjectId},
+            protocol::wl_pointer,
+            Client, DataInit, Dispatch, DisplayHandle, GlobalDispatch, New, Resource, WEnum,
+        },
+    },
+    utils::{Logical, Point},
+};
+
+const VERSION: u32 = 2;
+
+pub trait VirtualPointerHandler {
+    fn virtual_pointer_state(&mut self) -> &mut VirtualPointerState;
+    // whether `client` may create virtual pointers at all
+    fn virtual_pointer_allowed(&mut self, client: &Client) -> bool;
+    fn virtual_pointer_motion(&mut self, time: u32, delta: Point<f64, Logical>);
+    // `x` and `y` go from 0.0 to 1.0 across `output`, or across every output without one
+    fn virtual_pointer_motion_absolute(
+        &mut self,
+        output: Option<&Output>,
+        time: u32,
+        x: f64,
+        y: f64,
+    );
+    fn virtual_pointer_button(&mut self, time: u32, button: u32, state: ButtonState);
+    fn virtual_pointer_axis(&mut self, frame: AxisFrame);
+    // a virtual pointer was created or destroyed
+    fn virtual_pointers_changed(&mut self);
+}
+
+pub struct VirtualPointerState {
+    pointers: Vec<ZwlrVirtualPointerV1>,
+}
+
+pub struct VirtualPointerData {
+    // the output absolute motion is mapped to
+    output: Option<Output>,
+    // the scrolling since the last frame, the source comes before the first axis event that has
+    // a time to start the frame with
+    frame: Mutex<Option<AxisFrame>>,
+    source: Mutex<Option<AxisSource>>,
+}
+
+impl VirtualPointerState {
+    pub fn new<D>(display: &DisplayHandle) -> Self
+    where
+        D: GlobalDispatch<ZwlrVirtualPointerManagerV1, ()> + 'static,
+    {
+        display.create_global::<D, ZwlrVirtualPointerManagerV1, _>(VERSION, ());
+        Self {
+            pointers: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pointers.is_empty()
+    }
+}
+
+fn axis(pointer: &ZwlrVirtualPointerV1, axis: WEnum<wl_pointer::Axis>) -> Option<Axis> {
+    match axis {
+        WEnum::Value(wl_pointer::Axis::VerticalScroll) => Some(Axis::Vertical),
+        WEnum::Value(wl_pointer::Axis::HorizontalScroll) => Some(Axis::Horizontal),
+        _ => {
+            pointer.post_error(zwlr_virtual_pointer_v1::Error::InvalidAxis, "invalid axis");
+            None
+        }
+    }
+}
+
+fn axis_source(
+    pointer: &ZwlrVirtualPointerV1,
+    source: WEnum<wl_pointer::AxisSource>,
+) -> Option<AxisSource> {
+    match source {
+        WEnum::Value(wl_pointer::AxisSource::Wheel) => Some(AxisSource::Wheel),
+        WEnum::Value(wl_pointer::AxisSource::Finger) => Some(AxisSource::Finger),
+        WEnum::Value(wl_pointer::AxisSource::Continuous) => Some(AxisSource::Continuous),
+        WEnum::Value(wl_pointer::AxisSource::WheelTilt) => Some(AxisSource::WheelTilt),
+        _ => {
+            pointer.post_error(
+                zwlr_virtual_pointer_v1::Error::InvalidAxisSource,
+                "invalid axis source",
+            );
+            None
+        }
+    }
+}
+
+impl<D> GlobalDispatch<ZwlrVirtualPointerManagerV1, (), D> for VirtualPointerState
+where
+    D: GlobalDispatch<ZwlrVirtualPointerManagerV1, ()>
+        + Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        + VirtualPointerHandler
+        + 'static,
+{
+    fn bind(
+        _state: &mut D,
+        _display: &DisplayHandle,
+        _client: &Client,
+        resource: New<ZwlrVirtualPointerManagerV1>,
+        _global_data: &(),
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        data_init.init(resource, ());
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerManagerV1, (), D> for VirtualPointerState
+where
+    D: Dispatch<ZwlrVirtualPointerManagerV1, ()>
+        + Dispatch<ZwlrVirtualPointerV1, VirtualPointerData>
+        + VirtualPointerHandler
+        + 'static,
+{
+    // there is only one seat, so the one asked for doesn't matter
+    fn request(
+        state: &mut D,
+        client: &Client,
+        _manager: &ZwlrVirtualPointerManagerV1,
+        request: zwlr_virtual_pointer_manager_v1::Request,
+        _data: &(),
+        _display: &DisplayHandle,
+        data_init: &mut DataInit<'_, D>,
+    ) {
+        let (id, output) = match request {
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointer { id, .. } => (id, None),
+            zwlr_virtual_pointer_manager_v1::Request::CreateVirtualPointerWithOutput {
+                id,
+                output,
+                ..
+            } => (id, output.as_ref().and_then(Output::from_resource)),
+            _ => return,
+        };
+
+        let pointer = data_init.init(
+            id,
+            VirtualPointerData {
+                output,
+                frame: Mutex::new(None),
+                source: Mutex::new(None),
+            },
+        );
+        // the protocol has no error for this, the pointer just doesn't do anything
+        if !state.virtual_pointer_allowed(client) {
+            tracing::warn!("Virtual pointer refused, the client is not allowed to create one");
+            return;
+        }
+        state.virtual_pointer_state().pointers.push(pointer);
+        state.virtual_pointers_changed();
+    }
+}
+
+impl<D> Dispatch<ZwlrVirtualPointerV1, VirtualPointerData, D> for VirtualPointerState
+where
+    D: Dispatch<ZwlrVirtualPointerV1, VirtualPointerData> + VirtualPointerHandler + 'static,
+{
+    fn request(
+        state: &mut D,
+        _client: &Client,
+        pointer: &ZwlrVirtualPointerV1,
+        request: zwlr_virtual_pointer_v1::Request,
+        data: &VirtualPointerData,
+        _display: &DisplayHandle,
+        _data_init: &mut DataInit<'_, D>,
+    ) {
+        if !state.virtual_pointer_state().pointers.contains(pointer) {
+            return;
+        }
+
+        // scrolling goes into the frame, and is sent with the next frame request
+        let update_frame = |time: u32, update: &dyn Fn(AxisFrame) -> AxisFrame| {
+            let mut frame = data.frame.lock().unwrap();
+            let current = frame.take().unwrap_or_else(|| {
+                let new = AxisFrame::new(time);
+                match *data.source.lock().unwrap() {
+                    Some(source) => new.source(source),
+                    None => new,
+                }
+            });
+            *frame = Some(update(current));
+        };
+
+        match request {
+            zwlr_virtual_pointer_v1::Request::Motion { time, dx, dy } => {
+                state.virtual_pointer_motion(time, (dx, dy).into())
+            }
+            zwlr_virtual_pointer_v1::Request::MotionAbsolute {
+                time,
+                x,
+                y,
+                x_extent,
+                y_extent,
+            } => {
+                if x_extent == 0 || y_extent == 0 {
+                    return;
+                }
+                let (x, y) = (x as f64 / x_extent as f64, y as f64 / y_extent as f64);
+                state.virtual_pointer_motion_absolute(data.output.as_ref(), time, x, y);
+            }
+            zwlr_virtual_pointer_v1::Request::Button {
+                time,
+                button,
+                state: button_state,
+            } => {
+                let button_state = match button_state {
+                    WEnum::Value(wl_pointer::ButtonState::Pressed) => ButtonState::Pressed,
+                    _ => ButtonState::Released,
+                };
+                state.virtual_pointer_button(time, button, button_state);
+            }
+            zwlr_virtual_pointer_v1::Request::Axis {
+                time,
+                axis: axis_,
+                value,
+            } => {
+                if let Some(axis) = axis(pointer, axis_) {
+                    update_frame(time, &|frame| frame.value(axis, value));
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisSource {
+                axis_source: source,
+            } => {
+                if let Some(source) = axis_source(pointer, source) {
+                    *data.source.lock().unwrap() = Some(source);
+                    if let Some(frame) = data.frame.lock().unwrap().as_mut() {
+                        *frame = frame.source(source);
+                    }
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisStop { time, axis: axis_ } => {
+                if let Some(axis) = axis(pointer, axis_) {
+                    update_frame(time, &|frame| frame.stop(axis));
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::AxisDiscrete {
+                time,
+                axis: axis_,
+                value,
+                discrete,
+            } => {
+                if let Some(axis) = axis(pointer, axis_) {
+                    update_frame(time, &|frame| {
+                        frame.value(axis, value).discrete(axis, discrete)
+                    });
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Frame => {
+                let frame = data.frame.lock().unwrap().take();
+                *data.source.lock().unwrap() = None;
+                if let Some(frame) = frame {
+                    state.virtual_pointer_axis(frame);
+                }
+            }
+            zwlr_virtual_pointer_v1::Request::Destroy => {}
+            _ => {}
+        }
+    }
+
+    fn destroyed(state: &mut D, _client: ClientId, resource: ObjectId, _data: &VirtualPointerData) {
+        let pointers = &mut state.virtual_pointer_state().pointers;
+        let count = pointers.len();
+        pointers.retain(|pointer| pointer.id() != resource);
+        // pointers of clients that weren't allowed were never added
+        if pointers.len() != count {
+            state.virtual_pointers_changed();
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! delegate_virtual_pointer {
+    ($(@<$( $lt:tt $( : $clt:tt $(+ $dlt:tt )* )? ),+>)? $ty: ty) => {
+        smithay::reexports::wayland_server::delegate_global_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: ()
+        ] => $crate::protocols::virtual_pointer::VirtualPointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1: ()
+        ] => $crate::protocols::virtual_pointer::VirtualPointerState);
+        smithay::reexports::wayland_server::delegate_dispatch!($(@< $( $lt $( : $clt $(+ $dlt )* )? ),+ >)? $ty: [
+            smithay::reexports::wayland_protocols_wlr::virtual_pointer::v1::server::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1: $crate::protocols::virtual_pointer::VirtualPointerData
+        ] => $crate::protocols::virtual_pointer::VirtualPointerState);
+    };
+}
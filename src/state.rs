@@ -2,6 +2,7 @@
 use std::{
     ffi::OsString,
     os::unix::io::AsRawFd,
+    path::PathBuf,
     sync::{Arc, Mutex},
     time::Duration,
 };
@@ -24,39 +25,65 @@ use smithay::{
     },
     output::Output,
     reexports::{
-        calloop::{generic::Generic, Interest, LoopHandle, LoopSignal, Mode, PostAction},
+        calloop::{
+            generic::Generic, Interest, LoopHandle, LoopSignal, Mode, PostAction, RegistrationToken,
+        },
         wayland_server::{
             backend::{ClientData, ClientId, DisconnectReason},
             protocol::wl_surface::WlSurface,
             Display, DisplayHandle,
         },
     },
-    utils::{Clock, Logical, Monotonic, Point},
+    utils::{Clock, Logical, Monotonic, Point, Serial},
     wayland::{
-        compositor::CompositorState,
+        compositor::{get_parent, CompositorState},
         data_device::DataDeviceState,
         dmabuf::DmabufFeedback,
         output::OutputManagerState,
         presentation::PresentationState,
+        primary_selection::PrimarySelectionState,
+        relative_pointer::RelativePointerManagerState,
         shell::{
             wlr_layer::{Layer, WlrLayerShellState},
             xdg::{decoration::XdgDecorationState, XdgShellState},
         },
         shm::ShmState,
         socket::ListeningSocketSource,
+        tablet_manager::{TabletManagerState, TabletSeatTrait},
+        xdg_activation::XdgActivationState,
     },
 };
 
-use crate::CalloopData;
+use crate::{
+    config::{ConfigWatcher, KeyboardConfig},
+    handlers::{
+        gestures::CapturedGesture,
+        keybindings::ScreenshotTarget,
+        keyboard::KeyboardLocks,
+        selection::{ClipboardCopy, ServerSelection},
+        workspaces::Workspaces,
+    },
+    ipc::IpcSocket,
+    protocols::{
+        data_control::DataControlState, foreign_toplevel::ForeignToplevelState,
+        idle_inhibit::IdleInhibitState, idle_notify::IdleNotifierState,
+        input_method::InputMethodState, pointer_constraints::PointerConstraintsState,
+        pointer_gestures::PointerGesturesState, session_lock::SessionLockState,
+        text_input::TextInputState,
+    },
+    CalloopData, CorrosionConfig,
+};
 
 pub struct Corrosion<BackendData: Backend + 'static> {
     pub display_handle: DisplayHandle,
     pub start_time: std::time::Instant,
     pub socket_name: OsString,
+    pub ipc_socket: Option<IpcSocket>,
     pub backend_data: BackendData,
 
     pub space: Space<Window>,
     pub handle: LoopHandle<'static, CalloopData<BackendData>>,
+    pub config: CorrosionConfig,
 
     // Smithay State
     pub compositor_state: CompositorState,
@@ -69,6 +96,48 @@ pub struct Corrosion<BackendData: Backend + 'static> {
     pub presentation_state: PresentationState,
     pub popup_manager: PopupManager,
     pub wlr_layer_state: WlrLayerShellState,
+    pub idle_notifier_state: IdleNotifierState,
+    pub idle_inhibit_state: IdleInhibitState,
+    pub idle_check_timer: Option<RegistrationToken>,
+    pub session_lock_state: SessionLockState,
+    pub foreign_toplevel_state: ForeignToplevelState,
+    // minimized windows are unmapped, this is where they go back to
+    pub minimized_windows: Vec<(Window, Point<i32, Logical>)>,
+    // windows of the workspaces that aren't shown
+    pub workspaces: Workspaces,
+    pub xdg_activation_state: XdgActivationState,
+    // windows that asked for the focus without being allowed to take it
+    pub urgent_windows: Vec<Window>,
+    // the last key or button press, activation tokens have to come from it
+    pub last_input_serial: Option<Serial>,
+    // the keymap the seat keyboard has now and the layout and locks clients see on top of xkb's
+    pub keyboard_config: KeyboardConfig,
+    pub keyboard_locks: KeyboardLocks,
+    // the keyboard that was typed on last
+    pub keyboard_device: Option<String>,
+    // the keymap a virtual keyboard brought, the seat keyboard has it instead of the config one
+    pub virtual_keymap: Option<String>,
+    // the keyboard focus while no keyboard is plugged in
+    pub keyboard_focus: Option<WlSurface>,
+    pub primary_selection_state: PrimarySelectionState,
+    pub data_control_state: DataControlState,
+    // selections that don't come from a regular client
+    pub server_selection: Option<ServerSelection>,
+    pub server_primary_selection: Option<ServerSelection>,
+    pub clipboard_copy: Option<ClipboardCopy>,
+    pub pointer_constraints_state: PointerConstraintsState,
+    pub relative_pointer_state: RelativePointerManagerState,
+    pub pointer_gestures_state: PointerGesturesState,
+    // a touchpad gesture that is bound in the config, so clients don't get it
+    pub captured_gesture: Option<CapturedGesture>,
+    pub tablet_manager_state: TabletManagerState,
+    // the cursor of a tablet tool, while the tool is close to its tablet
+    pub tablet_tool_location: Option<Point<f64, Logical>>,
+    pub tablet_cursor_image_status: Arc<Mutex<CursorImageStatus>>,
+    pub text_input_state: TextInputState,
+    pub input_method_state: InputMethodState,
+    // the icon of a running drag and drop, drawn at the pointer
+    pub dnd_icon: Option<WlSurface>,
 
     pub cursor_image_status: Arc<Mutex<CursorImageStatus>>,
     pub pointer_location: Point<f64, Logical>,
@@ -82,10 +151,14 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
         handle: LoopHandle<'static, CalloopData<BackendData>>,
         display: &mut Display<Self>,
         backend_data: BackendData,
+        config: CorrosionConfig,
     ) -> Self {
         let clock = Clock::new().expect("Unable to make clock");
         let start_time = std::time::Instant::now();
 
+        // Reload the config whenever one of its files changes
+        Self::init_config_watcher(&handle, &config);
+
         let dh = display.handle();
 
         // Creates a compositor global. Used to store and access surface trees.
@@ -105,18 +178,33 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
         let mut seat_state = SeatState::new();
         // Creates a global that handles clipboard data and drag-n-drop
         let data_device_state = DataDeviceState::new::<Self>(&dh);
+        // The selection that middle click pastes
+        let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
+        // Lets clipboard managers see and set both selections
+        let data_control_state = DataControlState::new::<Self>(&dh);
 
         // A seat is a group of keyboards, pointer and touch devices.
         // A seat typically has a pointer and maintains a keyboard focus and a pointer focus.
-        let mut seat: Seat<Self> = seat_state.new_wl_seat(&dh, &backend_data.seat_name());
-
-        // Notify clients that we have a keyboard, for the sake of the example we assume that keyboard is always present.
-        // You may want to track keyboard hot-plug in real compositor.
-        seat.add_keyboard(Default::default(), 500, 500).unwrap();
-
-        // Notify clients that we have a pointer (mouse)
-        // Here we assume that there is always pointer plugged in
-        seat.add_pointer();
+        let seat: Seat<Self> = seat_state.new_wl_seat(&dh, &backend_data.seat_name());
+
+        // The keyboard, pointer and touch capabilities come and go with the devices that are
+        // plugged in, see set_seat_capabilities
+
+        // Lets games and 3D tools lock the pointer and get its raw motion
+        let pointer_constraints_state = PointerConstraintsState::new::<Self>(&dh);
+        let relative_pointer_state = RelativePointerManagerState::new::<Self>(&dh);
+        // Lets image viewers and browsers pinch to zoom
+        let pointer_gestures_state = PointerGesturesState::new::<Self>(&dh);
+        // Graphics tablets, their tools get a cursor of their own
+        let tablet_manager_state = TabletManagerState::new::<Self>(&dh);
+        let tablet_cursor_image_status = Arc::new(Mutex::new(CursorImageStatus::Default));
+        let tool_cursor_image_status = tablet_cursor_image_status.clone();
+        seat.tablet_seat().on_cursor_surface(move |_tool, image| {
+            *tool_cursor_image_status.lock().unwrap() = image;
+        });
+        // Lets fcitx5 and ibus compose text for clients, e.g. for CJK
+        let text_input_state = TextInputState::new::<Self>(&dh);
+        let input_method_state = InputMethodState::new::<Self>(&dh);
 
         let cursor_image_status = Arc::new(Mutex::new(CursorImageStatus::Default));
 
@@ -135,8 +223,21 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
         // protocol
         let wlr_layer_state = WlrLayerShellState::new::<Self>(&dh);
 
+        // Tells clients like swayidle when the user is idle, and lets video players keep us from
+        // going idle
+        let idle_notifier_state = IdleNotifierState::new::<Self>(&dh);
+        let idle_inhibit_state = IdleInhibitState::new::<Self>(&dh);
+        // Lets lockers like swaylock lock the session
+        let session_lock_state = SessionLockState::new::<Self>(&dh);
+        // The window list for taskbars
+        let foreign_toplevel_state = ForeignToplevelState::new::<Self>(&dh);
+        // Lets apps raise themselves when they were started or asked for by the user
+        let xdg_activation_state = XdgActivationState::new::<Self>(&dh);
+
         // Initializes a wayland listener socket
         let socket_name = Self::init_wayland_listener(display, &handle);
+        // Lets other programs control the compositor, e.g. turning outputs off
+        let ipc_socket = Self::init_ipc(&handle, &socket_name);
 
         // Return the state
         Self {
@@ -145,10 +246,12 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
 
             space,
             handle,
+            config,
             seat_name: backend_data.seat_name(),
             backend_data,
 
             socket_name,
+            ipc_socket,
 
             compositor_state,
             xdg_shell_state,
@@ -160,6 +263,36 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
             presentation_state,
             popup_manager,
             wlr_layer_state,
+            idle_notifier_state,
+            idle_inhibit_state,
+            idle_check_timer: None,
+            session_lock_state,
+            foreign_toplevel_state,
+            minimized_windows: Vec::new(),
+            workspaces: Workspaces::default(),
+            xdg_activation_state,
+            urgent_windows: Vec::new(),
+            last_input_serial: None,
+            keyboard_config: KeyboardConfig::default(),
+            keyboard_locks: KeyboardLocks::default(),
+            keyboard_device: None,
+            virtual_keymap: None,
+            keyboard_focus: None,
+            primary_selection_state,
+            data_control_state,
+            server_selection: None,
+            server_primary_selection: None,
+            clipboard_copy: None,
+            pointer_constraints_state,
+            relative_pointer_state,
+            pointer_gestures_state,
+            captured_gesture: None,
+            tablet_manager_state,
+            tablet_tool_location: None,
+            tablet_cursor_image_status,
+            text_input_state,
+            input_method_state,
+            dnd_icon: None,
 
             cursor_image_status,
             pointer_location: (0.0, 0.0).into(),
@@ -211,20 +344,118 @@ impl<BackendData: Backend + 'static> Corrosion<BackendData> {
         socket_name
     }
 
+    // Watches the config file and everything it includes, and reloads when one of them changes
+    fn init_config_watcher(
+        event_loop: &LoopHandle<'static, CalloopData<BackendData>>,
+        config: &CorrosionConfig,
+    ) {
+        let watcher = match ConfigWatcher::new(config.sources()) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                tracing::error!("Unable to watch the config for changes: {}", err);
+                return;
+            }
+        };
+        event_loop
+            .insert_source(
+                Generic::new(watcher, Interest::READ, Mode::Level),
+                |_, watcher, data| {
+                    if watcher.changed() {
+                        let sources = data.state.reload_config();
+                        watcher.watch(&sources);
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .expect("Failed to init the config watcher.");
+    }
+
+    // Reloads the config, keeping the current one if the new one is invalid. Returns the files to
+    // watch for the next reload, after a failed one that includes the files it broke on
+    pub fn reload_config(&mut self) -> Vec<PathBuf> {
+        let mut sources = Vec::new();
+        match CorrosionConfig::load(&mut sources) {
+            Ok(config) => {
+                self.config = config;
+                tracing::info!("Reloaded config");
+                self.apply_keyboard_config();
+                BackendData::apply_config(self);
+            }
+            Err(err) => {
+                tracing::error!("Unable to reload config, keeping the current one: {}", err);
+                for source in self.config.sources() {
+                    if !sources.contains(source) {
+                        sources.push(source.clone());
+                    }
+                }
+            }
+        }
+        sources
+    }
+
+    // Asks the backend to repaint every output, only outputs with damage actually get a new frame
+    pub fn schedule_render(&mut self) {
+        let outputs: Vec<Output> = self.space.outputs().cloned().collect();
+        for output in outputs {
+            BackendData::schedule_render(self, &output);
+        }
+    }
+
+    // Asks the backend to repaint the output under `location`, for what the compositor draws
+    // itself, like the cursor
+    pub fn schedule_render_at(&mut self, location: Point<f64, Logical>) {
+        if let Some(output) = self.space.output_under(location).next().cloned() {
+            BackendData::schedule_render(self, &output);
+        }
+    }
+
+    // Asks the backend to repaint the outputs `surface` is shown on, surfaces that aren't part of
+    // a window (popups, layer surfaces, cursors) repaint everything
+    pub fn schedule_render_for_surface(&mut self, surface: &WlSurface) {
+        let mut root = surface.clone();
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
+        let outputs: Vec<Output> = match self
+            .space
+            .elements()
+            .find(|window| window.toplevel().wl_surface() == &root)
+        {
+            Some(window) => self.space.outputs_for_element(window),
+            None => self.space.outputs().cloned().collect(),
+        };
+        for output in outputs {
+            BackendData::schedule_render(self, &output);
+        }
+    }
+
     // This function is used to get the surface under the pointer
     pub fn surface_under_pointer(
         &self,
         pointer: &PointerHandle<Self>,
     ) -> Option<(WlSurface, Point<i32, Logical>)> {
-        let pos = pointer.current_location();
-        let output = self
-            .space
-            .outputs()
-            .find(|output| {
-                let geometry = self.space.output_geometry(output).unwrap();
-                geometry.contains(pos.to_i32_round())
-            })
-            .unwrap();
+        self.surface_under(pointer.current_location())
+    }
+
+    // The surface at `pos`, and where it is. Also used for touch points
+    pub fn surface_under(
+        &self,
+        pos: Point<f64, Logical>,
+    ) -> Option<(WlSurface, Point<i32, Logical>)> {
+        let output = self.space.outputs().find(|output| {
+            let geometry = self.space.output_geometry(output).unwrap();
+            geometry.contains(pos.to_i32_round())
+        })?;
+
+        // Nothing but the lock surface of the output gets pointer input while locked
+        if self.is_locked() {
+            let location = self.space.output_geometry(output).unwrap().loc;
+            return self
+                .session_lock_state
+                .lock_surface(output)
+                .map(|surface| (surface.wl_surface().clone(), location));
+        }
+
         let map = desktop::layer_map_for_output(output);
         let mut under = None;
 
@@ -369,4 +600,23 @@ pub trait Backend {
     fn seat_name(&self) -> String;
     fn early_import(&mut self, output: &WlSurface);
     fn reset_buffers(&mut self, surface: &Output);
+    // applies the backend specific parts of a reloaded config
+    fn apply_config(state: &mut Corrosion<Self>)
+    where
+        Self: Sized + 'static;
+    fn schedule_render(state: &mut Corrosion<Self>, output: &Output)
+    where
+        Self: Sized + 'static;
+    // saves a screenshot of the target
+    fn screenshot(state: &mut Corrosion<Self>, target: ScreenshotTarget) -> Result<(), String>
+    where
+        Self: Sized + 'static;
+    // dpms for every output, or only the one with the given name
+    fn set_output_power(
+        state: &mut Corrosion<Self>,
+        output: Option<&str>,
+        on: bool,
+    ) -> Result<(), String>
+    where
+        Self: Sized + 'static;
 }
@@ -3,7 +3,8 @@ use std::time::Duration;
 use smithay::{
     backend::{
         renderer::{
-            damage::OutputDamageTracker, element::surface::WaylandSurfaceRenderElement,
+            damage::OutputDamageTracker,
+            element::surface::{render_elements_from_surface_tree, WaylandSurfaceRenderElement},
             gles::GlesRenderer,
         },
         winit::{self, WinitError, WinitEvent, WinitEventLoop, WinitGraphicsBackend},
@@ -19,7 +20,10 @@ use smithay::{
     utils::{Rectangle, Transform},
 };
 
-use crate::{state::Backend, CalloopData, Corrosion};
+use crate::{
+    drawing::CLEAR_COLOR_LOCKED, handlers::keybindings::ScreenshotTarget, state::Backend,
+    CalloopData, Corrosion, CorrosionConfig,
+};
 
 pub struct WinitData {
     loop_signal: LoopSignal,
@@ -37,9 +41,63 @@ impl Backend for WinitData {
     fn early_import(&mut self, _output: &WlSurface) {}
 
     fn reset_buffers(&mut self, _surface: &Output) {}
+
+    fn apply_config(state: &mut Corrosion<Self>) {
+        for output in state.space.outputs() {
+            configure_output(&state.config, output);
+        }
+    }
+
+    // the window is redrawn on a fixed timer anyway
+    fn schedule_render(_state: &mut Corrosion<Self>, _output: &Output) {}
+
+    fn screenshot(_state: &mut Corrosion<Self>, _target: ScreenshotTarget) -> Result<(), String> {
+        Err(String::from(
+            "screenshots are not supported by the winit backend",
+        ))
+    }
+
+    fn set_output_power(
+        _state: &mut Corrosion<Self>,
+        _output: Option<&str>,
+        _on: bool,
+    ) -> Result<(), String> {
+        Err(String::from("dpms is not supported by the winit backend"))
+    }
 }
 
-pub fn init_winit<BackendData: Backend + 'static>() -> Result<(), Box<dyn std::error::Error>> {
+// Applies the [output] config, only the scale and transform make sense for a window
+fn configure_output(config: &CorrosionConfig, output: &Output) {
+    let properties = output.physical_properties();
+    let output_config = config
+        .output_config(&output.name(), &properties.make, &properties.model)
+        .cloned()
+        .unwrap_or_default();
+    output.change_current_state(
+        None,
+        Some(winit_transform(output_config.transform.into())),
+        Some(output_config.scale()),
+        None,
+    );
+}
+
+// winit renders upside down, so the configured transform gets flipped vertically on top
+fn winit_transform(transform: Transform) -> Transform {
+    match transform {
+        Transform::Normal => Transform::Flipped180,
+        Transform::_90 => Transform::Flipped90,
+        Transform::_180 => Transform::Flipped,
+        Transform::_270 => Transform::Flipped270,
+        Transform::Flipped => Transform::_180,
+        Transform::Flipped90 => Transform::_90,
+        Transform::Flipped180 => Transform::Normal,
+        Transform::Flipped270 => Transform::_270,
+    }
+}
+
+pub fn init_winit<BackendData: Backend + 'static>(
+    config: CorrosionConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
     let mut event_loop = EventLoop::try_new().expect("Unable to create callback loop");
     let mut display = Display::new().expect("Unable to create display :(");
     let backend_data = WinitData {
@@ -47,7 +105,7 @@ pub fn init_winit<BackendData: Backend + 'static>() -> Result<(), Box<dyn std::e
     };
 
     let mut state: Corrosion<WinitData> =
-        Corrosion::new(event_loop.handle(), &mut display, backend_data);
+        Corrosion::new(event_loop.handle(), &mut display, backend_data, config);
 
     let (mut backend, mut winit) = winit::init()?;
 
@@ -68,12 +126,8 @@ pub fn init_winit<BackendData: Backend + 'static>() -> Result<(), Box<dyn std::e
     );
 
     let _global = output.create_global::<Corrosion<BackendData>>(&display.handle());
-    output.change_current_state(
-        Some(mode),
-        Some(Transform::Flipped180),
-        None,
-        Some((0, 0).into()),
-    );
+    output.change_current_state(Some(mode), None, None, Some((0, 0).into()));
+    configure_output(&state.config, &output);
     output.set_preferred(mode);
 
     state.space.map_output(&output, (0, 0));
@@ -105,7 +159,9 @@ pub fn init_winit<BackendData: Backend + 'static>() -> Result<(), Box<dyn std::e
 
     // aaand we run our loop :3
     event_loop
-        .run(None, &mut data, move |_| {})
+        .run(None, &mut data, move |data| {
+            data.state.take_over_clipboard()
+        })
         .expect("Unable to initialize winit backend");
     Ok(())
 }
@@ -165,15 +221,36 @@ pub fn winit_dispatch<BackendData: Backend>(
 
     // This code renders the output, submits the frame, and refreshes the space.
     backend.bind()?;
-    smithay::desktop::space::render_output::<_, WaylandSurfaceRenderElement<GlesRenderer>, _, _>(
-        output,
-        backend.renderer(),
-        0,
-        [&state.space],
-        &[],
-        damage_tracked_renderer,
-        [0.1, 0.1, 0.1, 1.0],
-    )?;
+    if state.is_locked() {
+        // only the lock surface, the desktop stays hidden
+        let elements: Vec<WaylandSurfaceRenderElement<GlesRenderer>> =
+            match state.session_lock_state.lock_surface(output) {
+                Some(lock_surface) => render_elements_from_surface_tree(
+                    backend.renderer(),
+                    lock_surface.wl_surface(),
+                    (0, 0),
+                    output.current_scale().fractional_scale(),
+                ),
+                None => Vec::new(),
+            };
+        damage_tracked_renderer.render_output(
+            backend.renderer(),
+            0,
+            &elements,
+            CLEAR_COLOR_LOCKED,
+        )?;
+        state.send_lock_surface_frames(output);
+    } else {
+        smithay::desktop::space::render_output::<_, WaylandSurfaceRenderElement<GlesRenderer>, _, _>(
+            output,
+            backend.renderer(),
+            0,
+            [&state.space],
+            &[],
+            damage_tracked_renderer,
+            [0.1, 0.1, 0.1, 1.0],
+        )?;
+    }
     backend.submit(Some(&[damage]))?;
 
     // This code sends the frame to the clients.